@@ -0,0 +1,197 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Hash algorithms supported for image digests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 3] = [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha512,
+        HashAlgorithm::Blake3,
+    ];
+
+    /// Length of the hex-encoded digest produced by this algorithm.
+    #[must_use]
+    pub fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 64,
+            HashAlgorithm::Sha512 => 128,
+        }
+    }
+
+    /// Hash `data`, returning the lowercase hex digest.
+    #[must_use]
+    pub fn hex_digest(self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha256 => crate::checksum_string(data),
+            HashAlgorithm::Sha512 => {
+                let hash = ring::digest::digest(&ring::digest::SHA512, data);
+                data_encoding::HEXLOWER.encode(hash.as_ref())
+            }
+            HashAlgorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HashAlgorithm::Sha256 => "sha256",
+                HashAlgorithm::Sha512 => "sha512",
+                HashAlgorithm::Blake3 => "blake3",
+            }
+        )
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HashAlgorithm::ALL
+            .into_iter()
+            .find(|alg| alg.to_string() == s)
+            .ok_or_else(|| DigestError::UnsupportedAlgorithm(s.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub enum DigestError {
+    /// The digest's algorithm prefix is not one we support.
+    UnsupportedAlgorithm(String),
+    /// The hex part of the digest is not valid for its algorithm.
+    InvalidHex(String),
+}
+
+impl Display for DigestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DigestError::UnsupportedAlgorithm(alg) => {
+                write!(f, "Unsupported hash algorithm: {alg}")
+            }
+            DigestError::InvalidHex(digest) => write!(f, "Invalid hex digest: {digest}"),
+        }
+    }
+}
+
+impl std::error::Error for DigestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+/// A self-describing digest like `sha256:…`. Bare hex strings
+/// are treated as legacy SHA-256 digests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Digest {
+    pub algorithm: HashAlgorithm,
+    /// Lowercase hex encoded digest, without the algorithm prefix.
+    pub hex: String,
+}
+
+impl Digest {
+    /// Hash `data` with the given algorithm.
+    #[must_use]
+    pub fn compute(algorithm: HashAlgorithm, data: &[u8]) -> Self {
+        Digest {
+            algorithm,
+            hex: algorithm.hex_digest(data),
+        }
+    }
+
+    /// Parse a digest string, either `<algorithm>:<hex>` or bare SHA-256 hex.
+    ///
+    /// # Errors
+    ///
+    /// [`DigestError`]
+    pub fn parse(digest: &str) -> Result<Self, DigestError> {
+        let (algorithm, hex) = match digest.split_once(':') {
+            Some((algorithm, hex)) => (algorithm.parse()?, hex),
+            None => (HashAlgorithm::Sha256, digest),
+        };
+        let hex = hex.to_ascii_lowercase();
+        if hex.len() != algorithm.hex_len() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(DigestError::InvalidHex(digest.to_string()));
+        }
+        Ok(Digest { algorithm, hex })
+    }
+
+    /// Check whether `data` hashes to this digest.
+    #[must_use]
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.algorithm.hex_digest(data) == self.hex
+    }
+}
+
+/// Hashes data fed to it in chunks, e.g. while it's being downloaded, so the
+/// data doesn't have to be hashed again once it's all there.
+pub struct Hasher {
+    algorithm: HashAlgorithm,
+    state: HasherState,
+}
+
+enum HasherState {
+    Ring(Box<ring::digest::Context>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    #[must_use]
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        let state = match algorithm {
+            HashAlgorithm::Sha256 => {
+                HasherState::Ring(Box::new(ring::digest::Context::new(&ring::digest::SHA256)))
+            }
+            HashAlgorithm::Sha512 => {
+                HasherState::Ring(Box::new(ring::digest::Context::new(&ring::digest::SHA512)))
+            }
+            HashAlgorithm::Blake3 => HasherState::Blake3(Box::default()),
+        };
+        Hasher { algorithm, state }
+    }
+
+    /// Hash the next chunk of data.
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            HasherState::Ring(context) => context.update(data),
+            HasherState::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// The digest of all the data hashed so far.
+    #[must_use]
+    pub fn finalize(self) -> Digest {
+        let hex = match self.state {
+            HasherState::Ring(context) => data_encoding::HEXLOWER.encode(context.finish().as_ref()),
+            HasherState::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        };
+        Digest {
+            algorithm: self.algorithm,
+            hex,
+        }
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Digest::parse(s)
+    }
+}
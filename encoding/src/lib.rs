@@ -2,12 +2,95 @@
 pub use regex::Error as RegexError;
 use regex::Regex;
 
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+
+mod digest;
+pub use digest::{Digest, DigestError, HashAlgorithm, Hasher};
+
 #[must_use]
 pub fn checksum_string(data: &[u8]) -> String {
     let hash = ring::digest::digest(&ring::digest::SHA256, data);
     data_encoding::HEXLOWER.encode(hash.as_ref())
 }
 
+#[derive(Debug)]
+pub enum SignatureError {
+    /// Failed to generate a new key pair.
+    KeyGeneration,
+    /// The PKCS#8 private key document was rejected.
+    InvalidPrivateKey(String),
+    /// A public key or signature wasn't valid lowercase hex.
+    InvalidEncoding(data_encoding::DecodeError),
+    /// The signature does not match the data and public key.
+    BadSignature,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::KeyGeneration => write!(f, "Failed to generate signing key."),
+            SignatureError::InvalidPrivateKey(reason) => {
+                write!(f, "Invalid private key: {reason}")
+            }
+            SignatureError::InvalidEncoding(e) => write!(f, "Invalid hex encoding: {e}"),
+            SignatureError::BadSignature => write!(f, "Signature verification failed."),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+/// Generate a new Ed25519 key pair, returned as a PKCS#8 document.
+///
+/// # Errors
+///
+/// [`SignatureError::KeyGeneration`]
+pub fn generate_signing_key() -> Result<Vec<u8>, SignatureError> {
+    let rng = ring::rand::SystemRandom::new();
+    Ed25519KeyPair::generate_pkcs8(&rng)
+        .map(|doc| doc.as_ref().to_vec())
+        .map_err(|_| SignatureError::KeyGeneration)
+}
+
+/// Sign `data` with the Ed25519 key in the given PKCS#8 document. Returns
+/// the hex-encoded public key and hex-encoded signature.
+///
+/// # Errors
+///
+/// [`SignatureError::InvalidPrivateKey`]
+pub fn sign(pkcs8: &[u8], data: &[u8]) -> Result<(String, String), SignatureError> {
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+        .map_err(|e| SignatureError::InvalidPrivateKey(e.to_string()))?;
+    let public_key = data_encoding::HEXLOWER.encode(key_pair.public_key().as_ref());
+    let signature = data_encoding::HEXLOWER.encode(key_pair.sign(data).as_ref());
+    Ok((public_key, signature))
+}
+
+/// Verify a hex-encoded Ed25519 `signature` of `data` against a hex-encoded `public_key`.
+///
+/// # Errors
+///
+/// [`SignatureError`]
+pub fn verify_signature(
+    public_key: &str,
+    data: &[u8],
+    signature: &str,
+) -> Result<(), SignatureError> {
+    let public_key = data_encoding::HEXLOWER
+        .decode(public_key.as_bytes())
+        .map_err(SignatureError::InvalidEncoding)?;
+    let signature = data_encoding::HEXLOWER
+        .decode(signature.as_bytes())
+        .map_err(SignatureError::InvalidEncoding)?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(data, &signature)
+        .map_err(|_| SignatureError::BadSignature)
+}
+
 const FILENAME_SAFE_CHAR: &str = "_";
 
 #[cfg(target_os = "windows")]
@@ -59,6 +142,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_self_describing_digests() {
+        let input = "hello world!".as_bytes();
+        let legacy = Digest::parse(&checksum_string(input)).expect("digest");
+        assert_eq!(legacy.algorithm, HashAlgorithm::Sha256);
+        assert!(legacy.matches(input));
+
+        for algorithm in HashAlgorithm::ALL {
+            let digest = Digest::compute(algorithm, input);
+            let parsed = Digest::parse(&digest.to_string()).expect("digest");
+            assert_eq!(parsed, digest);
+            assert!(parsed.matches(input));
+
+            let mut hasher = Hasher::new(algorithm);
+            for chunk in input.chunks(5) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize(), digest);
+        }
+
+        assert!(matches!(
+            Digest::parse("md5:5d41402abc4b2a76b9719d911017c592"),
+            Err(DigestError::UnsupportedAlgorithm(_))
+        ));
+        assert!(matches!(
+            Digest::parse("sha256:../../etc/passwd"),
+            Err(DigestError::InvalidHex(_))
+        ));
+    }
+
+    #[test]
+    fn ed25519_sign_and_verify() {
+        let key = generate_signing_key().expect("key");
+        let (public_key, signature) = sign(&key, b"hello world!").expect("signature");
+        assert!(verify_signature(&public_key, b"hello world!", &signature).is_ok());
+        assert!(matches!(
+            verify_signature(&public_key, b"hello world?", &signature),
+            Err(SignatureError::BadSignature)
+        ));
+    }
+
     #[test]
     fn safe_filename() {
         filename!(
@@ -1,4 +1,7 @@
-use image_repo::download_repo_manifest;
+use image_repo::{
+    download_repo_manifest,
+    http::{HttpClient, HttpConfig},
+};
 use std::env;
 
 #[tokio::main]
@@ -9,9 +12,10 @@ pub async fn main() {
         eprintln!("Usage: cargo run -p image-repo --example cli -- [url]");
     }
 
+    let client = HttpClient::new(&HttpConfig::default()).unwrap();
     for url in urls {
         println!("Attempting to download repo from {url} ...");
-        let result = download_repo_manifest(url).await;
+        let result = download_repo_manifest(&client, url).await;
         if let Ok(repo) = result {
             println!("{}", serde_json::to_string_pretty(&repo).unwrap());
         } else {
@@ -0,0 +1,167 @@
+//! Computing the [`ColorStats`] of an image: its dominant colours by median cut,
+//! and its mean luminance.
+
+use crate::types::{Color, ColorStats, ImgError, SupportedFormat};
+use image::RgbaImage;
+
+/// Number of colours in a palette.
+pub const PALETTE_SIZE: usize = 5;
+
+/// Images are scaled down to at most this many pixels on their longest side
+/// before being analysed, which is plenty for colour statistics.
+const ANALYSIS_SIZE: u32 = 256;
+
+/// Decode an image and compute its colour statistics, see [`stats`].
+///
+/// # Errors
+///
+/// [`ImgError::UnsupportedFormat`] if the image can't be decoded (currently AVIF),
+/// or [`ImgError::DecodingFailed`] if decoding fails.
+pub fn analyze(format: SupportedFormat, img_bytes: &[u8]) -> Result<Option<ColorStats>, ImgError> {
+    let img = crate::decoding::decode(format, img_bytes, "analyzed")?;
+    Ok(stats(
+        &img.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE).to_rgba8(),
+    ))
+}
+
+/// Compute the colour statistics of an image's pixels. Mostly transparent pixels
+/// are left out, since whatever is behind them shows through, so an image that
+/// is entirely transparent has none.
+#[must_use]
+pub fn stats(img: &RgbaImage) -> Option<ColorStats> {
+    let pixels = img
+        .pixels()
+        .filter(|pixel| pixel[3] >= 128)
+        .map(|&image::Rgba([r, g, b, _])| [r, g, b])
+        .collect::<Vec<_>>();
+    if pixels.is_empty() {
+        return None;
+    }
+    Some(ColorStats {
+        luminance: mean_luminance(&pixels),
+        palette: median_cut(pixels, PALETTE_SIZE),
+    })
+}
+
+/// Mean Rec. 709 luma of the pixels, from 0 to 1.
+#[allow(clippy::cast_precision_loss)]
+fn mean_luminance(pixels: &[[u8; 3]]) -> f64 {
+    if pixels.is_empty() {
+        return 0.0;
+    }
+    let total = pixels
+        .iter()
+        .map(|[r, g, b]| 0.2126 * f64::from(*r) + 0.7152 * f64::from(*g) + 0.0722 * f64::from(*b))
+        .sum::<f64>();
+    total / 255.0 / pixels.len() as f64
+}
+
+/// The channel with the widest range of values in a box of pixels, and that range.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = pixels.iter().map(|pixel| pixel[channel]);
+            let min = values.clone().min().unwrap_or_default();
+            let max = values.max().unwrap_or_default();
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or_default()
+}
+
+/// Reduce the pixels to at most `colors` colours by repeatedly splitting the box
+/// of pixels with the largest spread at the median of its widest channel. Each box
+/// becomes the average of its pixels, and the colours are ordered by how many
+/// pixels they stand for.
+fn median_cut(pixels: Vec<[u8; 3]>, colors: usize) -> Vec<Color> {
+    let mut boxes = vec![pixels];
+    while boxes.len() < colors {
+        // weigh the range by population so a few outliers don't get a colour each
+        let split = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, pixels)| {
+                let (channel, range) = widest_channel(pixels);
+                (i, channel, usize::from(range) * pixels.len())
+            })
+            .filter(|&(_, _, spread)| spread > 0)
+            .max_by_key(|&(_, _, spread)| spread);
+        let Some((i, channel, _)) = split else {
+            break;
+        };
+        let mut lower = boxes.swap_remove(i);
+        lower.sort_unstable_by_key(|pixel| pixel[channel]);
+        // split next to the median rather than through a run of equal values, so
+        // pixels of the same colour stay together
+        let median = lower[lower.len() / 2][channel];
+        let below = lower.partition_point(|pixel| pixel[channel] < median);
+        let at = if below > 0 {
+            below
+        } else {
+            lower.partition_point(|pixel| pixel[channel] <= median)
+        };
+        let upper = lower.split_off(at);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    boxes.retain(|pixels| !pixels.is_empty());
+    boxes.sort_by_key(|pixels| std::cmp::Reverse(pixels.len()));
+    boxes
+        .iter()
+        .map(|pixels| {
+            let len = pixels.len() as u64;
+            let mean = |channel: usize| {
+                let sum = pixels
+                    .iter()
+                    .map(|pixel| u64::from(pixel[channel]))
+                    .sum::<u64>();
+                u8::try_from((sum + len / 2) / len).unwrap_or(u8::MAX)
+            };
+            Color([mean(0), mean(1), mean(2)])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze, stats};
+    use crate::types::{Brightness, Color, ColorStats, SupportedFormat};
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn finds_dominant_colors() {
+        // a dark sky with a smaller bright orange nebula
+        let img = RgbaImage::from_fn(100, 100, |x, _| {
+            if x < 70 {
+                Rgba([5, 5, 20, 255])
+            } else {
+                Rgba([230, 120, 40, 255])
+            }
+        });
+        let stats = stats(&img).expect("stats");
+        assert_eq!(stats.palette, [Color([5, 5, 20]), Color([230, 120, 40])]);
+        assert!(
+            (stats.luminance - 0.1786).abs() < 0.001,
+            "{}",
+            stats.luminance
+        );
+        assert_eq!(stats.brightness(), Brightness::Dark);
+
+        let json = serde_json::to_value(&stats).expect("json");
+        assert_eq!(json["palette"][1], "#e67828");
+        let parsed = serde_json::from_value::<ColorStats>(json).expect("stats");
+        assert_eq!(parsed, stats);
+        assert!(serde_json::from_str::<Color>(r##""#e6782""##).is_err());
+
+        // ferris is on a transparent background
+        let ferris = analyze(SupportedFormat::Png, include_bytes!("../ferris.png"))
+            .expect("decodes")
+            .expect("stats");
+        assert_eq!(ferris.palette.len(), super::PALETTE_SIZE);
+        assert_eq!(ferris.brightness(), Brightness::Light);
+
+        let transparent = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 0]));
+        assert_eq!(super::stats(&transparent), None);
+    }
+}
@@ -0,0 +1,258 @@
+//! Fitting images to a display's aspect ratio using the curator's focal point
+//! and named crop regions.
+//!
+//! Wide panoramas cropped blindly to the centre often lose their subject, so an
+//! [`ImageData`] can say where the interesting part is. [`crop_for`] first picks
+//! the named crop closest to the target aspect ratio, if any is close enough, then
+//! cuts the largest rectangle of exactly that ratio out of it, as close to centred
+//! on the focal point as the bounds allow.
+
+use crate::types::{CropRegion, ImageData, Rect};
+
+/// How far a named crop's aspect ratio may be from the target, as a ratio of the
+/// two, and still be preferred over cropping the whole image.
+const CROP_TOLERANCE: f64 = 1.25;
+
+#[derive(Debug)]
+pub enum CropError {
+    /// The focal point isn't within `0.0..=1.0` on both axes.
+    FocalPointOutOfRange((f64, f64)),
+    /// A crop region has no area. Contains the region's name.
+    EmptyRegion(String),
+    /// A crop region extends past the edges of the image. Contains the region's name.
+    RegionOutOfBounds(String),
+}
+
+impl std::fmt::Display for CropError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CropError::FocalPointOutOfRange((x, y)) => write!(
+                f,
+                "Focal point ({x}, {y}) is outside the image, both coordinates must be between 0 and 1"
+            ),
+            CropError::EmptyRegion(name) => write!(f, "Crop region \"{name}\" is empty"),
+            CropError::RegionOutOfBounds(name) => {
+                write!(f, "Crop region \"{name}\" extends past the edges of the image")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CropError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+/// Check that an image's focal point and crop regions lie within the image.
+///
+/// # Errors
+///
+/// [`CropError`] for the first problem found.
+pub fn check(image: &ImageData) -> Result<(), CropError> {
+    if let Some(focal_point) = image.focal_point {
+        let in_range = |v: f64| (0.0..=1.0).contains(&v);
+        if !in_range(focal_point.x) || !in_range(focal_point.y) {
+            return Err(CropError::FocalPointOutOfRange((
+                focal_point.x,
+                focal_point.y,
+            )));
+        }
+    }
+    for crop in &image.crops {
+        if crop.rect.width == 0 || crop.rect.height == 0 {
+            return Err(CropError::EmptyRegion(crop.name.clone()));
+        }
+        let right = u64::from(crop.rect.x) + u64::from(crop.rect.width);
+        let bottom = u64::from(crop.rect.y) + u64::from(crop.rect.height);
+        if right > u64::from(image.width) || bottom > u64::from(image.height) {
+            return Err(CropError::RegionOutOfBounds(crop.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn aspect_ratio(width: u32, height: u32) -> f64 {
+    f64::from(width) / f64::from(height)
+}
+
+/// Clip a rectangle to the image, returning `None` if nothing is left.
+fn clip(rect: Rect, image: &ImageData) -> Option<Rect> {
+    let x = rect.x.min(image.width);
+    let y = rect.y.min(image.height);
+    let width = rect.width.min(image.width - x);
+    let height = rect.height.min(image.height - y);
+    (width > 0 && height > 0).then_some(Rect {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+/// The named crop whose aspect ratio is closest to `target`, if any is within
+/// [`CROP_TOLERANCE`]. Regions are clipped to the image first.
+fn best_region(image: &ImageData, target: f64) -> Option<Rect> {
+    image
+        .crops
+        .iter()
+        .filter_map(|CropRegion { rect, .. }| clip(*rect, image))
+        .map(|rect| {
+            let distance = (aspect_ratio(rect.width, rect.height) / target).ln().abs();
+            (rect, distance)
+        })
+        .filter(|(_, distance)| *distance <= CROP_TOLERANCE.ln())
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(rect, _)| rect)
+}
+
+/// Place a length on one axis so it is centred on `center` as far as the bounds allow.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn place(start: u32, bound: u32, len: u32, center: f64) -> u32 {
+    let offset = (center - f64::from(len) / 2.0).round().max(0.0) as u32;
+    offset.clamp(start, start + bound - len)
+}
+
+/// The largest rectangle of `target` aspect ratio within `bounds`, centred on
+/// `center` (in image pixels) as far as the bounds allow.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn fit(bounds: Rect, target: f64, center: (f64, f64)) -> Rect {
+    let (width, height) = if aspect_ratio(bounds.width, bounds.height) > target {
+        let width = (f64::from(bounds.height) * target).round() as u32;
+        (width.clamp(1, bounds.width), bounds.height)
+    } else {
+        let height = (f64::from(bounds.width) / target).round() as u32;
+        (bounds.width, height.clamp(1, bounds.height))
+    };
+    Rect {
+        x: place(bounds.x, bounds.width, width, center.0),
+        y: place(bounds.y, bounds.height, height, center.1),
+        width,
+        height,
+    }
+}
+
+/// The region of `image` to show on a display of `width` by `height` pixels.
+/// The region has the display's aspect ratio and lies within the image, so it
+/// only needs scaling to fill the display.
+#[must_use]
+pub fn crop_for(image: &ImageData, width: u32, height: u32) -> Rect {
+    let full = Rect {
+        x: 0,
+        y: 0,
+        width: image.width,
+        height: image.height,
+    };
+    if width == 0 || height == 0 || image.width == 0 || image.height == 0 {
+        return full;
+    }
+    let target = aspect_ratio(width, height);
+    let bounds = best_region(image, target).unwrap_or(full);
+    let center = image.focal_point.map_or_else(
+        || {
+            (
+                f64::from(bounds.x) + f64::from(bounds.width) / 2.0,
+                f64::from(bounds.y) + f64::from(bounds.height) / 2.0,
+            )
+        },
+        |point| {
+            (
+                point.x * f64::from(image.width),
+                point.y * f64::from(image.height),
+            )
+        },
+    );
+    fit(bounds, target, center)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, crop_for, CropError};
+    use crate::types::{CropRegion, FocalPoint, ImageData, Rect};
+
+    fn panorama() -> ImageData {
+        let mut image = serde_json::from_str::<ImageData>(
+            r#"{
+                "url": "https://stsci-opo.org/STScI-01G77PKB8NKR7S8Z6HBXMYATGJ.png",
+                "hash": "d2e0b35ab4a9e3f6d0b9d7d8a8f5a5a5e4e3c1b6b1c2d3e4f5a6b7c8d9e0f1a2",
+                "width": 14575,
+                "height": 8441,
+                "format": "png"
+            }"#,
+        )
+        .expect("image");
+        image.focal_point = Some(FocalPoint { x: 0.3, y: 0.6 });
+        image
+    }
+
+    #[test]
+    fn centres_on_focal_point() {
+        let image = panorama();
+        // 16:9 of the full width, pushed to the bottom edge towards the focal point
+        let rect = crop_for(&image, 1920, 1080);
+        assert_eq!(
+            rect,
+            Rect {
+                x: 0,
+                y: 243,
+                width: 14575,
+                height: 8198
+            }
+        );
+        // the focal point is at x = 4372.5, left of centre
+        let rect = crop_for(&image, 1080, 1920);
+        assert_eq!(
+            rect,
+            Rect {
+                x: 1999,
+                y: 0,
+                width: 4748,
+                height: 8441
+            }
+        );
+        // near an edge, the crop is pushed back inside the image
+        let mut image = image;
+        image.focal_point = Some(FocalPoint { x: 0.0, y: 0.0 });
+        let rect = crop_for(&image, 1080, 1920);
+        assert_eq!((rect.x, rect.y), (0, 0));
+    }
+
+    #[test]
+    fn prefers_named_crop_with_similar_aspect() {
+        let mut image = panorama();
+        let cliffs = Rect {
+            x: 2000,
+            y: 3000,
+            width: 3000,
+            height: 1700,
+        };
+        image.crops = vec![
+            CropRegion {
+                name: "portrait".into(),
+                rect: Rect {
+                    x: 4000,
+                    y: 0,
+                    width: 4000,
+                    height: 8000,
+                },
+            },
+            CropRegion {
+                name: "cliffs".into(),
+                rect: cliffs,
+            },
+        ];
+        let rect = crop_for(&image, 2560, 1440);
+        assert!(rect.x >= cliffs.x && rect.x + rect.width <= cliffs.x + cliffs.width);
+        assert!(rect.y >= cliffs.y && rect.y + rect.height <= cliffs.y + cliffs.height);
+        // ultrawide is too far from both, so the whole image is used
+        let rect = crop_for(&image, 5120, 1440);
+        assert_eq!(rect.width, 14575);
+
+        assert!(check(&image).is_ok());
+        image.crops[0].rect.height = 9000;
+        assert!(matches!(
+            check(&image),
+            Err(CropError::RegionOutOfBounds(name)) if name == "portrait"
+        ));
+    }
+}
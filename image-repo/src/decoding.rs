@@ -0,0 +1,324 @@
+//! Format detection and conversion helpers for the `decoding` feature.
+
+use crate::types::{ImgError, Rect, SupportedFormat};
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::Path,
+};
+
+/// The shortest input `imghdr` can look at; it indexes into the first 12 bytes
+/// unchecked and panics on anything shorter.
+const MIN_HEADER_LEN: usize = 12;
+
+/// AVIF files are ISOBMFF containers whose `ftyp` box names an AVIF brand,
+/// either as its major brand or, e.g. for `mif1` files, as a compatible brand.
+fn is_avif(img_bytes: &[u8]) -> bool {
+    if img_bytes.get(4..8) != Some(b"ftyp") {
+        return false;
+    }
+    let Some(size) = img_bytes.get(..4).and_then(|size| size.try_into().ok()) else {
+        return false;
+    };
+    let size = usize::try_from(u32::from_be_bytes(size)).unwrap_or(usize::MAX);
+    let ftyp = &img_bytes[..size.min(img_bytes.len())];
+    // major brand, then a minor version, then the compatible brands
+    let major = ftyp.get(8..12).into_iter();
+    let compatible = ftyp.get(16..).unwrap_or_default().chunks_exact(4);
+    major
+        .chain(compatible)
+        .any(|brand| matches!(brand, b"avif" | b"avis"))
+}
+
+/// How much of a file [`sniff_file`] reads. Enough for the `ftyp` box of an
+/// AVIF file, which lists its brands.
+pub const SNIFF_LEN: u64 = 4096;
+
+/// Detect the format of the image file at `path` from its first [`SNIFF_LEN`]
+/// bytes, without reading the rest of it. `None` if it isn't an image in a
+/// supported format, so that e.g. a large video isn't read in full to find out.
+///
+/// # Errors
+///
+/// Fails if the file can't be read.
+pub fn sniff_file(path: &Path) -> io::Result<Option<SupportedFormat>> {
+    let mut header = vec![];
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut header)?;
+    Ok(detect_format(&header).ok())
+}
+
+/// Detect the format of an image from its leading bytes.
+///
+/// # Errors
+///
+/// [`ImgError::CouldntDetectFormat`] or [`ImgError::UnsupportedFormat`]
+pub fn detect_format(img_bytes: &[u8]) -> Result<SupportedFormat, ImgError> {
+    if is_avif(img_bytes) {
+        return Ok(SupportedFormat::Avif);
+    }
+
+    if img_bytes.len() < MIN_HEADER_LEN {
+        return Err(ImgError::CouldntDetectFormat);
+    }
+    let Some(format) = imghdr::from_bytes(img_bytes) else {
+        return Err(ImgError::CouldntDetectFormat);
+    };
+    match format {
+        imghdr::Type::Jpeg => Ok(SupportedFormat::Jpg),
+        imghdr::Type::Png => Ok(SupportedFormat::Png),
+        imghdr::Type::Tiff => Ok(SupportedFormat::Tiff),
+        imghdr::Type::Webp => Ok(SupportedFormat::Webp),
+        _ => Err(ImgError::UnsupportedFormat(format!("{format:?}"))),
+    }
+}
+
+/// The [`image::ImageFormat`] used to decode the given format, if `image` can decode it.
+fn image_format(format: SupportedFormat) -> Option<image::ImageFormat> {
+    match format {
+        SupportedFormat::Jpg => Some(image::ImageFormat::Jpeg),
+        SupportedFormat::Png => Some(image::ImageFormat::Png),
+        SupportedFormat::Tiff => Some(image::ImageFormat::Tiff),
+        SupportedFormat::Webp => Some(image::ImageFormat::WebP),
+        // decoding AVIF needs the native dav1d library
+        SupportedFormat::Avif => None,
+    }
+}
+
+/// Decode an image's pixels. `what` describes what the image is being decoded
+/// for, for the error message when its format can't be decoded.
+pub(crate) fn decode(
+    format: SupportedFormat,
+    img_bytes: &[u8],
+    what: &str,
+) -> Result<image::DynamicImage, ImgError> {
+    let Some(image_format) = image_format(format) else {
+        return Err(ImgError::UnsupportedFormat(format!(
+            "{format} (can't be {what})"
+        )));
+    };
+    Ok(image::io::Reader::with_format(Cursor::new(img_bytes), image_format).decode()?)
+}
+
+/// Read the dimensions of an image, from its header where possible. Only
+/// images whose header [`crate::header`] can't parse are decoded.
+///
+/// # Errors
+///
+/// [`ImgError`] if the header can't be read and the image can't be decoded.
+pub fn dimensions(format: SupportedFormat, img_bytes: &[u8]) -> Result<(u32, u32), ImgError> {
+    if let Some(dimensions) = crate::header::dimensions(format, img_bytes) {
+        return Ok(dimensions);
+    }
+    decode_dimensions(format, img_bytes)
+}
+
+fn decode_dimensions(format: SupportedFormat, img_bytes: &[u8]) -> Result<(u32, u32), ImgError> {
+    use image::GenericImageView;
+
+    Ok(decode(format, img_bytes, "decoded")?.dimensions())
+}
+
+/// Fully decode an image to check that its pixel data is intact and matches the
+/// dimensions in its header. This is slow and memory hungry for large images, so
+/// it's an optional check on top of [`dimensions`]. AVIF images can't be decoded,
+/// so only their header is checked.
+///
+/// # Errors
+///
+/// [`ImgError::DecodingFailed`] if the image is corrupt, or
+/// [`ImgError::DimensionsMismatch`] if the header disagrees with the pixel data.
+pub fn check_integrity(format: SupportedFormat, img_bytes: &[u8]) -> Result<(u32, u32), ImgError> {
+    let header = crate::header::dimensions(format, img_bytes);
+    if format == SupportedFormat::Avif {
+        return header.ok_or(ImgError::CouldntDetectFormat);
+    }
+    let decoded = decode_dimensions(format, img_bytes)?;
+    match header {
+        Some(header) if header != decoded => Err(ImgError::DimensionsMismatch((header, decoded))),
+        _ => Ok(decoded),
+    }
+}
+
+/// Convert an image to a format every desktop environment can display as a
+/// wallpaper. Images already in such a format are returned as-is; others are
+/// re-encoded as PNG.
+///
+/// # Errors
+///
+/// [`ImgError::UnsupportedFormat`] if the image can't be decoded for conversion
+/// (currently AVIF), or [`ImgError::DecodingFailed`] if decoding or re-encoding fails.
+pub fn to_desktop_compatible(
+    format: SupportedFormat,
+    img_bytes: &[u8],
+) -> Result<(SupportedFormat, Vec<u8>), ImgError> {
+    if format.is_desktop_compatible() {
+        return Ok((format, img_bytes.to_vec()));
+    }
+
+    let img = decode(format, img_bytes, "converted")?;
+    let mut png = Cursor::new(vec![]);
+    img.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok((SupportedFormat::Png, png.into_inner()))
+}
+
+/// Cut `rect` out of an image and scale it down to fit within `width` by `height`
+/// pixels, re-encoded as PNG. Use [`crate::crop::crop_for`] to get a `rect` that
+/// fits a display. Smaller crops are never scaled up.
+///
+/// # Errors
+///
+/// [`ImgError::UnsupportedFormat`] if the image can't be decoded (currently AVIF),
+/// or [`ImgError::DecodingFailed`] if decoding or re-encoding fails.
+pub fn crop_to_fit(
+    format: SupportedFormat,
+    img_bytes: &[u8],
+    rect: Rect,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, ImgError> {
+    let mut img = decode(format, img_bytes, "cropped")?;
+    let mut img = img.crop(rect.x, rect.y, rect.width, rect.height);
+    if img.width() > width || img.height() > height {
+        img = img.resize(width, height, image::imageops::FilterType::Lanczos3);
+    }
+    let mut png = Cursor::new(vec![]);
+    img.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_integrity, crop_to_fit, detect_format, dimensions, to_desktop_compatible};
+    use crate::types::{ImgError, Rect, SupportedFormat};
+    use std::io::Cursor;
+
+    fn ferris_as(format: image::ImageOutputFormat) -> Vec<u8> {
+        let img = image::load_from_memory(include_bytes!("../ferris.png")).expect("ferris");
+        let mut bytes = Cursor::new(vec![]);
+        img.write_to(&mut bytes, format).expect("encode");
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn converts_tiff_to_png() {
+        let tiff = ferris_as(image::ImageOutputFormat::Tiff);
+        assert!(matches!(detect_format(&tiff), Ok(SupportedFormat::Tiff)));
+
+        let (format, png) = to_desktop_compatible(SupportedFormat::Tiff, &tiff).expect("png");
+        assert!(matches!(format, SupportedFormat::Png));
+        assert!(matches!(detect_format(&png), Ok(SupportedFormat::Png)));
+        assert!(detect_format(b"hi\n").is_err());
+    }
+
+    #[test]
+    fn crops_and_scales_down() {
+        let ferris = include_bytes!("../ferris.png");
+        let rect = Rect {
+            x: 100,
+            y: 0,
+            width: 200,
+            height: 300,
+        };
+        let png = crop_to_fit(SupportedFormat::Png, ferris, rect, 100, 200).expect("crop");
+        let img = image::load_from_memory(&png).expect("png");
+        assert_eq!((img.width(), img.height()), (100, 150));
+        let png = crop_to_fit(SupportedFormat::Png, ferris, rect, 1000, 1000).expect("crop");
+        let img = image::load_from_memory(&png).expect("png");
+        assert_eq!((img.width(), img.height()), (200, 300));
+    }
+
+    #[test]
+    fn reads_dimensions_without_decoding() {
+        for (format, bytes) in [
+            (
+                SupportedFormat::Png,
+                include_bytes!("../ferris.png").to_vec(),
+            ),
+            (
+                SupportedFormat::Jpg,
+                ferris_as(image::ImageOutputFormat::Jpeg(90)),
+            ),
+            (
+                SupportedFormat::Tiff,
+                ferris_as(image::ImageOutputFormat::Tiff),
+            ),
+        ] {
+            assert_eq!(
+                crate::header::dimensions(format, &bytes),
+                Some((460, 307)),
+                "{format}"
+            );
+            assert_eq!(
+                check_integrity(format, &bytes).expect("decodes"),
+                (460, 307)
+            );
+        }
+
+        // a truncated file still has its header, but no longer decodes
+        let ferris = include_bytes!("../ferris.png");
+        let truncated = &ferris[..ferris.len() / 2];
+        assert_eq!(
+            dimensions(SupportedFormat::Png, truncated).expect("header"),
+            (460, 307)
+        );
+        assert!(check_integrity(SupportedFormat::Png, truncated).is_err());
+    }
+
+    fn isobmff_box(kind: &[u8], contents: &[u8]) -> Vec<u8> {
+        let size = u32::try_from(contents.len() + 8).expect("size");
+        [&size.to_be_bytes(), kind, contents].concat()
+    }
+
+    #[test]
+    fn reads_avif_header() {
+        let full = |kind, contents: &[u8]| isobmff_box(kind, &[&[0; 4], contents].concat());
+        let ispe = |width: u32, height: u32| {
+            full(
+                b"ispe",
+                &[width.to_be_bytes(), height.to_be_bytes()].concat(),
+            )
+        };
+        // `mif1` files can list `avif` as a compatible brand only
+        let ftyp = isobmff_box(b"ftyp", b"mif1\0\0\0\0mif1avif");
+        // the first property is a thumbnail's size, the primary item (2) has the third
+        let ipco = isobmff_box(
+            b"ipco",
+            &[ispe(320, 185), isobmff_box(b"pixi", &[]), ispe(14575, 8441)].concat(),
+        );
+        let ipma = full(b"ipma", &[0, 0, 0, 2, 0, 1, 1, 0x81, 0, 2, 2, 0x82, 0x03]);
+        let meta = full(
+            b"meta",
+            &[
+                full(b"pitm", &[0, 2]),
+                isobmff_box(b"iprp", &[ipco, ipma].concat()),
+            ]
+            .concat(),
+        );
+        // an `ispe` lookalike in the pixel data comes first
+        let mdat = isobmff_box(b"mdat", &ispe(1, 1));
+        let avif = [ftyp, mdat, meta].concat();
+
+        assert!(matches!(detect_format(&avif), Ok(SupportedFormat::Avif)));
+        assert_eq!(
+            dimensions(SupportedFormat::Avif, &avif).expect("header"),
+            (14575, 8441)
+        );
+
+        let heic = isobmff_box(b"ftyp", b"mif1\0\0\0\0mif1heic");
+        assert!(!matches!(detect_format(&heic), Ok(SupportedFormat::Avif)));
+    }
+
+    #[test]
+    fn reads_avif_dimensions_without_decoding() {
+        let avif = include_bytes!("../ferris.avif");
+        assert!(matches!(detect_format(avif), Ok(SupportedFormat::Avif)));
+        assert_eq!(
+            check_integrity(SupportedFormat::Avif, avif).expect("header"),
+            (92, 61)
+        );
+        assert!(matches!(
+            to_desktop_compatible(SupportedFormat::Avif, avif),
+            Err(ImgError::UnsupportedFormat(_))
+        ));
+    }
+}
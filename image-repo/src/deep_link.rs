@@ -0,0 +1,57 @@
+//! Links that open the app and offer to add a repository, for putting on a
+//! repository's web page: `jdt://add?url=<manifest URL>`.
+
+use crate::Url;
+use url::form_urlencoded;
+
+/// URL scheme the app registers for its links.
+pub const SCHEME: &str = "jdt";
+
+/// The link that adds the repository whose manifest is published at `manifest`.
+#[must_use]
+pub fn add_repository(manifest: &Url) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("url", manifest.as_str())
+        .finish();
+    format!("{SCHEME}://add?{query}")
+}
+
+/// The manifest URL of a link made by [`add_repository`], or `None` if `link`
+/// isn't one. Only `http` and `https` manifests are accepted, so a web page can't
+/// get the app to read local files.
+#[must_use]
+pub fn parse(link: &Url) -> Option<Url> {
+    if link.scheme() != SCHEME || link.host_str() != Some("add") {
+        return None;
+    }
+    let (_, manifest) = link.query_pairs().find(|(key, _)| key == "url")?;
+    Url::parse(&manifest)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_repository, parse};
+    use crate::Url;
+
+    #[test]
+    fn round_trips_manifest_urls() {
+        let manifest = Url::parse("https://example.com/jwst/repo.json?v=2&x=y").expect("url");
+        let link = add_repository(&manifest);
+        assert_eq!(
+            link,
+            "jdt://add?url=https%3A%2F%2Fexample.com%2Fjwst%2Frepo.json%3Fv%3D2%26x%3Dy"
+        );
+        assert_eq!(parse(&Url::parse(&link).expect("link")), Some(manifest));
+
+        for link in [
+            "jdt://add?url=file%3A%2F%2F%2Fetc%2Fpasswd",
+            "jdt://remove?url=https%3A%2F%2Fexample.com%2Frepo.json",
+            "https://add?url=https%3A%2F%2Fexample.com%2Frepo.json",
+            "jdt://add",
+        ] {
+            assert_eq!(parse(&Url::parse(link).expect("link")), None, "{link}");
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Reading image dimensions from file headers, without decoding any pixels.
+//!
+//! Decoding a 16-bit 14575x8441 PNG takes gigabytes of memory and several
+//! seconds, while its width and height are sitting in the first 24 bytes. These
+//! parsers only look at as much of the file as they need, and return `None` for
+//! anything they don't understand so callers can fall back to decoding.
+
+use crate::types::SupportedFormat;
+
+/// Read the dimensions of an image from its header.
+#[must_use]
+pub fn dimensions(format: SupportedFormat, img_bytes: &[u8]) -> Option<(u32, u32)> {
+    let (width, height) = match format {
+        SupportedFormat::Png => png(img_bytes),
+        SupportedFormat::Jpg => jpeg(img_bytes),
+        SupportedFormat::Tiff => tiff(img_bytes),
+        SupportedFormat::Webp => webp(img_bytes),
+        SupportedFormat::Avif => avif(img_bytes),
+    }?;
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// An unsigned integer of `len` bytes, at most 4, at `offset`.
+fn uint(img_bytes: &[u8], offset: usize, len: usize, big_endian: bool) -> Option<u32> {
+    let value = img_bytes.get(offset..offset.checked_add(len)?)?;
+    let fold = |acc: u32, &byte: &u8| (acc << 8) | u32::from(byte);
+    Some(if big_endian {
+        value.iter().fold(0, fold)
+    } else {
+        value.iter().rev().fold(0, fold)
+    })
+}
+
+fn u16_be(img_bytes: &[u8], offset: usize) -> Option<u32> {
+    uint(img_bytes, offset, 2, true)
+}
+
+fn u32_be(img_bytes: &[u8], offset: usize) -> Option<u32> {
+    uint(img_bytes, offset, 4, true)
+}
+
+fn uint_le(img_bytes: &[u8], offset: usize, len: usize) -> Option<u32> {
+    uint(img_bytes, offset, len, false)
+}
+
+/// The `IHDR` chunk always comes first, right after the 8 byte signature.
+fn png(img_bytes: &[u8]) -> Option<(u32, u32)> {
+    if img_bytes.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    Some((u32_be(img_bytes, 16)?, u32_be(img_bytes, 20)?))
+}
+
+/// Walk the JPEG markers up to the first start of frame (`SOFn`) segment.
+fn jpeg(img_bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        if *img_bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        // markers may be preceded by any number of fill bytes
+        while *img_bytes.get(pos + 1)? == 0xFF {
+            pos += 1;
+        }
+        let marker = *img_bytes.get(pos + 1)?;
+        pos += 2;
+        match marker {
+            // standalone markers without a length
+            0x01 | 0xD0..=0xD7 => {}
+            // end of image or start of scan before any frame header
+            0xD9 | 0xDA => return None,
+            // SOF0 to SOF15, except DHT, JPG and DAC which share the range
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                // length, then precision, then height and width
+                let height = u16_be(img_bytes, pos + 3)?;
+                let width = u16_be(img_bytes, pos + 5)?;
+                return Some((width, height));
+            }
+            _ => pos += usize::try_from(u16_be(img_bytes, pos)?).ok()?,
+        }
+    }
+}
+
+/// Read the `ImageWidth` and `ImageLength` tags of the first IFD. `BigTIFF` isn't
+/// supported.
+fn tiff(img_bytes: &[u8]) -> Option<(u32, u32)> {
+    let big_endian = match img_bytes.get(..4)? {
+        b"II\x2A\x00" => false,
+        b"MM\x00\x2A" => true,
+        _ => return None,
+    };
+    let read = |offset: usize, len: usize| uint(img_bytes, offset, len, big_endian);
+
+    let ifd = usize::try_from(read(4, 4)?).ok()?;
+    let entries = usize::try_from(read(ifd, 2)?).ok()?;
+    let (mut width, mut height) = (None, None);
+    for i in 0..entries {
+        let entry = ifd + 2 + i * 12;
+        let value = match read(entry + 2, 2)? {
+            // SHORT
+            3 => read(entry + 8, 2)?,
+            // LONG
+            4 => read(entry + 8, 4)?,
+            _ => continue,
+        };
+        match read(entry, 2)? {
+            256 => width = Some(value),
+            257 => height = Some(value),
+            _ => {}
+        }
+    }
+    Some((width?, height?))
+}
+
+/// WebP files hold a single `VP8X` (extended), `VP8L` (lossless) or `VP8 `
+/// (lossy) chunk first, each with its own header layout.
+fn webp(img_bytes: &[u8]) -> Option<(u32, u32)> {
+    if img_bytes.get(..4)? != b"RIFF" || img_bytes.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let data = 20;
+    match img_bytes.get(12..16)? {
+        // flags and reserved bytes, then 24-bit canvas width and height minus one
+        b"VP8X" => Some((
+            uint_le(img_bytes, data + 4, 3)? + 1,
+            uint_le(img_bytes, data + 7, 3)? + 1,
+        )),
+        // signature byte, then 14 bits each of width and height minus one
+        b"VP8L" => {
+            if *img_bytes.get(data)? != 0x2F {
+                return None;
+            }
+            let bits = uint_le(img_bytes, data + 1, 4)?;
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        // frame tag and start code, then 14 bits each of width and height
+        b"VP8 " => {
+            if img_bytes.get(data + 3..data + 6)? != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            Some((
+                uint_le(img_bytes, data + 6, 2)? & 0x3FFF,
+                uint_le(img_bytes, data + 8, 2)? & 0x3FFF,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// The boxes in an ISOBMFF container, as their type and contents. Stops at the
+/// first box that runs past the end of `data`.
+fn isobmff_boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let (header, size) = match u32_be(data, 0)? {
+            // the box runs to the end of the file
+            0 => (8, data.len()),
+            // a 64 bit size follows the type
+            1 => {
+                let size = u64::from(u32_be(data, 8)?) << 32 | u64::from(u32_be(data, 12)?);
+                (16, usize::try_from(size).ok()?)
+            }
+            size => (8, usize::try_from(size).ok()?),
+        };
+        let kind = data.get(4..8)?;
+        let contents = data.get(header..size)?;
+        data = &data[size..];
+        Some((kind, contents))
+    })
+}
+
+/// The contents of the first box of the given type.
+fn isobmff_box<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    isobmff_boxes(data).find_map(|(k, contents)| (k == kind).then_some(contents))
+}
+
+/// The boxes inside an AVIF/HEIF file's `meta` box, which describes its items.
+/// `meta` and most boxes in it are full boxes, whose contents start with a
+/// version byte and 3 bytes of flags.
+fn heif_meta(img_bytes: &[u8]) -> Option<&[u8]> {
+    isobmff_box(img_bytes, b"meta")?.get(4..)
+}
+
+/// The ID of the primary item, from the `pitm` box.
+fn heif_primary_item(meta: &[u8]) -> Option<u32> {
+    let pitm = isobmff_box(meta, b"pitm")?;
+    if *pitm.first()? == 0 {
+        u16_be(pitm, 4)
+    } else {
+        u32_be(pitm, 4)
+    }
+}
+
+/// The properties of an item, as their box type and contents: `iprp/ipma` lists
+/// the indices of each item's properties, and `iprp/ipco` holds the properties.
+fn heif_item_properties(meta: &[u8], item: u32) -> Option<Vec<(&[u8], &[u8])>> {
+    let iprp = isobmff_box(meta, b"iprp")?;
+    let ipma = isobmff_box(iprp, b"ipma")?;
+    let properties: Vec<_> = isobmff_boxes(isobmff_box(iprp, b"ipco")?).collect();
+
+    let (version, large_indices) = (*ipma.first()?, ipma.get(3)? & 1 == 1);
+    let index_len = if large_indices { 2 } else { 1 };
+    let entries = u32_be(ipma, 4)?;
+    let mut pos = 8;
+    for _ in 0..entries {
+        let id_len = if version == 0 { 2 } else { 4 };
+        let id = uint(ipma, pos, id_len, true)?;
+        let associations = usize::from(*ipma.get(pos + id_len)?);
+        pos += id_len + 1;
+        if id != item {
+            pos += associations * index_len;
+            continue;
+        }
+        return (0..associations)
+            .map(|i| {
+                // the top bit marks essential properties, the rest is a 1-based index
+                let index = uint(ipma, pos + i * index_len, index_len, true)?
+                    & if large_indices { 0x7FFF } else { 0x7F };
+                properties
+                    .get(usize::try_from(index).ok()?.checked_sub(1)?)
+                    .copied()
+            })
+            .collect();
+    }
+    None
+}
+
+/// Read the `ispe` (image spatial extents) property of the primary item. Other
+/// `ispe` boxes, e.g. for thumbnails or the alpha channel, are ignored.
+fn avif(img_bytes: &[u8]) -> Option<(u32, u32)> {
+    let meta = heif_meta(img_bytes)?;
+    let properties = heif_item_properties(meta, heif_primary_item(meta)?)?;
+    let ispe = properties
+        .iter()
+        .find_map(|&(kind, contents)| (kind == b"ispe").then_some(contents))?;
+    // after the version and flags
+    Some((u32_be(ispe, 4)?, u32_be(ispe, 8)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dimensions;
+    use crate::types::SupportedFormat;
+
+    #[test]
+    fn reads_png_header() {
+        let ferris = include_bytes!("../ferris.png");
+        assert_eq!(dimensions(SupportedFormat::Png, ferris), Some((460, 307)));
+        // only the header is needed
+        assert_eq!(
+            dimensions(SupportedFormat::Png, &ferris[..24]),
+            Some((460, 307))
+        );
+        assert_eq!(dimensions(SupportedFormat::Png, &ferris[..20]), None);
+    }
+
+    #[test]
+    fn reads_webp_headers() {
+        let riff = |chunk: &[u8], data: &[u8]| {
+            let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+            webp.extend_from_slice(chunk);
+            webp.extend_from_slice(&[0; 4]);
+            webp.extend_from_slice(data);
+            webp
+        };
+        // 14575x8441, stored minus one
+        let extended = riff(
+            b"VP8X",
+            &[0x10, 0, 0, 0, 0xEE, 0x38, 0x00, 0xF8, 0x20, 0x00],
+        );
+        assert_eq!(
+            dimensions(SupportedFormat::Webp, &extended),
+            Some((14575, 8441))
+        );
+
+        let bits: u32 = (460 - 1) | ((307 - 1) << 14);
+        let mut data = vec![0x2F];
+        data.extend_from_slice(&bits.to_le_bytes());
+        let lossless = riff(b"VP8L", &data);
+        assert_eq!(
+            dimensions(SupportedFormat::Webp, &lossless),
+            Some((460, 307))
+        );
+
+        let lossy = riff(
+            b"VP8 ",
+            &[0, 0, 0, 0x9D, 0x01, 0x2A, 0xCC, 0x01, 0x33, 0x01],
+        );
+        assert_eq!(dimensions(SupportedFormat::Webp, &lossy), Some((460, 307)));
+    }
+}
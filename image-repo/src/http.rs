@@ -0,0 +1,353 @@
+//! HTTP client settings shared by everything that downloads manifests and
+//! images, so the app and `repogen` behave the same on slow or flaky networks.
+//!
+//! Requests that fail in a way that may be temporary, i.e. connection errors,
+//! timeouts, `429 Too Many Requests` and `5xx` responses, are retried with
+//! exponential backoff. Any other response is returned for the caller to handle.
+
+use crate::Url;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Identifies the app to servers, e.g. for publishers looking at their logs.
+pub const USER_AGENT: &str = concat!(
+    "jdt/",
+    env!("CARGO_PKG_VERSION"),
+    " (wallpaper image repository client)"
+);
+
+/// How to make HTTP requests. Missing fields take their default, so a settings
+/// file only needs to list what it changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HttpConfig {
+    /// Seconds to wait for a connection to the server.
+    pub connect_timeout_secs: u64,
+    /// Seconds to wait for the server to respond, or to send more of the body.
+    /// Large downloads are fine as long as they keep making progress.
+    pub read_timeout_secs: u64,
+    /// How often to retry a request that failed in a way that may be temporary.
+    pub retries: u32,
+    /// Milliseconds to wait before the first retry, doubling for each one after.
+    pub retry_delay_ms: u64,
+    /// Proxy for every request, e.g. `http://proxy.example.com:8080`. Without
+    /// one, the `HTTP_PROXY` and `HTTPS_PROXY` environment variables are used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Url>,
+    pub user_agent: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            retries: 3,
+            retry_delay_ms: 500,
+            proxy: None,
+            user_agent: USER_AGENT.to_string(),
+        }
+    }
+}
+
+impl HttpConfig {
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    fn proxy(&self) -> Result<Option<reqwest::Proxy>, reqwest::Error> {
+        self.proxy
+            .as_ref()
+            .map(|proxy| reqwest::Proxy::all(proxy.clone()))
+            .transpose()
+    }
+
+    /// How long to wait before retry number `attempt`, counting from 0.
+    fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.retry_delay_ms.saturating_mul(1 << attempt.min(16)))
+    }
+}
+
+/// Whether a response is worth asking for again.
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Whether a request failed in a way that is worth trying again.
+fn is_transient_error(error: &HttpError) -> bool {
+    match error {
+        HttpError::Request(e) => e.is_timeout() || e.is_connect(),
+        HttpError::ReadTimeout(_) => true,
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Request(reqwest::Error),
+    /// The server sent nothing for longer than [`HttpConfig::read_timeout_secs`].
+    ReadTimeout(Duration),
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Request(e) => write!(f, "{e}"),
+            HttpError::ReadTimeout(timeout) => write!(
+                f,
+                "The server sent nothing for {} seconds",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Request(e) => Some(e),
+            HttpError::ReadTimeout(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(value: reqwest::Error) -> Self {
+        HttpError::Request(value)
+    }
+}
+
+/// An async HTTP client configured by an [`HttpConfig`]. Cheap to clone, and
+/// clones share their connections.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    config: HttpConfig,
+}
+
+impl HttpClient {
+    /// # Errors
+    ///
+    /// If the proxy isn't a valid proxy URL, or the TLS backend can't be set up.
+    pub fn new(config: &HttpConfig) -> Result<Self, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout())
+            .user_agent(&config.user_agent);
+        if let Some(proxy) = config.proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        Ok(Self {
+            client: builder.build()?,
+            config: config.clone(),
+        })
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &HttpConfig {
+        &self.config
+    }
+
+    /// Send the request made by `request`, retrying as described in the
+    /// [module docs](self). The last response is returned even if it's an error
+    /// status, e.g. when the server is still failing after every retry.
+    ///
+    /// # Errors
+    ///
+    /// [`HttpError`] if the last attempt failed without a response.
+    pub async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpError> {
+        let timeout = self.config.read_timeout();
+        let mut attempt = 0;
+        loop {
+            let result = match tokio::time::timeout(timeout, request(&self.client).send()).await {
+                Ok(result) => result.map_err(HttpError::from),
+                Err(_) => Err(HttpError::ReadTimeout(timeout)),
+            };
+            let retry = match &result {
+                Ok(resp) => is_transient_status(resp.status()),
+                Err(e) => is_transient_error(e),
+            };
+            if !retry || attempt >= self.config.retries {
+                return result;
+            }
+            tokio::time::sleep(self.config.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// `GET` a URL, see [`HttpClient::send`].
+    ///
+    /// # Errors
+    ///
+    /// [`HttpError`] if the last attempt failed without a response.
+    pub async fn get(&self, url: &Url) -> Result<reqwest::Response, HttpError> {
+        self.send(|client| client.get(url.clone())).await
+    }
+
+    /// Append the next chunk of a response's body to `bytes`, returning whether
+    /// there was one. Unlike reading the whole body at once, this only times out
+    /// if the server stops sending.
+    ///
+    /// # Errors
+    ///
+    /// [`HttpError`] if the connection failed or timed out.
+    pub async fn read_chunk(
+        &self,
+        resp: &mut reqwest::Response,
+        bytes: &mut Vec<u8>,
+    ) -> Result<bool, HttpError> {
+        let timeout = self.config.read_timeout();
+        match tokio::time::timeout(timeout, resp.chunk()).await {
+            Ok(Ok(Some(chunk))) => {
+                bytes.extend_from_slice(&chunk);
+                Ok(true)
+            }
+            Ok(Ok(None)) => Ok(false),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(HttpError::ReadTimeout(timeout)),
+        }
+    }
+
+    /// Read the rest of a response's body, see [`HttpClient::read_chunk`].
+    ///
+    /// # Errors
+    ///
+    /// [`HttpError`] if the connection failed or timed out.
+    pub async fn read_body(&self, mut resp: reqwest::Response) -> Result<Vec<u8>, HttpError> {
+        let mut bytes = vec![];
+        while self.read_chunk(&mut resp, &mut bytes).await? {}
+        Ok(bytes)
+    }
+}
+
+/// A blocking HTTP client configured by an [`HttpConfig`], for command line
+/// tools. Its read timeout applies to each read of the body, not the whole
+/// download. Must not be created or used within an async runtime.
+#[derive(Debug, Clone)]
+pub struct BlockingHttpClient {
+    client: reqwest::blocking::Client,
+    config: HttpConfig,
+}
+
+impl BlockingHttpClient {
+    /// # Errors
+    ///
+    /// If the proxy isn't a valid proxy URL, or the TLS backend can't be set up.
+    pub fn new(config: &HttpConfig) -> Result<Self, reqwest::Error> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .connect_timeout(config.connect_timeout())
+            .timeout(config.read_timeout())
+            .user_agent(&config.user_agent);
+        if let Some(proxy) = config.proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        Ok(Self {
+            client: builder.build()?,
+            config: config.clone(),
+        })
+    }
+
+    /// Send the request made by `request`, like [`HttpClient::send`].
+    ///
+    /// # Errors
+    ///
+    /// [`reqwest::Error`] if the last attempt failed without a response.
+    pub fn send(
+        &self,
+        request: impl Fn(&reqwest::blocking::Client) -> reqwest::blocking::RequestBuilder,
+    ) -> Result<reqwest::blocking::Response, reqwest::Error> {
+        let mut attempt = 0;
+        loop {
+            let result = request(&self.client).send();
+            let retry = match &result {
+                Ok(resp) => is_transient_status(resp.status()),
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+            if !retry || attempt >= self.config.retries {
+                return result;
+            }
+            std::thread::sleep(self.config.backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    /// `GET` a URL, see [`BlockingHttpClient::send`].
+    ///
+    /// # Errors
+    ///
+    /// [`reqwest::Error`] if the last attempt failed without a response.
+    pub fn get(&self, url: &Url) -> Result<reqwest::blocking::Response, reqwest::Error> {
+        self.send(|client| client.get(url.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpClient, HttpConfig};
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn fills_in_missing_settings() {
+        let config = serde_json::from_str::<HttpConfig>(
+            r#"{ "retries": 1, "proxy": "http://proxy.example.com:8080" }"#,
+        )
+        .expect("config");
+        assert_eq!(config.retries, 1);
+        assert_eq!(
+            config.read_timeout_secs,
+            HttpConfig::default().read_timeout_secs
+        );
+        assert!(config.user_agent.starts_with("jdt/"));
+        assert_eq!(config.backoff(0), Duration::from_millis(500));
+        assert_eq!(config.backoff(2), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        // fails twice, then succeeds
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let server = thread::spawn(move || {
+            let mut agents = vec![];
+            for status in [
+                "503 Service Unavailable",
+                "500 Internal Server Error",
+                "200 OK",
+            ] {
+                let (mut stream, _) = listener.accept().expect("accept");
+                let mut request = [0; 1024];
+                let len = stream.read(&mut request).expect("read");
+                agents.push(String::from_utf8_lossy(&request[..len]).to_lowercase());
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+                );
+                stream.write_all(response.as_bytes()).expect("write");
+            }
+            agents
+        });
+
+        let client = HttpClient::new(&HttpConfig {
+            retry_delay_ms: 1,
+            ..HttpConfig::default()
+        })
+        .expect("client");
+        let url = format!("http://{addr}/repo.json").parse().expect("url");
+        let resp = client.get(&url).await.expect("response");
+        assert_eq!(resp.status(), 200);
+        assert_eq!(client.read_body(resp).await.expect("body"), b"ok");
+        let requests = server.join().expect("server");
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].contains("user-agent: jdt/"), "{}", requests[0]);
+    }
+}
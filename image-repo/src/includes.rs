@@ -0,0 +1,224 @@
+//! Composite repositories.
+//!
+//! A repository can list other repository manifests by URL in
+//! [`ImageRepo::includes`]. Resolving a repository downloads those manifests,
+//! recursively, and appends their images to its own. Images are deduplicated by
+//! hash, so an image that appears in several included repositories is only
+//! listed once, and each included image records which repository it came from
+//! in [`ImageData::included_from`]. Included manifests must have valid
+//! signatures, if signed, but checking who signed them is left to the caller.
+
+use crate::{
+    download_signed_repo_manifest,
+    http::HttpClient,
+    types::{ImageData, ImageRepo, IncludeSource},
+    Digest, Error, Url,
+};
+use std::collections::HashSet;
+
+/// How many levels of nested includes are followed before giving up.
+pub const MAX_INCLUDE_DEPTH: usize = 4;
+
+/// Key used to deduplicate images, so that the same digest written
+/// with and without an algorithm prefix counts as one image.
+fn dedup_key(image: &ImageData) -> String {
+    Digest::parse(&image.hash).map_or_else(|_| image.hash.clone(), |digest| digest.to_string())
+}
+
+/// A manifest that was downloaded while resolving includes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedInclude {
+    pub url: Url,
+    /// The publisher key the manifest was signed with, if it was signed.
+    pub signed_by: Option<String>,
+}
+
+/// Resolve [`ImageRepo::includes`], appending the images of every included
+/// repository to `repo.images`. Included repositories are visited depth-first,
+/// in the order they're listed. `repo_url` is the URL `repo` itself was
+/// downloaded from, if any, used to detect a repository including itself.
+/// Included manifests are downloaded with `client`. Returns every manifest that
+/// was included, so their publisher keys can be checked like `repo`'s own.
+///
+/// # Errors
+///
+/// [`Error::IncludeCycle`] if a repository includes itself, directly or
+/// indirectly. [`Error::IncludeDepthExceeded`] if includes are nested deeper
+/// than [`MAX_INCLUDE_DEPTH`]. Otherwise, any error downloading an included manifest.
+pub async fn resolve_includes(
+    client: &HttpClient,
+    repo: &mut ImageRepo,
+    repo_url: Option<&Url>,
+) -> Result<Vec<ResolvedInclude>, Error> {
+    let mut seen = HashSet::new();
+    repo.images.retain(|image| seen.insert(dedup_key(image)));
+    for image in &mut repo.images {
+        // only ever set by resolution, never trust what the manifest says
+        image.included_from = None;
+    }
+
+    let root = repo_url.into_iter().cloned().collect::<Vec<_>>();
+    // stack of (manifest URL, nesting depth, chain of ancestor URLs)
+    let mut stack = repo
+        .includes
+        .iter()
+        .rev()
+        .map(|url| (url.clone(), 1, root.clone()))
+        .collect::<Vec<_>>();
+    let mut visited = HashSet::new();
+    let mut resolved = vec![];
+
+    while let Some((url, depth, ancestors)) = stack.pop() {
+        if ancestors.contains(&url) {
+            return Err(Error::IncludeCycle(url));
+        }
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(Error::IncludeDepthExceeded(url));
+        }
+        // the same repository included along two different paths isn't a cycle,
+        // but there's nothing new to get from it the second time
+        if !visited.insert(url.clone()) {
+            continue;
+        }
+
+        let (included, signed_by) = download_signed_repo_manifest(client, &url).await?;
+        resolved.push(ResolvedInclude {
+            url: url.clone(),
+            signed_by,
+        });
+        let source = IncludeSource {
+            name: included.name.clone(),
+            url: url.clone(),
+        };
+        repo.images.extend(
+            included
+                .images
+                .into_iter()
+                .filter(|image| seen.insert(dedup_key(image)))
+                .map(|mut image| {
+                    image.included_from = Some(source.clone());
+                    image
+                }),
+        );
+
+        let mut chain = ancestors;
+        chain.push(url);
+        stack.extend(
+            included
+                .includes
+                .into_iter()
+                .rev()
+                .map(|child| (child, depth + 1, chain.clone())),
+        );
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_includes, MAX_INCLUDE_DEPTH};
+    use crate::{
+        http::{HttpClient, HttpConfig},
+        types::ImageRepo,
+        Error, Url,
+    };
+    use std::{fs, path::Path};
+
+    const FERRIS: &str = "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb";
+
+    fn client() -> HttpClient {
+        HttpClient::new(&HttpConfig::default()).expect("client")
+    }
+
+    /// Write a manifest named `name` to `dir`, returning its `file://` URL.
+    fn write_manifest(dir: &Path, name: &str, includes: &[&Url], hashes: &[&str]) -> Url {
+        let images = hashes
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| {
+                serde_json::json!({
+                    "url": format!("https://example.com/{name}/{i}.png"),
+                    "hash": hash,
+                    "width": 460,
+                    "height": 307,
+                    "format": "png",
+                    "includedFrom": { "name": "Forged", "url": "https://forged.example.com/" }
+                })
+            })
+            .collect::<Vec<_>>();
+        let path = dir.join(format!("{name}.json"));
+        let manifest = serde_json::json!({ "name": name, "includes": includes, "images": images });
+        fs::write(&path, manifest.to_string()).expect("write manifest");
+        Url::from_file_path(path).expect("file url")
+    }
+
+    #[tokio::test]
+    async fn detects_self_include() {
+        let url = Url::parse("https://example.com/repo.json").expect("url");
+        let mut repo = serde_json::from_str::<ImageRepo>(
+            r#"{ "name": "Ouroboros", "includes": ["https://example.com/repo.json"], "images": [] }"#,
+        )
+        .expect("image_repo");
+        let result = resolve_includes(&client(), &mut repo, Some(&url)).await;
+        assert!(matches!(result, Err(Error::IncludeCycle(cycle)) if cycle == url));
+    }
+
+    #[tokio::test]
+    async fn limits_include_depth() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let dir = dir.path();
+        let mut url = write_manifest(dir, "leaf", &[], &[]);
+        for depth in 0..MAX_INCLUDE_DEPTH {
+            url = write_manifest(dir, &format!("nested-{depth}"), &[&url], &[]);
+        }
+        let mut repo = serde_json::from_value::<ImageRepo>(
+            serde_json::json!({ "name": "Root", "includes": [url], "images": [] }),
+        )
+        .expect("image_repo");
+        let result = resolve_includes(&client(), &mut repo, None).await;
+        assert!(
+            matches!(result, Err(Error::IncludeDepthExceeded(leaf)) if leaf.as_str().ends_with("leaf.json"))
+        );
+    }
+
+    #[tokio::test]
+    async fn deduplicates_and_records_provenance() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let dir = dir.path();
+        let other = "blake3:51424140b70a97fb4095ede915f4b88506d15a7520850cbf2daf7a8a8d9f57de";
+        let prefixed = format!("sha256:{FERRIS}");
+        let first = write_manifest(dir, "first", &[], &[&prefixed, other]);
+        let second = write_manifest(dir, "second", &[&first], &[other, FERRIS]);
+        let root = write_manifest(dir, "root", &[&second, &first], &[FERRIS]);
+        let mut repo = crate::download_repo_manifest(&client(), &root)
+            .await
+            .expect("image_repo");
+
+        let resolved = resolve_includes(&client(), &mut repo, Some(&root)).await;
+        let resolved = resolved.expect("resolved");
+        assert_eq!(
+            resolved
+                .iter()
+                .map(|include| &include.url)
+                .collect::<Vec<_>>(),
+            [&second, &first]
+        );
+        let sources = repo
+            .images
+            .iter()
+            .map(|image| {
+                let source = image.included_from.as_ref();
+                (
+                    image.hash.as_str(),
+                    source.map(|source| source.name.as_str()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sources, [(FERRIS, None), (other, Some("second"))]);
+        assert_eq!(
+            repo.images[1].included_from.as_ref().expect("source").url,
+            second
+        );
+    }
+}
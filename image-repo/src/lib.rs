@@ -1,17 +1,79 @@
 #![deny(clippy::all, clippy::pedantic, rust_2018_idioms, clippy::unwrap_used)]
 
+use http::{HttpClient, HttpError};
+use schema::SchemaError;
+use signing::SigningError;
 use types::ImageRepo;
 use url::ParseError;
 
+#[cfg(feature = "decoding")]
+pub mod colors;
+pub mod crop;
+#[cfg(feature = "decoding")]
+pub mod decoding;
+pub mod deep_link;
+pub mod header;
+pub mod http;
+pub mod includes;
+pub mod lint;
+#[cfg(feature = "decoding")]
+pub mod local;
+#[cfg(feature = "decoding")]
+pub mod perceptual;
+#[cfg(feature = "decoding")]
+pub mod preview;
+pub mod schema;
+pub mod signing;
 pub mod types;
+#[cfg(feature = "decoding")]
+pub mod xmp;
 pub use reqwest::Url;
 
-pub use encoding::RegexError;
+pub use encoding::{safe_filename, Digest, DigestError, HashAlgorithm, Hasher, RegexError};
 
 #[derive(Debug)]
 pub enum Error {
     UrlParse(ParseError),
-    Request(reqwest::Error),
+    Request(HttpError),
+    /// Failed to read a `file://` URL.
+    Io(std::io::Error),
+    Schema(SchemaError),
+    Signing(SigningError),
+    /// A repository includes itself, directly or indirectly.
+    IncludeCycle(Url),
+    /// Includes are nested deeper than [`includes::MAX_INCLUDE_DEPTH`].
+    IncludeDepthExceeded(Url),
+    /// A manifest that wasn't read from disk refers to a local `file://` URL,
+    /// see [`ImageRepo::check_local_urls`].
+    LocalUrl(Url),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UrlParse(e) => write!(f, "Invalid URL: {e}"),
+            Error::Request(e) => write!(f, "HTTP failed: {e}"),
+            Error::Io(e) => write!(f, "Failed to read file: {e}"),
+            Error::Schema(e) => write!(f, "{e}"),
+            Error::Signing(e) => write!(f, "Invalid repository signature: {e}"),
+            Error::IncludeCycle(url) => write!(f, "Repository {url} includes itself."),
+            Error::IncludeDepthExceeded(url) => write!(
+                f,
+                "Repository {url} is nested more than {} includes deep.",
+                includes::MAX_INCLUDE_DEPTH
+            ),
+            Error::LocalUrl(url) => write!(
+                f,
+                "Only repositories on this computer can use local files, not {url}."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
 }
 
 impl From<ParseError> for Error {
@@ -20,22 +82,80 @@ impl From<ParseError> for Error {
     }
 }
 
+impl From<HttpError> for Error {
+    fn from(value: HttpError) -> Self {
+        Error::Request(value)
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
-        Error::Request(value)
+        Error::Request(value.into())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<SchemaError> for Error {
+    fn from(value: SchemaError) -> Self {
+        Error::Schema(value)
+    }
+}
+
+impl From<SigningError> for Error {
+    fn from(value: SigningError) -> Self {
+        Error::Signing(value)
     }
 }
 
-/// Download a repository JSON file from a URL and parse it to an [`types::ImageRepo`]
+/// Download a repository JSON file from a URL and parse it to an [`types::ImageRepo`],
+/// upgrading it to the current schema version. If the manifest is signed, the signature
+/// must be valid, but the publisher key is not checked against any trusted key.
+/// `file://` URLs are read from the local filesystem, anything else is requested
+/// with `client`. Only manifests read from the local filesystem may refer to
+/// `file://` URLs themselves.
 ///
 /// # Errors
 ///
 /// [`Error`]
-pub async fn download_repo_manifest<T>(url: T) -> Result<ImageRepo, Error>
+pub async fn download_repo_manifest<T>(client: &HttpClient, url: T) -> Result<ImageRepo, Error>
 where
     T: AsRef<str>,
 {
     let url = Url::parse(url.as_ref())?;
-    let repo = reqwest::get(url).await?.json::<ImageRepo>().await?;
+    let (repo, _) = download_signed_repo_manifest(client, &url).await?;
     Ok(repo)
 }
+
+/// Download a repository JSON file like [`download_repo_manifest`], also returning
+/// the publisher key it was signed with, if any, for checking against a trusted key.
+///
+/// # Errors
+///
+/// [`Error`]
+pub async fn download_signed_repo_manifest(
+    client: &HttpClient,
+    url: &Url,
+) -> Result<(ImageRepo, Option<String>), Error> {
+    let bytes = if url.scheme() == "file" {
+        let path = url.to_file_path().map_err(|()| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid file URL: {url}"),
+            )
+        })?;
+        tokio::fs::read(path).await?
+    } else {
+        let resp = client.get(url).await?.error_for_status()?;
+        client.read_body(resp).await?
+    };
+    let doc = serde_json::from_slice::<serde_json::Value>(&bytes).map_err(SchemaError::from)?;
+    let signed_by = signing::verify(&doc)?;
+    let repo = schema::from_value(doc)?;
+    repo.check_local_urls(url).map_err(Error::LocalUrl)?;
+    Ok((repo, signed_by))
+}
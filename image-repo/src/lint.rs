@@ -0,0 +1,376 @@
+//! Checks for mistakes in a manifest that can be spotted without downloading
+//! anything. Each problem is reported as a [`Diagnostic`] with the JSON path of
+//! the value at fault, e.g. `$.images[2].hash`, and how serious it is: errors
+//! make the manifest unusable or wrong, warnings are worth fixing.
+
+use crate::{
+    types::{ImageData, ImageRepo, SupportedFormat},
+    Digest, Url,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Images with more pixels than this are more than the app can reasonably decode.
+pub const MAX_PIXELS: u64 = 1 << 30;
+
+/// How serious a problem is, for lint diagnostics and for `repogen check`'s
+/// report on a published manifest's links.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    /// The manifest is broken or wrong, e.g. the app will reject it or a link
+    /// serves a different image.
+    Error,
+    /// The manifest works, but should probably be fixed.
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        })
+    }
+}
+
+/// What a [`Diagnostic`] is about, for tools that act on particular problems.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Rule {
+    EmptyName,
+    DuplicateHash,
+    DuplicateUrl,
+    MalformedHash,
+    InvalidDimensions,
+    InsecureUrl,
+    FormatMismatch,
+    MissingMetadata,
+    UpdateUrlMismatch,
+    InvalidCrop,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    /// JSON path of the value at fault.
+    pub path: String,
+    pub severity: Severity,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Collects diagnostics as the manifest is checked.
+#[derive(Default)]
+struct Linter {
+    diagnostics: Vec<Diagnostic>,
+    /// Where each URL was first seen, to spot URLs used twice.
+    urls: HashMap<Url, String>,
+}
+
+impl Linter {
+    fn report(&mut self, path: &str, severity: Severity, rule: Rule, message: String) {
+        self.diagnostics.push(Diagnostic {
+            path: path.to_string(),
+            severity,
+            rule,
+            message,
+        });
+    }
+
+    /// Warn about URLs that aren't HTTPS, which anyone on the way can tamper with.
+    fn check_https(&mut self, path: &str, url: &Url) {
+        if url.scheme() != "https" {
+            let message = format!("{url} isn't an HTTPS URL");
+            self.report(path, Severity::Warning, Rule::InsecureUrl, message);
+        }
+    }
+
+    /// Check an image URL, which must not be used by any other image or mirror.
+    fn check_image_url(&mut self, path: &str, url: &Url) {
+        self.check_https(path, url);
+        if let Some(first) = self.urls.get(url) {
+            let message = format!("{url} is also used at {first}");
+            self.report(path, Severity::Error, Rule::DuplicateUrl, message);
+        } else {
+            self.urls.insert(url.clone(), path.to_string());
+        }
+    }
+
+    fn check_dimensions(&mut self, path: &str, image: &ImageData) {
+        let (width, height) = (image.width, image.height);
+        if width == 0 || height == 0 {
+            let message = "Width and height must not be zero".to_string();
+            self.report(path, Severity::Error, Rule::InvalidDimensions, message);
+            return;
+        }
+        let max = image.format.max_dimension();
+        for (field, value) in [("width", width), ("height", height)] {
+            if value > max {
+                let message = format!("A {} image can be at most {max} pixels", image.format);
+                let path = format!("{path}.{field}");
+                self.report(&path, Severity::Error, Rule::InvalidDimensions, message);
+            }
+        }
+        if u64::from(width) * u64::from(height) > MAX_PIXELS {
+            let message = format!("{width}x{height} is too large to decode as a wallpaper");
+            self.report(path, Severity::Warning, Rule::InvalidDimensions, message);
+        }
+    }
+
+    /// Warn about a `format` the URL's file extension disagrees with, which is
+    /// usually a copy and paste mistake.
+    fn check_format(&mut self, path: &str, image: &ImageData) {
+        let extension = image
+            .url
+            .path_segments()
+            .and_then(Iterator::last)
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension);
+        let Some(extension) = extension else {
+            return;
+        };
+        match SupportedFormat::from_extension(extension) {
+            Some(format) if format != image.format => {
+                let message = format!("URL ends in .{extension} but format is {}", image.format);
+                let path = format!("{path}.format");
+                self.report(&path, Severity::Warning, Rule::FormatMismatch, message);
+            }
+            _ => {}
+        }
+    }
+
+    fn check_metadata(&mut self, path: &str, image: &ImageData) {
+        let missing = [
+            (
+                "title",
+                image.metadata.title.is_none(),
+                "the app shows it with the image",
+            ),
+            (
+                "credit",
+                image.metadata.credit.is_none(),
+                "most images must be credited when shown",
+            ),
+        ];
+        for (field, is_missing, why) in missing {
+            if is_missing {
+                let message = format!("No {field}; {why}");
+                let path = format!("{path}.metadata.{field}");
+                self.report(&path, Severity::Warning, Rule::MissingMetadata, message);
+            }
+        }
+    }
+}
+
+/// Check a manifest for mistakes. `source` is where the manifest was downloaded
+/// from, which its `updateUrl` must match for the app to accept it, or its
+/// `file://` URL if it was read from disk, in which case the `updateUrl` should
+/// at least end in the manifest's file name.
+#[must_use]
+pub fn lint(repo: &ImageRepo, source: Option<&Url>) -> Vec<Diagnostic> {
+    let mut linter = Linter::default();
+    if repo.name.trim().is_empty() {
+        let message = "Empty".to_string();
+        linter.report("$.name", Severity::Error, Rule::EmptyName, message);
+    }
+    if let Some(update_url) = &repo.update_url {
+        linter.check_https("$.updateUrl", update_url);
+        if update_url.path().ends_with('/') {
+            let message = "Should be the URL of the manifest file itself".to_string();
+            linter.report(
+                "$.updateUrl",
+                Severity::Error,
+                Rule::UpdateUrlMismatch,
+                message,
+            );
+        }
+        match source {
+            Some(source) if source.scheme() == "file" => {
+                fn file_name(url: &Url) -> Option<&str> {
+                    url.path_segments().and_then(Iterator::last)
+                }
+                if file_name(update_url) != file_name(source) {
+                    let message = format!(
+                        "Doesn't end in the manifest's file name, {}",
+                        file_name(source).unwrap_or_default()
+                    );
+                    linter.report(
+                        "$.updateUrl",
+                        Severity::Warning,
+                        Rule::UpdateUrlMismatch,
+                        message,
+                    );
+                }
+            }
+            Some(source) if source != update_url => {
+                let message = format!("Downloaded from {source}, so the app will reject it");
+                linter.report(
+                    "$.updateUrl",
+                    Severity::Error,
+                    Rule::UpdateUrlMismatch,
+                    message,
+                );
+            }
+            _ => {}
+        }
+    }
+    for (i, url) in repo.includes.iter().enumerate() {
+        linter.check_https(&format!("$.includes[{i}]"), url);
+    }
+    for (i, url) in repo.mirrors.iter().enumerate() {
+        linter.check_https(&format!("$.mirrors[{i}]"), url);
+    }
+
+    let mut hashes = HashMap::new();
+    for (i, image) in repo.images.iter().enumerate() {
+        let path = format!("$.images[{i}]");
+        let hash_path = format!("{path}.hash");
+        match Digest::parse(&image.hash) {
+            Ok(digest) => {
+                if let Some(first) = hashes.insert(digest, i) {
+                    let message = format!("Same hash as $.images[{first}]");
+                    linter.report(&hash_path, Severity::Error, Rule::DuplicateHash, message);
+                }
+            }
+            Err(e) => {
+                linter.report(
+                    &hash_path,
+                    Severity::Error,
+                    Rule::MalformedHash,
+                    e.to_string(),
+                );
+            }
+        }
+        linter.check_image_url(&format!("{path}.url"), &image.url);
+        for (j, mirror) in image.mirrors.iter().enumerate() {
+            linter.check_image_url(&format!("{path}.mirrors[{j}]"), mirror);
+        }
+        if let Some(thumbnail_url) = &image.thumbnail_url {
+            linter.check_https(&format!("{path}.thumbnailUrl"), thumbnail_url);
+        }
+        linter.check_dimensions(&path, image);
+        linter.check_format(&path, image);
+        linter.check_metadata(&path, image);
+        if let Err(e) = crate::crop::check(image) {
+            let path = format!("{path}.crops");
+            linter.report(&path, Severity::Error, Rule::InvalidCrop, e.to_string());
+        }
+    }
+    linter.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, Rule, Severity};
+    use crate::{types::ImageRepo, Url};
+
+    #[test]
+    fn reports_problems_with_paths() {
+        let repo = serde_json::from_str::<ImageRepo>(
+            r#"{
+                "name": " ",
+                "updateUrl": "http://example.com/jwst/",
+                "includes": ["http://example.com/more.json"],
+                "images": [
+                    {
+                        "url": "https://example.com/cliffs.png",
+                        "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                        "width": 14575,
+                        "height": 8441,
+                        "format": "png",
+                        "metadata": { "title": "Cosmic Cliffs", "credit": "NASA, ESA, CSA, and STScI" }
+                    },
+                    {
+                        "url": "https://example.com/ring.jpg",
+                        "hash": "sha256:b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                        "width": 70000,
+                        "height": 0,
+                        "format": "png",
+                        "mirrors": ["https://example.com/cliffs.png"],
+                        "metadata": { "title": "Southern Ring" }
+                    },
+                    {
+                        "url": "https://example.com/quintet.tif",
+                        "hash": "sha256:b645",
+                        "width": 40000,
+                        "height": 40000,
+                        "format": "tiff",
+                        "metadata": { "title": "Stephan's Quintet", "credit": "NASA" }
+                    }
+                ]
+            }"#,
+        )
+        .expect("repo");
+        let source = Url::parse("https://example.com/jwst/repo.json").expect("url");
+
+        let diagnostics = lint(&repo, Some(&source));
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.path.as_str(), d.severity, d.rule))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("$.name", Severity::Error, Rule::EmptyName),
+                ("$.updateUrl", Severity::Warning, Rule::InsecureUrl),
+                ("$.updateUrl", Severity::Error, Rule::UpdateUrlMismatch),
+                ("$.updateUrl", Severity::Error, Rule::UpdateUrlMismatch),
+                ("$.includes[0]", Severity::Warning, Rule::InsecureUrl),
+                ("$.images[1].hash", Severity::Error, Rule::DuplicateHash),
+                (
+                    "$.images[1].mirrors[0]",
+                    Severity::Error,
+                    Rule::DuplicateUrl
+                ),
+                ("$.images[1]", Severity::Error, Rule::InvalidDimensions),
+                (
+                    "$.images[1].format",
+                    Severity::Warning,
+                    Rule::FormatMismatch
+                ),
+                (
+                    "$.images[1].metadata.credit",
+                    Severity::Warning,
+                    Rule::MissingMetadata
+                ),
+                ("$.images[2].hash", Severity::Error, Rule::MalformedHash),
+                ("$.images[2]", Severity::Warning, Rule::InvalidDimensions),
+            ]
+        );
+        assert_eq!(
+            diagnostics[6].to_string(),
+            "$.images[1].mirrors[0]: https://example.com/cliffs.png is also used at $.images[0].url"
+        );
+        let json = serde_json::to_value(&diagnostics[0]).expect("json");
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["rule"], "emptyName");
+    }
+
+    #[test]
+    fn compares_update_url_with_local_file_name() {
+        let repo = serde_json::from_str::<ImageRepo>(
+            r#"{ "name": "JWST", "updateUrl": "https://example.com/jwst/repo.json", "images": [] }"#,
+        )
+        .expect("repo");
+        let same = Url::parse("file:///home/curator/jwst/repo.json").expect("url");
+        assert_eq!(lint(&repo, Some(&same)), []);
+
+        let other = Url::parse("file:///home/curator/jwst/draft.json").expect("url");
+        let diagnostics = lint(&repo, Some(&other));
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.path.as_str(), d.severity, d.rule))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [("$.updateUrl", Severity::Warning, Rule::UpdateUrlMismatch)]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! Repositories backed by a local directory of images rather than a manifest.
+//!
+//! The directory is scanned and every image in it that can be decoded becomes
+//! an [`ImageData`] with a `file://` URL. The resulting [`ImageRepo`] has the
+//! directory's own `file://` URL as its update URL, so updating it re-scans
+//! the directory.
+
+use crate::types::{ImageData, ImageRepo};
+use crate::Url;
+use serde_json::Map;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+fn invalid_path(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Not an absolute path: {}", path.display()),
+    )
+}
+
+/// List the regular files directly inside `dir`, sorted by path.
+fn files_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Scan `dir` for images and build an [`ImageRepo`] from them. Files that aren't
+/// images in a supported format, judging by their first
+/// [`crate::decoding::SNIFF_LEN`] bytes, or that can't be read are skipped.
+/// Subdirectories are not scanned.
+///
+/// # Errors
+///
+/// Fails if `dir` is not an absolute path or the directory can't be read.
+pub fn scan_directory(dir: &Path) -> io::Result<ImageRepo> {
+    let dir_url = Url::from_directory_path(dir).map_err(|()| invalid_path(dir))?;
+    let mut images = vec![];
+    for path in files_in(dir)? {
+        let url = Url::from_file_path(&path).map_err(|()| invalid_path(&path))?;
+        // only read files that look like images in full, e.g. not videos
+        if !matches!(crate::decoding::sniff_file(&path), Ok(Some(_))) {
+            continue;
+        }
+        let Ok(bytes) = fs::read(&path) else {
+            continue;
+        };
+        if let Ok(image) = ImageData::try_from((url, bytes)) {
+            images.push(image);
+        }
+    }
+
+    Ok(ImageRepo {
+        schema_version: crate::schema::CURRENT_SCHEMA_VERSION,
+        name: dir.file_name().map_or_else(
+            || dir.to_string_lossy().to_string(),
+            |name| name.to_string_lossy().to_string(),
+        ),
+        description: Some(format!("Local folder {}", dir.display())),
+        update_url: Some(dir_url),
+        images,
+        includes: vec![],
+        mirrors: vec![],
+        signature: None,
+        extra: Map::new(),
+    })
+}
+
+/// Get the most recent modification time of `dir` or any file directly inside it.
+/// Adding, removing or renaming a file updates the directory's own modification
+/// time; editing a file in place only updates that file's.
+///
+/// # Errors
+///
+/// Fails if the directory or its entries' metadata can't be read.
+pub fn last_modified(dir: &Path) -> io::Result<SystemTime> {
+    let mut latest = fs::metadata(dir)?.modified()?;
+    for path in files_in(dir)? {
+        latest = latest.max(fs::metadata(path)?.modified()?);
+    }
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scan_directory;
+    use std::fs;
+
+    #[test]
+    fn scans_images_in_directory() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        fs::write(dir.join("ferris.png"), include_bytes!("../ferris.png")).expect("write image");
+        fs::write(dir.join("notes.txt"), "not an image").expect("write text");
+        fs::write(dir.join("video.mp4"), vec![0; 1 << 20]).expect("write video");
+
+        let repo = scan_directory(dir).expect("image_repo");
+
+        assert_eq!(
+            Some(repo.name.as_str()),
+            dir.file_name().and_then(|name| name.to_str())
+        );
+        assert_eq!(repo.images.len(), 1);
+        assert_eq!(repo.images[0].url.scheme(), "file");
+        assert_eq!((repo.images[0].width, repo.images[0].height), (460, 307));
+    }
+}
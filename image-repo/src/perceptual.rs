@@ -0,0 +1,74 @@
+//! Perceptual hashing, to spot the same picture published more than once, e.g.
+//! recompressed as a JPEG or at a lower resolution. See [`PerceptualHash`].
+//!
+//! Difference hashes survive recompression and scaling, but not cropping: a crop
+//! of part of an image hashes like a different image.
+
+use crate::types::{ImgError, PerceptualHash, SupportedFormat};
+use image::{imageops::FilterType, DynamicImage};
+
+/// Decode an image and compute its perceptual hash.
+///
+/// # Errors
+///
+/// [`ImgError::UnsupportedFormat`] if the image can't be decoded (currently AVIF),
+/// or [`ImgError::DecodingFailed`] if decoding fails.
+pub fn hash(format: SupportedFormat, img_bytes: &[u8]) -> Result<PerceptualHash, ImgError> {
+    let img = crate::decoding::decode(format, img_bytes, "hashed")?;
+    Ok(dhash(&img))
+}
+
+/// Compute the difference hash of an image: scale it to 9x8 grayscale pixels and
+/// set a bit for each pixel that's brighter than the one to its right.
+#[must_use]
+pub fn dhash(img: &DynamicImage) -> PerceptualHash {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut bits = 0_u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            bits <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                bits |= 1;
+            }
+        }
+    }
+    PerceptualHash(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dhash, hash};
+    use crate::types::{PerceptualHash, SupportedFormat};
+    use image::{imageops::FilterType, ImageOutputFormat};
+    use std::io::Cursor;
+
+    #[test]
+    fn matches_recompressed_images() {
+        let ferris = include_bytes!("../ferris.png");
+        let original = hash(SupportedFormat::Png, ferris).expect("hash");
+
+        // the same picture as a small, heavily compressed JPEG
+        let img = image::load_from_memory(ferris).expect("png");
+        let mut jpeg = Cursor::new(vec![]);
+        img.resize(200, 200, FilterType::Triangle)
+            .to_rgb8()
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(40))
+            .expect("jpeg");
+        let recompressed = hash(SupportedFormat::Jpg, jpeg.get_ref()).expect("hash");
+        assert!(
+            original.is_near_duplicate(recompressed),
+            "{original} {recompressed}"
+        );
+
+        let flipped = dhash(&img.flipv());
+        assert!(!original.is_near_duplicate(flipped), "{original} {flipped}");
+
+        let json = serde_json::to_value(original).expect("json");
+        assert_eq!(json, original.to_string());
+        assert_eq!(
+            serde_json::from_value::<PerceptualHash>(json).expect("hash"),
+            original
+        );
+        assert!(serde_json::from_str::<PerceptualHash>(r#""abc""#).is_err());
+    }
+}
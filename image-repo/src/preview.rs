@@ -0,0 +1,182 @@
+//! Small previews of images for the UI to show before the full file has
+//! downloaded: a JPEG thumbnail, and a [BlurHash](https://blurha.sh) placeholder
+//! that's compact enough to embed in the manifest and shown until even the
+//! thumbnail has loaded.
+
+use crate::types::{ImgError, SupportedFormat};
+use image::{codecs::jpeg::JpegEncoder, RgbImage};
+use std::f64::consts::PI;
+
+/// Longest side of a thumbnail in pixels.
+pub const THUMBNAIL_SIZE: u32 = 480;
+
+/// JPEG quality of thumbnails, out of 100.
+const THUMBNAIL_QUALITY: u8 = 80;
+
+/// Characters of the base 83 encoding used by blur hashes.
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+pub struct Preview {
+    /// JPEG thumbnail, at most [`THUMBNAIL_SIZE`] pixels on its longest side.
+    pub thumbnail: Vec<u8>,
+    pub blur_hash: String,
+}
+
+/// Generate the thumbnail and blur hash of an image. This decodes the whole image.
+///
+/// # Errors
+///
+/// [`ImgError::UnsupportedFormat`] if the image can't be decoded (currently AVIF),
+/// or [`ImgError::DecodingFailed`] if decoding or encoding the thumbnail fails.
+pub fn generate(format: SupportedFormat, img_bytes: &[u8]) -> Result<Preview, ImgError> {
+    let mut img = crate::decoding::decode(format, img_bytes, "previewed")?;
+    if img.width() > THUMBNAIL_SIZE || img.height() > THUMBNAIL_SIZE {
+        img = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    }
+    let thumbnail = img.to_rgb8();
+    let mut jpeg = vec![];
+    JpegEncoder::new_with_quality(&mut jpeg, THUMBNAIL_QUALITY).encode_image(&thumbnail)?;
+    // 4 components along the long side, 3 along the short one
+    let components = if thumbnail.width() >= thumbnail.height() {
+        (4, 3)
+    } else {
+        (3, 4)
+    };
+    Ok(Preview {
+        thumbnail: jpeg,
+        blur_hash: blur_hash(&thumbnail, components),
+    })
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = f64::from(value) / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+fn sign_pow(value: f64, exp: f64) -> f64 {
+    value.abs().powf(exp).copysign(value)
+}
+
+fn base83(value: u32, len: u32, out: &mut String) {
+    for i in (0..len).rev() {
+        let digit = (value / 83_u32.pow(i)) % 83;
+        out.push(char::from(BASE83[digit as usize]));
+    }
+}
+
+/// Encode an image as a blur hash with `components` (x, y) cosine components,
+/// each between 1 and 9. Follows the reference implementation, so the result
+/// can be decoded by any `BlurHash` library.
+#[must_use]
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn blur_hash(img: &RgbImage, (x_components, y_components): (u32, u32)) -> String {
+    let x_components = x_components.clamp(1, 9);
+    let y_components = y_components.clamp(1, 9);
+    let (width, height) = img.dimensions();
+    let pixels = f64::from(width) * f64::from(height);
+    // cos(pi * i * x / width) for each component and column, and likewise for rows
+    let basis = |components: u32, len: u32| {
+        (0..components)
+            .map(|i| {
+                (0..len)
+                    .map(|x| (PI * f64::from(i) * f64::from(x) / f64::from(len)).cos())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let x_basis = basis(x_components, width);
+    let y_basis = basis(y_components, height);
+
+    let mut factors = vec![];
+    for (j, y_cos) in y_basis.iter().enumerate() {
+        for (i, x_cos) in x_basis.iter().enumerate() {
+            let mut factor = [0.0; 3];
+            for (x, y, pixel) in img.enumerate_pixels() {
+                let weight = x_cos[x as usize] * y_cos[y as usize];
+                for (sum, &channel) in factor.iter_mut().zip(&pixel.0) {
+                    *sum += weight * srgb_to_linear(channel);
+                }
+            }
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            factors.push(factor.map(|sum| sum * normalisation / pixels));
+        }
+    }
+
+    let mut hash = String::new();
+    base83((x_components - 1) + (y_components - 1) * 9, 1, &mut hash);
+    // there is always at least the DC component
+    let (dc, ac) = (factors[0], &factors[1..]);
+    let max_value = ac.iter().flatten().fold(0.0_f64, |max, v| max.max(v.abs()));
+    let max_value = if ac.is_empty() {
+        base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let quantised = (max_value * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        base83(quantised, 1, &mut hash);
+        f64::from(quantised + 1) / 166.0
+    };
+    let [r, g, b] = dc.map(linear_to_srgb);
+    base83((r << 16) + (g << 8) + b, 4, &mut hash);
+    for factor in ac {
+        let [r, g, b] = factor.map(|v| {
+            (sign_pow(v / max_value, 0.5) * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        base83(r * 19 * 19 + g * 19 + b, 2, &mut hash);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blur_hash, generate};
+    use crate::types::SupportedFormat;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn encodes_blur_hash() {
+        // a plain black image, the placeholder from the BlurHash documentation
+        let black = RgbImage::new(32, 24);
+        assert_eq!(blur_hash(&black, (4, 3)), "L00000fQfQfQfQfQfQfQfQfQfQfQ");
+
+        let white = RgbImage::from_pixel(32, 24, Rgb([255, 255, 255]));
+        assert_eq!(blur_hash(&white, (1, 1)), "00TSUA");
+
+        // size flag, maximum AC value, DC colour, then 2 characters per AC component
+        let gradient = RgbImage::from_fn(32, 24, |x, _| Rgb([u8::try_from(x * 8).expect("u8"); 3]));
+        let hash = blur_hash(&gradient, (4, 3));
+        assert_eq!(hash.len(), 1 + 1 + 4 + 2 * 11);
+        assert_ne!(&hash[6..8], "fQ");
+    }
+
+    #[test]
+    fn generates_thumbnail() {
+        let preview =
+            generate(SupportedFormat::Png, include_bytes!("../ferris.png")).expect("preview");
+        let thumbnail = image::load_from_memory(&preview.thumbnail).expect("jpeg");
+        // smaller images aren't scaled up
+        assert_eq!((thumbnail.width(), thumbnail.height()), (460, 307));
+        assert!(preview.blur_hash.starts_with('L'));
+    }
+}
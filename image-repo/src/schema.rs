@@ -0,0 +1,193 @@
+//! Versioning for the repository manifest format.
+//!
+//! Manifests declare the format they were written against via a top-level
+//! `schemaVersion` field. Documents without one predate versioning and are
+//! treated as version `0`. Older documents are upgraded step by step to
+//! [`CURRENT_SCHEMA_VERSION`] before being deserialized into an [`ImageRepo`];
+//! documents newer than [`CURRENT_SCHEMA_VERSION`] are rejected, since we can't
+//! know what their fields mean.
+
+use crate::types::ImageRepo;
+use serde_json::{Map, Value};
+
+/// The manifest schema version this build understands and writes.
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// Name of the JSON field holding the schema version.
+const SCHEMA_VERSION_FIELD: &str = "schemaVersion";
+
+#[derive(Debug)]
+pub enum SchemaError {
+    /// Manifest is not valid JSON, or does not match the schema.
+    Json(serde_json::Error),
+    /// Manifest's top level is not a JSON object.
+    NotAnObject,
+    /// `schemaVersion` is present but isn't a non-negative integer.
+    InvalidVersion(Value),
+    /// Manifest was written for a newer schema than this build understands.
+    /// Contains the manifest's version.
+    UnsupportedVersion(u32),
+    /// No migration upgrades manifests from this schema version, which is a bug
+    /// in this build rather than in the manifest. Contains the version.
+    MissingMigration(u32),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Json(e) => write!(f, "Invalid repository manifest: {e}"),
+            SchemaError::NotAnObject => write!(f, "Repository manifest must be a JSON object."),
+            SchemaError::InvalidVersion(value) => {
+                write!(f, "Invalid {SCHEMA_VERSION_FIELD}: {value}")
+            }
+            SchemaError::UnsupportedVersion(version) => write!(
+                f,
+                "Repository manifest uses schema version {version}, but this version of the app only supports up to {CURRENT_SCHEMA_VERSION}. Please update the app."
+            ),
+            SchemaError::MissingMigration(version) => write!(
+                f,
+                "Can't upgrade repository manifest from schema version {version} to {CURRENT_SCHEMA_VERSION}."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<serde_json::Error> for SchemaError {
+    fn from(value: serde_json::Error) -> Self {
+        SchemaError::Json(value)
+    }
+}
+
+/// Read the declared schema version of a manifest document.
+fn schema_version(doc: &Map<String, Value>) -> Result<u32, SchemaError> {
+    match doc.get(SCHEMA_VERSION_FIELD) {
+        None => Ok(0),
+        Some(value) => value
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| SchemaError::InvalidVersion(value.clone())),
+    }
+}
+
+/// Version 0 (unversioned) to version 1. The shape is unchanged; version 1
+/// only introduces the `schemaVersion` field itself.
+fn migrate_v0_to_v1(doc: &mut Map<String, Value>) {
+    doc.insert(SCHEMA_VERSION_FIELD.into(), Value::from(1));
+}
+
+/// Upgrade a manifest document from `version` to the next version.
+fn migrate_from(doc: &mut Map<String, Value>, version: u32) -> Result<(), SchemaError> {
+    match version {
+        0 => migrate_v0_to_v1(doc),
+        _ => return Err(SchemaError::MissingMigration(version)),
+    }
+    Ok(())
+}
+
+/// Upgrade a manifest document to [`CURRENT_SCHEMA_VERSION`] in place.
+/// Fields the migrations don't know about are left untouched.
+///
+/// # Errors
+///
+/// [`SchemaError`]
+pub fn migrate(doc: &mut Value) -> Result<(), SchemaError> {
+    let Some(doc) = doc.as_object_mut() else {
+        return Err(SchemaError::NotAnObject);
+    };
+
+    let mut version = schema_version(doc)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion(version));
+    }
+
+    while version < CURRENT_SCHEMA_VERSION {
+        migrate_from(doc, version)?;
+        version = schema_version(doc)?;
+    }
+
+    Ok(())
+}
+
+/// Parse a manifest from JSON bytes, upgrading it to the current schema.
+///
+/// # Errors
+///
+/// [`SchemaError`]
+pub fn from_slice(bytes: &[u8]) -> Result<ImageRepo, SchemaError> {
+    let doc = serde_json::from_slice::<Value>(bytes)?;
+    from_value(doc)
+}
+
+/// Parse a manifest from a JSON [`Value`], upgrading it to the current schema.
+///
+/// # Errors
+///
+/// [`SchemaError`]
+pub fn from_value(mut doc: Value) -> Result<ImageRepo, SchemaError> {
+    migrate(&mut doc)?;
+    Ok(serde_json::from_value(doc)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_slice, migrate_from, SchemaError, CURRENT_SCHEMA_VERSION};
+    use serde_json::Map;
+
+    #[test]
+    fn migrates_unversioned_manifest() {
+        let repo = from_slice(br#"{ "name": "Legacy", "images": [] }"#).expect("image_repo");
+        assert_eq!(repo.schema_version, CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn rejects_newer_manifest() {
+        let json = format!(
+            r#"{{ "schemaVersion": {}, "name": "Future", "images": [] }}"#,
+            CURRENT_SCHEMA_VERSION + 1
+        );
+        let result = from_slice(json.as_bytes());
+        assert!(matches!(
+            result,
+            Err(SchemaError::UnsupportedVersion(v)) if v == CURRENT_SCHEMA_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn reports_missing_migration() {
+        let result = migrate_from(&mut Map::new(), CURRENT_SCHEMA_VERSION);
+        assert!(matches!(
+            result,
+            Err(SchemaError::MissingMigration(v)) if v == CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    #[test]
+    fn preserves_unknown_fields() {
+        let json = r#"{
+  "schemaVersion": 1,
+  "name": "Repo",
+  "images": [
+    {
+      "url": "https://example.com/a.png",
+      "hash": "abc",
+      "width": 1,
+      "height": 1,
+      "format": "png",
+      "curatorNote": "keep me"
+    }
+  ],
+  "homepage": "https://example.com"
+}"#;
+        let repo = from_slice(json.as_bytes()).expect("image_repo");
+        assert_eq!(
+            serde_json::to_string_pretty(&repo).expect("json string"),
+            json
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Ed25519 signatures over repository manifests.
+//!
+//! A signed manifest carries a top-level `signature` object (see
+//! [`crate::types::ManifestSignature`]). The signature covers the manifest's
+//! canonical bytes: the whole JSON document *except* the `signature` field,
+//! with object keys sorted and no insignificant whitespace. This way a
+//! manifest can be reformatted or have its keys reordered without
+//! invalidating the signature, but any change to its content will.
+
+use crate::types::ManifestSignature;
+use serde_json::{Map, Value};
+
+pub use encoding::{generate_signing_key, SignatureError};
+
+/// Name of the top-level JSON field holding the signature.
+const SIGNATURE_FIELD: &str = "signature";
+
+/// The only signature algorithm currently supported.
+pub const ED25519: &str = "ed25519";
+
+#[derive(Debug)]
+pub enum SigningError {
+    /// Manifest's top level is not a JSON object.
+    NotAnObject,
+    /// The `signature` field doesn't have the expected shape.
+    Malformed(serde_json::Error),
+    /// The signature uses an algorithm we don't support.
+    UnsupportedAlgorithm(String),
+    /// Signing or verification failed.
+    Signature(SignatureError),
+}
+
+impl std::fmt::Display for SigningError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SigningError::NotAnObject => write!(f, "Repository manifest must be a JSON object."),
+            SigningError::Malformed(e) => write!(f, "Malformed manifest signature: {e}"),
+            SigningError::UnsupportedAlgorithm(alg) => {
+                write!(f, "Unsupported signature algorithm: {alg}")
+            }
+            SigningError::Signature(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SigningError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<SignatureError> for SigningError {
+    fn from(value: SignatureError) -> Self {
+        SigningError::Signature(value)
+    }
+}
+
+/// Recursively rebuild the value with object keys in sorted order.
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            Value::Object(
+                keys.into_iter()
+                    .map(|key| (key.clone(), sorted(&map[key])))
+                    .collect::<Map<_, _>>(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Get the canonical bytes of a manifest document, which are what gets signed.
+///
+/// # Errors
+///
+/// [`SigningError::NotAnObject`]
+pub fn canonical_bytes(doc: &Value) -> Result<Vec<u8>, SigningError> {
+    let Some(map) = doc.as_object() else {
+        return Err(SigningError::NotAnObject);
+    };
+    let mut map = map.clone();
+    map.remove(SIGNATURE_FIELD);
+    serde_json::to_vec(&sorted(&Value::Object(map))).map_err(SigningError::Malformed)
+}
+
+/// Sign the manifest document in place with the Ed25519 key in the given
+/// PKCS#8 document, replacing any existing signature.
+///
+/// # Errors
+///
+/// [`SigningError`]
+pub fn sign(doc: &mut Value, pkcs8: &[u8]) -> Result<(), SigningError> {
+    let bytes = canonical_bytes(doc)?;
+    let (public_key, value) = encoding::sign(pkcs8, &bytes)?;
+    let signature = ManifestSignature {
+        algorithm: ED25519.into(),
+        public_key,
+        value,
+    };
+    let Some(map) = doc.as_object_mut() else {
+        return Err(SigningError::NotAnObject);
+    };
+    map.insert(
+        SIGNATURE_FIELD.into(),
+        serde_json::to_value(signature).map_err(SigningError::Malformed)?,
+    );
+    Ok(())
+}
+
+/// Verify the manifest document's signature, if it has one.
+///
+/// Returns the hex-encoded public key the manifest was signed with, or
+/// `None` if the manifest is unsigned. Note that a valid signature only
+/// proves the holder of *that* key signed the manifest; callers must decide
+/// whether they trust the key.
+///
+/// # Errors
+///
+/// [`SigningError`]
+pub fn verify(doc: &Value) -> Result<Option<String>, SigningError> {
+    let Some(signature) = doc.get(SIGNATURE_FIELD) else {
+        return Ok(None);
+    };
+    let signature = serde_json::from_value::<ManifestSignature>(signature.clone())
+        .map_err(SigningError::Malformed)?;
+    if signature.algorithm != ED25519 {
+        return Err(SigningError::UnsupportedAlgorithm(signature.algorithm));
+    }
+
+    let bytes = canonical_bytes(doc)?;
+    encoding::verify_signature(&signature.public_key, &bytes, &signature.value)?;
+    Ok(Some(signature.public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, verify, SigningError};
+    use serde_json::Value;
+
+    const MANIFEST: &str = r#"{
+  "schemaVersion": 1,
+  "name": "Signed Repo",
+  "images": []
+}"#;
+
+    fn signed_manifest() -> Value {
+        let key = encoding::generate_signing_key().expect("key");
+        let mut doc = serde_json::from_str::<Value>(MANIFEST).expect("json");
+        sign(&mut doc, &key).expect("signature");
+        doc
+    }
+
+    #[test]
+    fn unsigned_manifest_has_no_key() {
+        let doc = serde_json::from_str::<Value>(MANIFEST).expect("json");
+        assert!(matches!(verify(&doc), Ok(None)));
+    }
+
+    #[test]
+    fn verifies_regardless_of_formatting() {
+        let doc = signed_manifest();
+        let reformatted = serde_json::to_string(&doc).expect("json");
+        let doc = serde_json::from_str::<Value>(&reformatted).expect("json");
+        assert!(matches!(verify(&doc), Ok(Some(_))));
+    }
+
+    #[test]
+    fn rejects_tampered_manifest() {
+        let mut doc = signed_manifest();
+        doc["name"] = Value::from("Evil Repo");
+        assert!(matches!(verify(&doc), Err(SigningError::Signature(_))));
+    }
+}
@@ -1,11 +1,63 @@
+use encoding::{Digest, DigestError};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum SupportedFormat {
+    #[serde(alias = "jpeg")]
     Jpg,
     Png,
+    #[serde(alias = "tif")]
+    Tiff,
+    Webp,
+    Avif,
+}
+
+impl SupportedFormat {
+    /// The format a file extension stands for, ignoring case.
+    #[must_use]
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(SupportedFormat::Jpg),
+            "png" => Some(SupportedFormat::Png),
+            "tif" | "tiff" => Some(SupportedFormat::Tiff),
+            "webp" => Some(SupportedFormat::Webp),
+            "avif" => Some(SupportedFormat::Avif),
+            _ => None,
+        }
+    }
+
+    /// The largest width or height the format can store, in pixels.
+    #[must_use]
+    pub fn max_dimension(self) -> u32 {
+        match self {
+            SupportedFormat::Jpg => u32::from(u16::MAX),
+            SupportedFormat::Png => i32::MAX.unsigned_abs(),
+            SupportedFormat::Webp => 16_383,
+            SupportedFormat::Tiff | SupportedFormat::Avif => u32::MAX,
+        }
+    }
+
+    /// Whether every desktop environment can display this format as a wallpaper.
+    /// Other formats need converting first.
+    #[must_use]
+    pub fn is_desktop_compatible(self) -> bool {
+        matches!(self, SupportedFormat::Jpg | SupportedFormat::Png)
+    }
+
+    /// The MIME type servers should send for this format.
+    #[must_use]
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            SupportedFormat::Jpg => "image/jpeg",
+            SupportedFormat::Png => "image/png",
+            SupportedFormat::Tiff => "image/tiff",
+            SupportedFormat::Webp => "image/webp",
+            SupportedFormat::Avif => "image/avif",
+        }
+    }
 }
 
 impl std::fmt::Display for SupportedFormat {
@@ -16,6 +68,9 @@ impl std::fmt::Display for SupportedFormat {
             match self {
                 SupportedFormat::Jpg => "jpg",
                 SupportedFormat::Png => "png",
+                SupportedFormat::Tiff => "tiff",
+                SupportedFormat::Webp => "webp",
+                SupportedFormat::Avif => "avif",
             }
         )
     }
@@ -29,6 +84,251 @@ pub struct ImageData {
     pub width: u32,
     pub height: u32,
     pub format: SupportedFormat,
+    /// Size of the image file in bytes. Optional, but lets the app estimate
+    /// disk usage before downloading and reject downloads of the wrong size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Alternative URLs serving the same file, tried in order if [`ImageData::url`]
+    /// fails. Since the hash identifies the image, bytes from any mirror are
+    /// accepted as long as they pass [`ImageData::verify_checksum`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Url>,
+    /// Descriptive metadata like title and credit. With the `decoding` feature,
+    /// this is filled in from AVM/XMP metadata embedded in the image, if any.
+    #[serde(default, skip_serializing_if = "ImageMetadata::is_empty")]
+    pub metadata: ImageMetadata,
+    /// The most important point of the image, kept in view when cropping it to a
+    /// display's aspect ratio, see [`crate::crop`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+    /// Curated crops of the image, e.g. a portrait crop for vertical monitors.
+    /// When fitting to a display, the one closest to its aspect ratio is used.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub crops: Vec<CropRegion>,
+    /// A small JPEG preview of the image, for the UI to show before the full
+    /// image has downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<Url>,
+    /// A [BlurHash](https://blurha.sh) placeholder, shown until the thumbnail loads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blur_hash: Option<String>,
+    /// Dominant colours and luminance of the image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colors: Option<ColorStats>,
+    /// Perceptual hash of the image, for spotting the same picture recompressed
+    /// or rescaled, which has a different [`ImageData::hash`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perceptual_hash: Option<PerceptualHash>,
+    /// The included repository this image came from, set when resolving
+    /// [`ImageRepo::includes`]. `None` for the repository's own images.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub included_from: Option<IncludeSource>,
+    /// Fields not known to this version of the schema. Kept so that
+    /// round-tripping a manifest doesn't silently drop them.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Descriptive metadata about an image. Field names follow the
+/// [Astronomy Visualization Metadata](https://www.virtualastronomy.org/avm_metadata.php)
+/// standard where there is an equivalent.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Credit line to display with the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit: Option<String>,
+    /// Names of the objects pictured, e.g. `NGC 3324`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subjects: Vec<String>,
+    /// Sky coordinates of the image's reference pixel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coordinates: Option<Coordinates>,
+    /// Spectral bands of the data shown, e.g. `Infrared`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spectral_bands: Vec<String>,
+}
+
+impl ImageMetadata {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self == &ImageMetadata::default()
+    }
+}
+
+/// Equatorial sky coordinates, in degrees.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Coordinates {
+    /// Right ascension.
+    pub ra: f64,
+    /// Declination.
+    pub dec: f64,
+}
+
+/// A point in an image, as fractions of its width and height measured from the
+/// top left corner, so `{ "x": 0.5, "y": 0.5 }` is the centre.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FocalPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A rectangle in an image, in pixels from the top left corner.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A named crop of an image chosen by the curator.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CropRegion {
+    /// Name describing the crop, e.g. `portrait` or `cliffs`.
+    pub name: String,
+    #[serde(flatten)]
+    pub rect: Rect,
+}
+
+/// An sRGB colour, written as a `#rrggbb` hex string.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+impl From<Color> for String {
+    fn from(value: Color) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid colour {value:?}, expected #rrggbb");
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .ok_or_else(invalid)?;
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+                .ok_or_else(invalid)
+        };
+        Ok(Color([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+/// Mean luminance below which an image counts as dark.
+pub const DARK_LUMINANCE: f64 = 0.25;
+
+/// Whether an image is predominantly dark or light, see [`ColorStats::brightness`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Brightness {
+    Dark,
+    Light,
+}
+
+/// Colour statistics of an image, for picking images that match a theme or the
+/// time of day, and for theming the UI around the current wallpaper.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ColorStats {
+    /// The image's dominant colours, most common first.
+    pub palette: Vec<Color>,
+    /// Mean luma of the image, from 0 for black to 1 for white.
+    pub luminance: f64,
+}
+
+impl ColorStats {
+    #[must_use]
+    pub fn brightness(&self) -> Brightness {
+        if self.luminance < DARK_LUMINANCE {
+            Brightness::Dark
+        } else {
+            Brightness::Light
+        }
+    }
+}
+
+/// Largest [`PerceptualHash::distance`] at which two images count as the same
+/// picture.
+pub const NEAR_DUPLICATE_DISTANCE: u32 = 10;
+
+/// A 64-bit difference hash (dHash) of an image, written as 16 hex digits. Each
+/// bit says whether a pixel of a 9x8 grayscale thumbnail is brighter than its
+/// right neighbour, so recompressing or rescaling an image barely changes it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct PerceptualHash(pub u64);
+
+impl PerceptualHash {
+    /// Number of bits that differ between two hashes, from 0 for the same
+    /// picture to 64.
+    #[must_use]
+    pub fn distance(self, other: PerceptualHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    /// Whether two hashes are within [`NEAR_DUPLICATE_DISTANCE`] of each other.
+    #[must_use]
+    pub fn is_near_duplicate(self, other: PerceptualHash) -> bool {
+        self.distance(other) <= NEAR_DUPLICATE_DISTANCE
+    }
+}
+
+impl std::fmt::Display for PerceptualHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl From<PerceptualHash> for String {
+    fn from(value: PerceptualHash) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for PerceptualHash {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.len() != 16 {
+            return Err(format!(
+                "Invalid perceptual hash {value:?}, expected 16 hex digits"
+            ));
+        }
+        u64::from_str_radix(&value, 16)
+            .map(PerceptualHash)
+            .map_err(|e| format!("Invalid perceptual hash {value:?}: {e}"))
+    }
+}
+
+/// Provenance of an image pulled in from an included repository.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IncludeSource {
+    /// Name of the included repository.
+    pub name: String,
+    /// URL of the included repository's manifest.
+    pub url: Url,
 }
 
 #[derive(Debug)]
@@ -37,17 +337,25 @@ pub enum ChecksumError {
     /// is the internal checksum first, then the checksum
     /// of the data passed by the caller.
     NoMatch((String, String)),
+    /// The [`ImageData::hash`] property is not a valid digest.
+    InvalidDigest(DigestError),
 }
 
 impl std::fmt::Display for ChecksumError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (expected, received) = match self {
-            ChecksumError::NoMatch((expected, received)) => (expected, received),
-        };
-        write!(
-            f,
-            "Checksums do not match. Expected {expected} but got {received}",
-        )
+        match self {
+            ChecksumError::NoMatch((expected, received)) => write!(
+                f,
+                "Checksums do not match. Expected {expected} but got {received}",
+            ),
+            ChecksumError::InvalidDigest(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<DigestError> for ChecksumError {
+    fn from(value: DigestError) -> Self {
+        ChecksumError::InvalidDigest(value)
     }
 }
 
@@ -58,40 +366,100 @@ impl std::error::Error for ChecksumError {
 }
 
 impl ImageData {
-    /// Given a byte slice, get a SHA256 checksum of it and
-    /// verify that it matches the [`ImageData::hash`] property stored
-    /// in this [`ImageData`].
+    /// Parse the [`ImageData::hash`] property. This is either a self-describing
+    /// digest like `sha512:…`, or a bare hex string, which is a legacy SHA-256 digest.
+    ///
+    /// # Errors
+    ///
+    /// [`ChecksumError::InvalidDigest`]
+    pub fn digest(&self) -> Result<Digest, ChecksumError> {
+        Ok(Digest::parse(&self.hash)?)
+    }
+
+    /// Given a byte slice, hash it with the algorithm specified by
+    /// the [`ImageData::hash`] property and verify that it matches.
     ///
     /// # Errors
     ///
     /// [`ChecksumError`]
     pub fn verify_checksum(&self, img_bytes: &[u8]) -> Result<(), ChecksumError> {
-        let hash = encoding::checksum_string(img_bytes);
-        if hash == self.hash {
+        let digest = self.digest()?;
+        if digest.matches(img_bytes) {
             Ok(())
         } else {
-            Err(ChecksumError::NoMatch((self.hash.clone(), hash)))
+            let received = Digest::compute(digest.algorithm, img_bytes);
+            Err(ChecksumError::NoMatch((
+                self.hash.clone(),
+                received.to_string(),
+            )))
         }
     }
 
     /// Deterministically generate the filepath tail for the given
     /// [`ImageData`]. This path should be appended to the storage
-    /// root under `$XDG_CACHE_HOME` before storing.
-    #[must_use]
-    pub fn to_file_name(&self) -> String {
-        format!("{}.{}", self.hash, self.format)
+    /// root under `$XDG_CACHE_HOME` before storing. The file name
+    /// includes the hash algorithm so that digests from different
+    /// algorithms can never collide.
+    ///
+    /// # Errors
+    ///
+    /// [`ChecksumError::InvalidDigest`]
+    pub fn to_file_name(&self) -> Result<String, ChecksumError> {
+        let digest = self.digest()?;
+        Ok(format!(
+            "{}-{}.{}",
+            digest.algorithm, digest.hex, self.format
+        ))
+    }
+
+    /// All URLs this image can be downloaded from, in the order they should be tried:
+    /// [`ImageData::url`] first, then each of [`ImageData::mirrors`].
+    pub fn urls(&self) -> impl Iterator<Item = &Url> {
+        std::iter::once(&self.url).chain(self.mirrors.iter())
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestSignature {
+    /// Signature algorithm, currently always `ed25519`.
+    pub algorithm: String,
+    /// Hex-encoded public key of the publisher.
+    pub public_key: String,
+    /// Hex-encoded signature over the manifest's canonical bytes.
+    pub value: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageRepo {
+    /// Manifest schema version, see [`crate::schema`]. Missing in manifests
+    /// written before versioning was introduced, which are version `0`.
+    #[serde(default)]
+    pub schema_version: u32,
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub update_url: Option<Url>,
     pub images: Vec<ImageData>,
+    /// URLs of other repository manifests whose images are part of this
+    /// repository, see [`crate::includes`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<Url>,
+    /// Base URLs that mirror every image in the repository. An image is expected
+    /// at the base URL joined with the last path segment of [`ImageData::url`],
+    /// e.g. `https://mirror.example.com/jwst/` mirrors `https://stsci-opo.org/STScI-01G.png`
+    /// as `https://mirror.example.com/jwst/STScI-01G.png`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Url>,
+    /// Publisher signature over the manifest, see [`crate::signing`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ManifestSignature>,
+    /// Fields not known to this version of the schema. Kept so that
+    /// round-tripping a manifest doesn't silently drop them.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl ImageRepo {
@@ -102,7 +470,7 @@ impl ImageRepo {
     /// Errors if regex fails to compile (should never happen).
     pub fn to_file_name(&self) -> Result<String, encoding::RegexError> {
         let raw_filename = [
-            self.name.to_string(),
+            self.name.clone(),
             self.update_url
                 .clone()
                 .map_or_else(String::new, |url| url.to_string()),
@@ -110,6 +478,61 @@ impl ImageRepo {
         .join(",");
         Ok(format!("{}.json", encoding::safe_filename(raw_filename)?))
     }
+
+    /// Get the repository's images with the repository-wide base [`ImageRepo::mirrors`]
+    /// appended to each image's own [`ImageData::mirrors`]. Use these when downloading
+    /// images so that every known mirror gets tried.
+    #[must_use]
+    pub fn images_with_mirrors(&self) -> Vec<ImageData> {
+        self.images
+            .iter()
+            .map(|image| {
+                let mut image = image.clone();
+                let file_name = image
+                    .url
+                    .path_segments()
+                    .and_then(Iterator::last)
+                    .filter(|segment| !segment.is_empty());
+                if let Some(file_name) = file_name {
+                    let base_mirrors = self
+                        .mirrors
+                        .iter()
+                        .filter_map(|base| base.join(file_name).ok())
+                        .filter(|url| !image.urls().any(|existing| existing == url))
+                        .collect::<Vec<_>>();
+                    image.mirrors.extend(base_mirrors);
+                }
+                image
+            })
+            .collect()
+    }
+
+    /// Every URL the manifest refers to: its images, their mirrors and
+    /// thumbnails, the repository-wide mirrors and the included manifests.
+    pub fn referenced_urls(&self) -> impl Iterator<Item = &Url> {
+        self.images
+            .iter()
+            .flat_map(|image| image.urls().chain(image.thumbnail_url.as_ref()))
+            .chain(self.mirrors.iter())
+            .chain(self.includes.iter())
+    }
+
+    /// Check that the manifest only points at local `file://` URLs if it was itself
+    /// read from disk, i.e. `manifest_url` is a `file://` URL too. Otherwise a
+    /// repository on the web could make the app read any file on the computer.
+    ///
+    /// # Errors
+    ///
+    /// The first `file://` URL in a manifest that wasn't read from disk.
+    pub fn check_local_urls(&self, manifest_url: &Url) -> Result<(), Url> {
+        if manifest_url.scheme() == "file" {
+            return Ok(());
+        }
+        match self.referenced_urls().find(|url| url.scheme() == "file") {
+            Some(url) => Err(url.clone()),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(feature = "decoding")]
@@ -118,6 +541,9 @@ pub enum ImgError {
     CouldntDetectFormat,
     UnsupportedFormat(String),
     DecodingFailed(image::ImageError),
+    /// The dimensions in the image's header differ from its pixel data. Contains
+    /// the header's and the decoded dimensions.
+    DimensionsMismatch(((u32, u32), (u32, u32))),
 }
 
 #[cfg(feature = "decoding")]
@@ -137,6 +563,9 @@ impl std::fmt::Display for ImgError {
                 ImgError::CouldntDetectFormat => "Unable to detect image format from bytes.".into(),
                 ImgError::UnsupportedFormat(fmt) => format!("Unsupported image format: {fmt}"),
                 ImgError::DecodingFailed(e) => format!("Failed to decode image: {e}"),
+                ImgError::DimensionsMismatch(((w, h), (decoded_w, decoded_h))) => format!(
+                    "Image header says {w}x{h}, but the image decodes to {decoded_w}x{decoded_h}"
+                ),
             }
         )
     }
@@ -163,28 +592,29 @@ impl TryFrom<(Url, &[u8])> for ImageData {
     type Error = ImgError;
 
     fn try_from((url, img_bytes): (Url, &[u8])) -> Result<Self, Self::Error> {
-        use image::GenericImageView;
-        use std::io::Cursor;
-
-        let Some(format) = imghdr::from_bytes(img_bytes) else {
-            return Err(ImgError::CouldntDetectFormat);
-        };
-        let format = match format {
-            imghdr::Type::Jpeg => SupportedFormat::Jpg,
-            imghdr::Type::Png => SupportedFormat::Png,
-            _ => return Err(ImgError::UnsupportedFormat(format!("{format:?}"))),
-        };
-
-        let img_reader = image::io::Reader::with_format(
-            Cursor::new(&img_bytes),
-            match format {
-                SupportedFormat::Jpg => image::ImageFormat::Jpeg,
-                SupportedFormat::Png => image::ImageFormat::Png,
-            },
-        )
-        .decode()?;
-        let (width, height) = img_reader.dimensions();
         let hash = encoding::checksum_string(img_bytes);
+        Self::with_hash(url, img_bytes, hash)
+    }
+}
+
+#[cfg(feature = "decoding")]
+impl ImageData {
+    /// Build the [`ImageData`] for an image whose hash is already known, e.g.
+    /// because it was hashed while downloading. `hash` is used as-is for
+    /// [`ImageData::hash`]; converting from `(Url, &[u8])` instead computes a
+    /// legacy SHA-256 digest.
+    ///
+    /// # Errors
+    ///
+    /// [`ImgError`] if the format or dimensions can't be read. Dimensions come from
+    /// the image's header, so the pixel data isn't checked; see
+    /// [`crate::decoding::check_integrity`].
+    pub fn with_hash(url: Url, img_bytes: &[u8], hash: String) -> Result<Self, ImgError> {
+        let format = crate::decoding::detect_format(img_bytes)?;
+        let (width, height) = crate::decoding::dimensions(format, img_bytes)?;
+        let metadata = crate::xmp::extract(format, img_bytes)
+            .and_then(|packet| crate::xmp::parse(&packet))
+            .unwrap_or_default();
 
         Ok(ImageData {
             url,
@@ -192,15 +622,28 @@ impl TryFrom<(Url, &[u8])> for ImageData {
             width,
             height,
             format,
+            size: u64::try_from(img_bytes.len()).ok(),
+            mirrors: vec![],
+            metadata,
+            focal_point: None,
+            crops: vec![],
+            thumbnail_url: None,
+            blur_hash: None,
+            colors: None,
+            perceptual_hash: None,
+            included_from: None,
+            extra: Map::new(),
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use encoding::{Digest, HashAlgorithm};
+    use serde_json::Map;
     use url::Url;
 
-    use super::{ImageData, ImageRepo};
+    use super::{ImageData, ImageMetadata, ImageRepo, SupportedFormat};
 
     #[test]
     fn deserializes_from_test_repo() {
@@ -212,6 +655,101 @@ mod tests {
         assert!(!repo.images.is_empty());
     }
 
+    #[test]
+    fn verifies_self_describing_digests() {
+        let img_bytes = include_bytes!("../ferris.png");
+        let mut data = ImageData {
+            url: Url::parse("https://rustacean.net/assets/rustacean-flat-noshadow.png")
+                .expect("url"),
+            hash: "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb".into(),
+            width: 460,
+            height: 307,
+            format: SupportedFormat::Png,
+            size: None,
+            mirrors: vec![],
+            metadata: ImageMetadata::default(),
+            focal_point: None,
+            crops: vec![],
+            thumbnail_url: None,
+            blur_hash: None,
+            colors: None,
+            perceptual_hash: None,
+            included_from: None,
+            extra: Map::new(),
+        };
+        assert!(data.verify_checksum(img_bytes).is_ok());
+        let legacy_file_name = data.to_file_name().expect("file name");
+
+        data.hash = format!("sha256:{}", data.hash);
+        assert!(data.verify_checksum(img_bytes).is_ok());
+        assert_eq!(data.to_file_name().expect("file name"), legacy_file_name);
+
+        data.hash = Digest::compute(HashAlgorithm::Blake3, img_bytes).to_string();
+        assert!(data.verify_checksum(img_bytes).is_ok());
+        assert!(data.verify_checksum(b"not ferris").is_err());
+        assert_ne!(data.to_file_name().expect("file name"), legacy_file_name);
+    }
+
+    #[test]
+    fn resolves_base_mirrors() {
+        let repo = serde_json::from_str::<ImageRepo>(
+            r#"{
+  "name": "Mirrored",
+  "mirrors": ["https://mirror.example.com/jwst/"],
+  "images": [
+    {
+      "url": "https://stsci-opo.org/STScI-01GA6KKWG229B16K4Q38CH3BXS.png",
+      "hash": "e89fb6764fa3f176e0abeea3b55d8b055195dca3fc1573753deb4612cb7834a9",
+      "width": 14575,
+      "height": 8441,
+      "format": "png",
+      "mirrors": ["https://other.example.com/a.png"]
+    }
+  ]
+}"#,
+        )
+        .expect("image_repo");
+        let images = repo.images_with_mirrors();
+        let urls = images[0].urls().map(Url::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://stsci-opo.org/STScI-01GA6KKWG229B16K4Q38CH3BXS.png",
+                "https://other.example.com/a.png",
+                "https://mirror.example.com/jwst/STScI-01GA6KKWG229B16K4Q38CH3BXS.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_allows_local_urls_in_local_manifests() {
+        let repo = serde_json::from_str::<ImageRepo>(
+            r#"{
+  "name": "Sneaky",
+  "images": [
+    {
+      "url": "https://example.com/a.png",
+      "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+      "width": 460,
+      "height": 307,
+      "format": "png",
+      "thumbnailUrl": "file:///home/user/.ssh/id_ed25519"
+    }
+  ]
+}"#,
+        )
+        .expect("image_repo");
+        let remote = Url::parse("https://example.com/repo.json").expect("url");
+        let local = Url::parse("file:///home/user/repo.json").expect("url");
+        assert_eq!(
+            repo.check_local_urls(&remote)
+                .expect_err("local url")
+                .as_str(),
+            "file:///home/user/.ssh/id_ed25519"
+        );
+        assert!(repo.check_local_urls(&local).is_ok());
+    }
+
     #[test]
     #[cfg(feature = "decoding")]
     fn decodes_from_btye_vec() {
@@ -228,7 +766,8 @@ mod tests {
   "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
   "width": 460,
   "height": 307,
-  "format": "png"
+  "format": "png",
+  "size": 49880
 }"#
         );
     }
@@ -0,0 +1,282 @@
+//! Extraction of embedded XMP packets and the Astronomy Visualization Metadata
+//! (AVM) they carry.
+//!
+//! Official observatory releases embed AVM as XMP: PNG in an `iTXt` chunk,
+//! JPEG in an `APP1` segment and TIFF in tag 700. For other formats we fall
+//! back to searching the file for the XMP packet wrapper.
+
+use crate::types::{Coordinates, ImageMetadata, SupportedFormat};
+
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const TIFF_XMP_TAG: u16 = 700;
+
+const NS_RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const NS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const NS_PHOTOSHOP: &str = "http://ns.adobe.com/photoshop/1.0/";
+const NS_AVM: &str = "http://www.communicatingastronomy.org/avm/1.0/";
+
+fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Find the XMP packet in an uncompressed PNG `iTXt` chunk.
+fn from_png(bytes: &[u8]) -> Option<&[u8]> {
+    let mut offset = 8;
+    while let Some(len) = read_u32(bytes, offset, true) {
+        let len = usize::try_from(len).ok()?;
+        let chunk_type = bytes.get(offset + 4..offset + 8)?;
+        let data = bytes.get(offset + 8..offset + 8 + len)?;
+        if chunk_type == b"IEND" {
+            break;
+        }
+        if chunk_type == b"iTXt" && data.starts_with(PNG_XMP_KEYWORD) {
+            // keyword, NUL, compression flag, compression method
+            let rest = data.get(PNG_XMP_KEYWORD.len() + 1..)?;
+            let (compressed, rest) = rest.split_first()?;
+            if *compressed != 0 {
+                return None;
+            }
+            // skip compression method, then NUL-terminated language tag and translated keyword
+            let mut rest = rest.get(1..)?;
+            for _ in 0..2 {
+                let nul = rest.iter().position(|b| *b == 0)?;
+                rest = &rest[nul + 1..];
+            }
+            return Some(rest);
+        }
+        // length, type, data, CRC
+        offset += 12 + len;
+    }
+    None
+}
+
+/// Find the XMP packet in a JPEG `APP1` segment.
+fn from_jpeg(bytes: &[u8]) -> Option<&[u8]> {
+    let mut offset = 2;
+    loop {
+        if *bytes.get(offset)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(offset + 1)?;
+        // start of scan; metadata segments all come before it
+        if marker == 0xDA {
+            return None;
+        }
+        let len = usize::from(read_u16(bytes, offset + 2, true)?);
+        let data = bytes.get(offset + 4..offset + 2 + len)?;
+        if marker == 0xE1 && data.starts_with(JPEG_XMP_HEADER) {
+            return Some(&data[JPEG_XMP_HEADER.len()..]);
+        }
+        offset += 2 + len;
+    }
+}
+
+/// Find the XMP packet in TIFF tag 700 of the first IFD.
+fn from_tiff(bytes: &[u8]) -> Option<&[u8]> {
+    let big_endian = match bytes.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let ifd = usize::try_from(read_u32(bytes, 4, big_endian)?).ok()?;
+    let entries = read_u16(bytes, ifd, big_endian)?;
+    (0..usize::from(entries)).find_map(|i| {
+        let entry = ifd + 2 + i * 12;
+        if read_u16(bytes, entry, big_endian)? != TIFF_XMP_TAG {
+            return None;
+        }
+        let count = usize::try_from(read_u32(bytes, entry + 4, big_endian)?).ok()?;
+        let value = if count <= 4 {
+            entry + 8
+        } else {
+            usize::try_from(read_u32(bytes, entry + 8, big_endian)?).ok()?
+        };
+        bytes.get(value..value + count)
+    })
+}
+
+/// Find an XMP packet anywhere in the file by its `x:xmpmeta` wrapper.
+fn search(bytes: &[u8]) -> Option<&[u8]> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+    let start = bytes.windows(START.len()).position(|w| w == START)?;
+    let len = bytes[start..].windows(END.len()).position(|w| w == END)?;
+    Some(&bytes[start..start + len + END.len()])
+}
+
+/// Extract the embedded XMP packet from an image, if it has one.
+#[must_use]
+pub fn extract(format: SupportedFormat, img_bytes: &[u8]) -> Option<String> {
+    let packet = match format {
+        SupportedFormat::Png => from_png(img_bytes),
+        SupportedFormat::Jpg => from_jpeg(img_bytes),
+        SupportedFormat::Tiff => from_tiff(img_bytes),
+        SupportedFormat::Webp | SupportedFormat::Avif => search(img_bytes),
+    }?;
+    Some(String::from_utf8_lossy(packet).into_owned())
+}
+
+/// Get the value of an XMP property element. For simple properties that's the
+/// element's text; for `rdf:Alt`, `rdf:Bag` and `rdf:Seq` it's the text of each `rdf:li`.
+fn property_values(node: roxmltree::Node<'_, '_>) -> Vec<String> {
+    let items = node
+        .descendants()
+        .filter(|n| n.has_tag_name((NS_RDF, "li")))
+        .filter_map(|n| n.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if !items.is_empty() {
+        return items;
+    }
+    node.text()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(|text| vec![text.to_string()])
+        .unwrap_or_default()
+}
+
+/// Parse AVM metadata from an XMP packet. Returns `None` if the packet isn't
+/// valid XML or contains no metadata we understand.
+#[must_use]
+pub fn parse(packet: &str) -> Option<ImageMetadata> {
+    // strip anything before the XML starts, like a BOM or `<?xpacket ?>` noise
+    let packet = &packet[packet.find('<')?..];
+    let doc = roxmltree::Document::parse(packet).ok()?;
+    let mut metadata = ImageMetadata::default();
+
+    for description in doc
+        .descendants()
+        .filter(|n| n.has_tag_name((NS_RDF, "Description")))
+    {
+        // properties can be written as attributes or child elements
+        let attributes = description.attributes().map(|attr| {
+            (
+                attr.namespace().unwrap_or_default(),
+                attr.name(),
+                vec![attr.value().to_string()],
+            )
+        });
+        let elements = description
+            .children()
+            .filter(roxmltree::Node::is_element)
+            .map(|n| {
+                (
+                    n.tag_name().namespace().unwrap_or_default(),
+                    n.tag_name().name(),
+                    property_values(n),
+                )
+            });
+
+        for (namespace, name, mut values) in attributes.chain(elements).collect::<Vec<_>>() {
+            if values.is_empty() {
+                continue;
+            }
+            match (namespace, name) {
+                (NS_DC, "title") => metadata.title = Some(values.remove(0)),
+                (NS_DC, "description") => metadata.description = Some(values.remove(0)),
+                (NS_PHOTOSHOP, "Credit") => metadata.credit = Some(values.remove(0)),
+                (NS_AVM, "Subject.Name") => metadata.subjects = values,
+                (NS_AVM, "Spectral.Band") => metadata.spectral_bands = values,
+                (NS_AVM, "Spatial.ReferenceValue") => {
+                    if let [ra, dec] = values.as_slice() {
+                        if let (Ok(ra), Ok(dec)) = (ra.parse(), dec.parse()) {
+                            metadata.coordinates = Some(Coordinates { ra, dec });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    (!metadata.is_empty()).then_some(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, parse};
+    use crate::types::{Coordinates, SupportedFormat};
+
+    const PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about=""
+        xmlns:dc="http://purl.org/dc/elements/1.1/"
+        xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/"
+        xmlns:avm="http://www.communicatingastronomy.org/avm/1.0/"
+        photoshop:Credit="NASA, ESA, CSA, and STScI">
+      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Cosmic Cliffs in the Carina Nebula</rdf:li></rdf:Alt></dc:title>
+      <avm:Subject.Name><rdf:Bag><rdf:li>NGC 3324</rdf:li></rdf:Bag></avm:Subject.Name>
+      <avm:Spatial.ReferenceValue><rdf:Seq><rdf:li>159.2</rdf:li><rdf:li>-58.6</rdf:li></rdf:Seq></avm:Spatial.ReferenceValue>
+      <avm:Spectral.Band><rdf:Seq><rdf:li>Infrared</rdf:li><rdf:li>Infrared</rdf:li></rdf:Seq></avm:Spectral.Band>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn parses_avm() {
+        let metadata = parse(PACKET).expect("metadata");
+        assert_eq!(
+            metadata.title.as_deref(),
+            Some("Cosmic Cliffs in the Carina Nebula")
+        );
+        assert_eq!(
+            metadata.credit.as_deref(),
+            Some("NASA, ESA, CSA, and STScI")
+        );
+        assert_eq!(metadata.subjects, ["NGC 3324"]);
+        assert_eq!(
+            metadata.coordinates,
+            Some(Coordinates {
+                ra: 159.2,
+                dec: -58.6
+            })
+        );
+        assert_eq!(metadata.spectral_bands, ["Infrared", "Infrared"]);
+    }
+
+    #[test]
+    fn extracts_from_png_itxt() {
+        let ferris = include_bytes!("../ferris.png");
+        let mut chunk_data = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        chunk_data.extend_from_slice(PACKET.as_bytes());
+        let mut chunk = u32::try_from(chunk_data.len())
+            .expect("length")
+            .to_be_bytes()
+            .to_vec();
+        chunk.extend_from_slice(b"iTXt");
+        chunk.extend_from_slice(&chunk_data);
+        // the CRC isn't checked when extracting
+        chunk.extend_from_slice(&[0; 4]);
+
+        // the 8 byte signature and 25 byte IHDR chunk, our chunk, then IEND
+        let mut png = ferris[..33].to_vec();
+        png.extend_from_slice(&chunk);
+        png.extend_from_slice(&[0, 0, 0, 0]);
+        png.extend_from_slice(b"IEND");
+        png.extend_from_slice(&[0; 4]);
+
+        assert_eq!(extract(SupportedFormat::Png, &png).as_deref(), Some(PACKET));
+        // ferris has XMP, but no AVM
+        let packet = extract(SupportedFormat::Png, ferris).expect("packet");
+        assert_eq!(parse(&packet), None);
+    }
+}
@@ -0,0 +1,344 @@
+//! The `check` subcommand: a link checker for published repositories, meant to
+//! run as a scheduled job. It prints a JSON report to stdout and exits with 1 if
+//! any link is broken.
+
+use crate::{
+    download::{self, run_parallel},
+    manifest, BoxError, Errors, Options,
+};
+use image_repo::{http::BlockingHttpClient, lint::Severity, types::ImageData};
+use reqwest::{
+    blocking::Response,
+    header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+    StatusCode,
+};
+use serde::Serialize;
+use std::{error::Error, fs, path::Path};
+use url::Url;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProblemKind {
+    /// The request failed or the server didn't respond with success.
+    Broken,
+    /// The URL redirects elsewhere.
+    Redirect,
+    /// The server sends a content type that doesn't match the image format.
+    ContentTypeMismatch,
+    /// The server reports a different size than the manifest declares.
+    SizeMismatch,
+    /// The downloaded image doesn't match its hash, size, format or dimensions.
+    VerificationFailed,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+    pub severity: Severity,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(severity: Severity, kind: ProblemKind, message: String) -> Self {
+        Problem {
+            severity,
+            kind,
+            message,
+        }
+    }
+}
+
+/// What the server said about a URL, without downloading the image.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Probe {
+    /// HTTP status of the final response. `None` for `file://` URLs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Where the URL ended up after following redirects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Size of the file according to the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// The result of checking one URL of an image.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkReport {
+    /// Index of the image in the manifest.
+    pub image: usize,
+    pub url: Url,
+    /// Whether the URL is a mirror rather than the image's main URL.
+    pub mirror: bool,
+    #[serde(flatten)]
+    pub probe: Probe,
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub checked: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub links: Vec<LinkReport>,
+}
+
+/// Total file size from a `Content-Range: bytes 0-0/12345` header.
+fn content_range_total(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+fn header(resp: &Response, name: reqwest::header::HeaderName) -> Option<&str> {
+    resp.headers().get(name)?.to_str().ok()
+}
+
+/// Ask the server about a URL with a `HEAD` request, falling back to fetching a
+/// single byte for servers that don't support `HEAD`.
+fn probe(client: &BlockingHttpClient, url: &Url) -> Result<Probe, BoxError> {
+    if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|()| format!("Invalid file URL: {url}"))?;
+        return Ok(Probe {
+            size: Some(fs::metadata(path)?.len()),
+            ..Probe::default()
+        });
+    }
+
+    let resp = match client.send(|client| client.head(url.clone())) {
+        Ok(resp) if resp.status().is_success() => resp,
+        _ => client.send(|client| client.get(url.clone()).header(RANGE, "bytes=0-0"))?,
+    };
+    // don't trust the length of partial responses, the total is in Content-Range
+    let size = if resp.status() == StatusCode::PARTIAL_CONTENT {
+        header(&resp, CONTENT_RANGE).and_then(content_range_total)
+    } else {
+        header(&resp, CONTENT_LENGTH).and_then(|len| len.parse().ok())
+    };
+    Ok(Probe {
+        status: Some(resp.status().as_u16()),
+        final_url: Some(resp.url().clone()).filter(|final_url| final_url != url),
+        content_type: header(&resp, CONTENT_TYPE).map(String::from),
+        size,
+    })
+}
+
+/// Compare what the server said about a URL with what the manifest expects.
+fn assess(image: &ImageData, probe: &Probe) -> Vec<Problem> {
+    let mut problems = vec![];
+    if let Some(status) = probe.status.filter(|status| !(200..300).contains(status)) {
+        problems.push(Problem::new(
+            Severity::Error,
+            ProblemKind::Broken,
+            format!("HTTP {status}"),
+        ));
+        return problems;
+    }
+    if let Some(final_url) = &probe.final_url {
+        problems.push(Problem::new(
+            Severity::Warning,
+            ProblemKind::Redirect,
+            format!("Redirects to {final_url}"),
+        ));
+    }
+    if let Some(content_type) = &probe.content_type {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        if !essence.eq_ignore_ascii_case(image.format.mime_type()) {
+            problems.push(Problem::new(
+                Severity::Warning,
+                ProblemKind::ContentTypeMismatch,
+                format!("Served as {essence}, expected {}", image.format.mime_type()),
+            ));
+        }
+    }
+    if let (Some(expected), Some(size)) = (image.size, probe.size) {
+        if expected != size {
+            problems.push(Problem::new(
+                Severity::Error,
+                ProblemKind::SizeMismatch,
+                format!("Size is {size} bytes, expected {expected}"),
+            ));
+        }
+    }
+    problems
+}
+
+/// Check one URL of an image. If `verify_contents` is set, the image is also
+/// downloaded and verified; `Some(true)` checks its format and dimensions too.
+fn check_link(
+    client: &BlockingHttpClient,
+    image: &ImageData,
+    url: &Url,
+    verify_contents: Option<bool>,
+    decode: bool,
+    progress: &download::Progress,
+) -> (Probe, Vec<Problem>) {
+    let probe = match probe(client, url) {
+        Ok(probe) => probe,
+        Err(e) => {
+            let problem = Problem::new(Severity::Error, ProblemKind::Broken, e.to_string());
+            return (Probe::default(), vec![problem]);
+        }
+    };
+    let mut problems = assess(image, &probe);
+    // no need to download what's already known to be wrong
+    let failed = problems.iter().any(|p| p.severity == Severity::Error);
+    if let Some(check_contents) = verify_contents.filter(|_| !failed) {
+        let verified = image.digest().map_err(BoxError::from).and_then(|digest| {
+            let download = download::download(url, digest.algorithm, progress)?;
+            manifest::verify_image(image, &download, check_contents, decode)
+        });
+        if let Err(e) = verified {
+            problems.push(Problem::new(
+                Severity::Error,
+                ProblemKind::VerificationFailed,
+                e.to_string(),
+            ));
+        }
+    }
+    (probe, problems)
+}
+
+/// Run the `check` subcommand with the arguments following it.
+pub fn run(args: &[&str], options: &Options) -> Result<(), Box<dyn Error>> {
+    let (download, manifest_path) = match args {
+        ["--download", manifest] | [manifest, "--download"] => (true, manifest),
+        [manifest] => (false, manifest),
+        _ => return Err(Errors::Usage("Expected a manifest and optionally --download").into()),
+    };
+    let repo = manifest::read(Path::new(manifest_path))?;
+    let images = repo.images_with_mirrors();
+    let links = images
+        .iter()
+        .enumerate()
+        .flat_map(|(i, image)| {
+            image
+                .urls()
+                .enumerate()
+                .map(move |(j, url)| (i, image, url, j > 0))
+        })
+        .collect::<Vec<_>>();
+
+    let client = download::client()?;
+    let results = run_parallel(
+        &links,
+        options.jobs,
+        "Checking",
+        |(_, image, url, mirror), progress| {
+            // mirrors serve the same bytes once the checksum matches, so only check contents once
+            let verify_contents = download.then_some(!mirror);
+            check_link(
+                client,
+                image,
+                url,
+                verify_contents,
+                options.decode,
+                progress,
+            )
+        },
+    );
+
+    let links = links
+        .iter()
+        .zip(results)
+        .map(|((image, _, url, mirror), (probe, problems))| LinkReport {
+            image: *image,
+            url: (*url).clone(),
+            mirror: *mirror,
+            probe,
+            problems,
+        })
+        .collect::<Vec<_>>();
+    let count = |severity| {
+        links
+            .iter()
+            .flat_map(|link| &link.problems)
+            .filter(|problem| problem.severity == severity)
+            .count()
+    };
+    let report = Report {
+        checked: links.len(),
+        errors: count(Severity::Error),
+        warnings: count(Severity::Warning),
+        links,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    for link in &report.links {
+        for problem in &link.problems {
+            eprintln!("    {}: {}", link.url, problem.message);
+        }
+    }
+    eprintln!(
+        "Checked {} links: {} errors, {} warnings",
+        report.checked, report.errors, report.warnings
+    );
+    if report.errors > 0 {
+        return Err(format!("Found {} errors", report.errors).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assess, content_range_total, Probe, ProblemKind, Severity};
+    use image_repo::types::ImageData;
+    use url::Url;
+
+    #[test]
+    fn reports_drift_and_redirects() {
+        let image = serde_json::from_str::<ImageData>(
+            r#"{
+                "url": "https://example.com/a.png",
+                "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                "width": 460,
+                "height": 307,
+                "format": "png",
+                "size": 49880
+            }"#,
+        )
+        .expect("image");
+        assert_eq!(content_range_total("bytes 0-0/49881"), Some(49881));
+
+        let probe = Probe {
+            status: Some(200),
+            final_url: Some(Url::parse("https://cdn.example.com/a.png").expect("url")),
+            content_type: Some("image/jpeg; charset=binary".into()),
+            size: Some(49881),
+        };
+        let problems = assess(&image, &probe)
+            .into_iter()
+            .map(|p| (p.severity, p.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                (Severity::Warning, ProblemKind::Redirect),
+                (Severity::Warning, ProblemKind::ContentTypeMismatch),
+                (Severity::Error, ProblemKind::SizeMismatch),
+            ]
+        );
+
+        let probe = Probe {
+            status: Some(404),
+            ..Probe::default()
+        };
+        let problems = assess(&image, &probe);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].kind, ProblemKind::Broken);
+
+        let probe = Probe {
+            status: Some(200),
+            content_type: Some("IMAGE/PNG".into()),
+            size: Some(49880),
+            ..Probe::default()
+        };
+        assert!(assess(&image, &probe).is_empty());
+    }
+}
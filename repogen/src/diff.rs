@@ -0,0 +1,318 @@
+//! The `diff` subcommand: compare two manifests by what they mean rather than
+//! how they're written, for reviewing changes to a repository. Images are paired
+//! up by hash, or else by URL, so reordering and reformatting don't show up.
+
+use crate::{manifest, Errors};
+use image_repo::{
+    types::{ImageData, ImageRepo},
+    Digest,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{error::Error, path::Path};
+use url::Url;
+
+/// A field that differs, as a dotted path such as `metadata.title`. A missing
+/// value means the field isn't set on that side.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub field: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// An image in both manifests whose fields differ.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Modified {
+    /// The image's URL in the new manifest.
+    pub url: Url,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Diff {
+    /// Changes to the repository's own fields, e.g. its name.
+    pub repository: Vec<Change>,
+    pub added: Vec<ImageData>,
+    pub removed: Vec<ImageData>,
+    pub modified: Vec<Modified>,
+    /// Number of images that are the same in both manifests.
+    pub unchanged: usize,
+}
+
+impl Diff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.repository.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+/// Find the fields that differ between two JSON values, recursing into objects.
+/// Arrays are compared as a whole.
+fn changes(field: &str, old: Option<&Value>, new: Option<&Value>, found: &mut Vec<Change>) {
+    // a missing object is as good as an empty one, e.g. metadata that was added
+    let empty = Value::Object(Map::new());
+    let (old, new) = match (old, new) {
+        (None, Some(Value::Object(_))) => (Some(&empty), new),
+        (Some(Value::Object(_)), None) => (old, Some(&empty)),
+        _ => (old, new),
+    };
+    if let (Some(Value::Object(old)), Some(Value::Object(new))) = (old, new) {
+        let keys = old
+            .keys()
+            .chain(new.keys().filter(|key| !old.contains_key(*key)));
+        for key in keys {
+            let field = if field.is_empty() {
+                key.clone()
+            } else {
+                format!("{field}.{key}")
+            };
+            changes(&field, old.get(key), new.get(key), found);
+        }
+    } else if old != new {
+        found.push(Change {
+            field: field.to_string(),
+            old: old.cloned(),
+            new: new.cloned(),
+        });
+    }
+}
+
+/// The image's hash with its algorithm spelled out, so a bare SHA-256 digest
+/// matches the same digest with a `sha256:` prefix.
+fn hash_key(image: &ImageData) -> String {
+    Digest::parse(&image.hash).map_or_else(|_| image.hash.clone(), |digest| digest.to_string())
+}
+
+/// Compare two manifests. Images with the same hash are the same image, even if
+/// moved to another URL; the remaining images are paired up by URL, e.g. when
+/// an image was re-published under the same URL.
+///
+/// # Errors
+///
+/// Only if a manifest can't be serialized, which shouldn't happen.
+pub fn diff(old: &ImageRepo, new: &ImageRepo) -> Result<Diff, serde_json::Error> {
+    let mut repository = vec![];
+    let [mut old_repo, mut new_repo] = [old, new].map(serde_json::to_value);
+    for repo in [&mut old_repo, &mut new_repo] {
+        if let Ok(Value::Object(repo)) = repo {
+            repo.remove("images");
+        }
+    }
+    changes("", Some(&old_repo?), Some(&new_repo?), &mut repository);
+
+    let mut unpaired = new.images.iter().enumerate().collect::<Vec<_>>();
+    let mut pairs = vec![];
+    let mut removed = vec![];
+    let same_hash = |a: &ImageData, b: &ImageData| hash_key(a) == hash_key(b);
+    let same_url = |a: &ImageData, b: &ImageData| a.url == b.url;
+    // by hash for every image first, so an image can't be claimed by URL first
+    // when the same file moved elsewhere
+    let mut by_url = vec![];
+    for image in &old.images {
+        match unpaired
+            .iter()
+            .position(|(_, other)| same_hash(image, other))
+        {
+            Some(i) => pairs.push((image, unpaired.remove(i))),
+            None => by_url.push(image),
+        }
+    }
+    for image in by_url {
+        match unpaired
+            .iter()
+            .position(|(_, other)| same_url(image, other))
+        {
+            Some(i) => pairs.push((image, unpaired.remove(i))),
+            None => removed.push(image.clone()),
+        }
+    }
+
+    pairs.sort_by_key(|(_, (i, _))| *i);
+    let mut modified = vec![];
+    let mut unchanged = 0;
+    // compare hashes with their algorithm spelled out, like when pairing them up
+    let normalized = |image: &ImageData| {
+        let mut value = serde_json::to_value(image)?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("hash".into(), hash_key(image).into());
+        }
+        Ok::<_, serde_json::Error>(value)
+    };
+    for (old_image, (_, new_image)) in pairs {
+        let mut found = vec![];
+        changes(
+            "",
+            Some(&normalized(old_image)?),
+            Some(&normalized(new_image)?),
+            &mut found,
+        );
+        if found.is_empty() {
+            unchanged += 1;
+        } else {
+            modified.push(Modified {
+                url: new_image.url.clone(),
+                changes: found,
+            });
+        }
+    }
+    Ok(Diff {
+        repository,
+        added: unpaired
+            .into_iter()
+            .map(|(_, image)| image.clone())
+            .collect(),
+        removed,
+        modified,
+        unchanged,
+    })
+}
+
+/// A value for printing, or `(none)` if it isn't set.
+fn show(value: Option<&Value>) -> String {
+    value.map_or_else(|| "(none)".to_string(), Value::to_string)
+}
+
+/// Print a diff for reading, in the style of a unified diff.
+fn print(diff: &Diff) {
+    for change in &diff.repository {
+        println!(
+            "~ {}: {} -> {}",
+            change.field,
+            show(change.old.as_ref()),
+            show(change.new.as_ref())
+        );
+    }
+    for image in &diff.removed {
+        println!("- {}", image.url);
+    }
+    for image in &diff.added {
+        println!("+ {}", image.url);
+    }
+    for image in &diff.modified {
+        println!("~ {}", image.url);
+        for change in &image.changes {
+            println!(
+                "    {}: {} -> {}",
+                change.field,
+                show(change.old.as_ref()),
+                show(change.new.as_ref())
+            );
+        }
+    }
+}
+
+/// Run the `diff` subcommand with the arguments following it.
+pub fn run(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let (json, old, new) = match args {
+        ["--json", old, new] | [old, new, "--json"] => (true, old, new),
+        [old, new] => (false, old, new),
+        _ => return Err(Errors::Usage("Expected two manifests and optionally --json").into()),
+    };
+    let diff = diff(
+        &manifest::read(Path::new(old))?,
+        &manifest::read(Path::new(new))?,
+    )?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print(&diff);
+    }
+    if diff.is_empty() {
+        eprintln!("No differences");
+    } else {
+        eprintln!(
+            "{} added, {} removed, {} modified, {} unchanged images",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.modified.len(),
+            diff.unchanged
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, Change};
+    use image_repo::types::ImageRepo;
+    use serde_json::json;
+
+    const HASH: &str = "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb";
+
+    fn image(url: &str, hash: &str, title: &str) -> serde_json::Value {
+        json!({
+            "url": format!("https://example.com/{url}"),
+            "hash": hash,
+            "width": 460,
+            "height": 307,
+            "format": "png",
+            "metadata": { "title": title }
+        })
+    }
+
+    #[test]
+    fn compares_images_by_hash_and_url() {
+        let hash = |digit: &str| digit.repeat(64);
+        let old = serde_json::from_value::<ImageRepo>(json!({
+            "name": "Webb",
+            "images": [
+                image("a.png", HASH, "A"),
+                image("b.png", &hash("1"), "B"),
+                image("c.png", &hash("2"), "C"),
+                image("d.png", &hash("3"), "D"),
+            ]
+        }))
+        .expect("old");
+        // reordered, a's hash algorithm spelled out, b retitled, c re-published under the
+        // same URL, d moved, and e new
+        let new = serde_json::from_value::<ImageRepo>(json!({
+            "name": "Webb Favourites",
+            "images": [
+                image("e.png", &hash("4"), "E"),
+                image("moved/d.png", &hash("3"), "D"),
+                image("c.png", &hash("5"), "C"),
+                image("b.png", &hash("1"), "B, retitled"),
+                image("a.png", &format!("sha256:{HASH}"), "A"),
+            ]
+        }))
+        .expect("new");
+
+        let diff = diff(&old, &new).expect("diff");
+        assert_eq!(
+            diff.repository,
+            [Change {
+                field: "name".into(),
+                old: Some(json!("Webb")),
+                new: Some(json!("Webb Favourites")),
+            }]
+        );
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].url.as_str(), "https://example.com/e.png");
+        assert!(diff.removed.is_empty());
+        let modified = diff
+            .modified
+            .iter()
+            .map(|image| {
+                let fields = image.changes.iter().map(|change| change.field.as_str());
+                (image.url.path(), fields.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            modified,
+            [
+                ("/moved/d.png", vec!["url"]),
+                ("/c.png", vec!["hash"]),
+                ("/b.png", vec!["metadata.title"]),
+            ]
+        );
+        assert_eq!(diff.unchanged, 1);
+        assert!(super::diff(&old, &old).expect("diff").is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+//! Concurrent downloads with progress bars. Files are hashed as they arrive, so
+//! they never need a second pass over the bytes just to compute their digest.
+
+use crate::{BoxError, Errors};
+use image_repo::{
+    http::{BlockingHttpClient, HttpConfig},
+    Digest, HashAlgorithm, Hasher,
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::StatusCode;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{ErrorKind, Read},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    thread,
+};
+use url::Url;
+
+/// Number of images processed at once unless `--jobs` says otherwise.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// `repogen`'s own [`image_repo::http::USER_AGENT`].
+const USER_AGENT: &str = concat!(
+    "repogen/",
+    env!("CARGO_PKG_VERSION"),
+    " (wallpaper image repository generator)"
+);
+
+/// The client for every request, so connections are reused between downloads.
+static CLIENT: OnceLock<BlockingHttpClient> = OnceLock::new();
+
+/// Get the HTTP client shared by every download, with the same timeouts and
+/// retries as the app. Proxies are taken from the environment.
+pub fn client() -> Result<&'static BlockingHttpClient, reqwest::Error> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let client = BlockingHttpClient::new(&HttpConfig {
+        user_agent: USER_AGENT.to_string(),
+        ..HttpConfig::default()
+    })?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// Size of the chunks read from the network or disk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Upper bound on how much memory is reserved up front based on the size a
+/// server announces, so a bogus `Content-Length` can't exhaust memory.
+const MAX_PREALLOCATION: u64 = 1 << 30;
+
+/// Progress bars for a batch of images: one for the whole batch, and one for
+/// each file being downloaded. Hidden when stderr isn't a terminal.
+pub struct Progress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+}
+
+impl Progress {
+    fn new(total: usize, action: &str) -> Self {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(u64::try_from(total).unwrap_or(u64::MAX)));
+        overall.set_style(
+            ProgressStyle::with_template("{msg} [{bar:40}] {pos}/{len} images ({elapsed})")
+                .expect("valid template")
+                .progress_chars("=> "),
+        );
+        overall.set_message(action.to_string());
+        Progress { multi, overall }
+    }
+
+    /// Print a message above the progress bars, or just to stderr if they're hidden.
+    pub fn println(&self, message: &str) {
+        if self.multi.is_hidden() || self.multi.println(message).is_err() {
+            eprintln!("{message}");
+        }
+    }
+
+    fn file_bar(&self, url: &Url, len: Option<u64>) -> ProgressBar {
+        let name = url
+            .path_segments()
+            .and_then(Iterator::last)
+            .unwrap_or(url.as_str())
+            .to_string();
+        let bar = match len {
+            Some(len) => ProgressBar::new(len).with_style(
+                ProgressStyle::with_template(
+                    "  {msg:30!} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})",
+                )
+                .expect("valid template")
+                .progress_chars("=> "),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("  {msg:30!} {spinner} {bytes} ({bytes_per_sec})")
+                    .expect("valid template"),
+            ),
+        };
+        bar.set_message(name);
+        self.multi.add(bar)
+    }
+}
+
+/// A downloaded file and its digest.
+pub struct Download {
+    pub bytes: Vec<u8>,
+    pub digest: Digest,
+}
+
+/// Read everything from `reader`, hashing it as it arrives.
+fn read_hashing(
+    mut reader: impl Read,
+    len: Option<u64>,
+    algorithm: HashAlgorithm,
+    bar: &ProgressBar,
+) -> std::io::Result<Download> {
+    let capacity = len.map_or(0, |len| len.min(MAX_PREALLOCATION));
+    let mut bytes = Vec::with_capacity(usize::try_from(capacity).unwrap_or_default());
+    let mut hasher = Hasher::new(algorithm);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&chunk[..read]);
+        bytes.extend_from_slice(&chunk[..read]);
+        bar.inc(u64::try_from(read).unwrap_or_default());
+    }
+    Ok(Download {
+        bytes,
+        digest: hasher.finalize(),
+    })
+}
+
+/// Download a file, or read it from disk for `file://` URLs, and hash it with
+/// `algorithm` on the way.
+pub fn download(
+    url: &Url,
+    algorithm: HashAlgorithm,
+    progress: &Progress,
+) -> Result<Download, BoxError> {
+    let (reader, len): (Box<dyn Read>, _) = if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|()| format!("Invalid file URL: {url}"))?;
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        (Box::new(file), Some(len))
+    } else {
+        let http_resp = client()?.get(url)?;
+        if http_resp.status() != StatusCode::OK {
+            return Err(Errors::HttpFailed(http_resp.status()).into());
+        }
+        let len = http_resp.content_length();
+        (Box::new(http_resp), len)
+    };
+
+    let bar = progress.file_bar(url, len);
+    let download = read_hashing(reader, len, algorithm, &bar);
+    bar.finish_and_clear();
+    Ok(download?)
+}
+
+/// The URL of a file given on the command line as either a URL or a local path.
+pub fn url_or_path(arg: &str) -> Result<Url, Box<dyn Error>> {
+    match Url::parse(arg) {
+        Ok(url) if matches!(url.scheme(), "http" | "https" | "file") => Ok(url),
+        _ => Ok(Url::from_file_path(fs::canonicalize(arg)?)
+            .map_err(|()| format!("Invalid path: {arg}"))?),
+    }
+}
+
+/// Fetch a small text file such as a feed or manifest, which may be at a
+/// `file://` URL.
+pub fn fetch(url: &Url) -> Result<String, Box<dyn Error>> {
+    if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|()| format!("Invalid file URL: {url}"))?;
+        return Ok(fs::read_to_string(path)?);
+    }
+    let resp = client()?.get(url)?;
+    if resp.status() != StatusCode::OK {
+        return Err(Errors::HttpFailed(resp.status()).into());
+    }
+    Ok(resp.text()?)
+}
+
+/// Run `f` on every item, `jobs` at a time, with a progress bar for the batch.
+/// Results are returned in the same order as `items`.
+pub fn run_parallel<T, R, F>(items: &[T], jobs: usize, action: &str, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T, &Progress) -> R + Sync,
+{
+    let progress = Progress::new(items.len(), action);
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let workers = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            return results;
+                        };
+                        results.push((i, f(item, &progress)));
+                        progress.overall.inc(1);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Vec<_>>()
+    });
+    progress.overall.finish_and_clear();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_hashing, run_parallel};
+    use image_repo::{Digest, HashAlgorithm};
+    use indicatif::ProgressBar;
+
+    #[test]
+    fn hashes_while_reading() {
+        let ferris = include_bytes!("../../image-repo/ferris.png");
+        let bar = ProgressBar::hidden();
+        let download =
+            read_hashing(&ferris[..], None, HashAlgorithm::Blake3, &bar).expect("download");
+        assert_eq!(download.bytes, ferris);
+        assert_eq!(
+            download.digest,
+            Digest::compute(HashAlgorithm::Blake3, ferris)
+        );
+        assert_eq!(bar.position(), 49880);
+    }
+
+    #[test]
+    fn keeps_results_in_order() {
+        let items = (0..50).collect::<Vec<u64>>();
+        let results = run_parallel(&items, 8, "Testing", |&i, _| {
+            // finish out of order
+            std::thread::sleep(std::time::Duration::from_millis(50 - i));
+            i * 2
+        });
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,269 @@
+//! The `files` subcommand: build image data from local copies of the images
+//! before they're uploaded, so nothing needs downloading. Each image's URL is the
+//! base URL joined with its path relative to the given directory.
+
+use crate::{
+    analyze, download::run_parallel, manifest, print_images, report_failures, BoxError, Errors,
+    ImageSpec, Options, Processed, Thumbnails,
+};
+use image_repo::{types::ImageData, Digest, HashAlgorithm};
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// A local image and the URL it will be published at.
+struct LocalFile {
+    path: PathBuf,
+    url: Url,
+    /// Whether the file was named on the command line, rather than found in a
+    /// directory. Only named files are required to be images.
+    explicit: bool,
+}
+
+/// The URL of a file at `relative` path segments below `base`. `base` is always
+/// treated as a directory, whether or not it ends with a slash.
+fn url_for(base: &Url, relative: &[String]) -> Result<Url, Box<dyn Error>> {
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .map_err(|()| format!("Not a valid base URL: {base}"))?
+        .pop_if_empty()
+        .extend(relative);
+    Ok(url)
+}
+
+/// Find the files under `dir`, recursively and sorted, as paths relative to it.
+/// Hidden files and directories are skipped.
+fn walk(dir: &Path, prefix: &[String], files: &mut Vec<(PathBuf, Vec<String>)>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(fs::DirEntry::file_name);
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let mut relative = prefix.to_vec();
+        relative.push(name);
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), &relative, files)?;
+        } else {
+            files.push((entry.path(), relative));
+        }
+    }
+    Ok(())
+}
+
+/// Find the images at `path`, a file or a directory, and their URLs below `base`.
+fn collect(base: &Url, path: &Path) -> Result<Vec<LocalFile>, Box<dyn Error>> {
+    if !path.is_dir() {
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("Not a file: {}", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        return Ok(vec![LocalFile {
+            path: path.to_path_buf(),
+            url: url_for(base, &[name])?,
+            explicit: true,
+        }]);
+    }
+
+    let mut found = vec![];
+    walk(path, &[], &mut found)?;
+    found
+        .into_iter()
+        .map(|(path, relative)| {
+            Ok(LocalFile {
+                path,
+                url: url_for(base, &relative)?,
+                explicit: false,
+            })
+        })
+        .collect()
+}
+
+/// Build the image data for a local file and [`analyze`] it, like
+/// [`crate::process`]. Returns `None` for files found in a directory that
+/// aren't images.
+fn process_file(
+    file: &LocalFile,
+    hash_algorithm: Option<HashAlgorithm>,
+    compared: &[HashAlgorithm],
+    options: &Options,
+    thumbnails: Option<&Thumbnails>,
+) -> Result<Option<Processed>, BoxError> {
+    if !file.explicit {
+        // only read as much of files found in a directory as it takes to tell
+        // whether they're images, in case they're large files of another kind
+        if image_repo::decoding::sniff_file(&file.path)?.is_none() {
+            return Ok(None);
+        }
+    }
+    let bytes = fs::read(&file.path)?;
+    let mut image = match hash_algorithm {
+        Some(algorithm) => {
+            let hash = Digest::compute(algorithm, &bytes).to_string();
+            ImageData::with_hash(file.url.clone(), &bytes, hash)?
+        }
+        None => ImageData::try_from((file.url.clone(), bytes.as_slice()))?,
+    };
+    analyze(&mut image, &bytes, options, thumbnails)?;
+    let digests = manifest::digests(image.digest()?, compared, &bytes);
+    Ok(Some(Processed { image, digests }))
+}
+
+/// Run the `files` subcommand with the arguments following it.
+pub fn run(args: &[&str], options: &Options) -> Result<(), Box<dyn Error>> {
+    let (into, args) = match args {
+        ["--into", manifest, args @ ..] => (Some(Path::new(manifest)), args),
+        args => (None, args),
+    };
+    let [base, paths @ ..] = args else {
+        return Err(Errors::Usage("Expected a base URL and files or directories").into());
+    };
+    if paths.is_empty() {
+        return Err(Errors::Usage("Expected a base URL and files or directories").into());
+    }
+    let base = Url::parse(base)?;
+    let mut files = vec![];
+    for path in paths {
+        files.extend(collect(&base, Path::new(path))?);
+    }
+
+    let repo = into.map(manifest::read).transpose()?;
+    let thumbnails = match (into, &repo) {
+        (Some(path), Some(repo)) => Thumbnails::for_manifest(path, repo, options)?,
+        _ if options.thumbnails => {
+            return Err(Errors::Usage("--thumbnails needs a manifest, see --into").into())
+        }
+        _ => None,
+    };
+    let algorithms = files
+        .iter()
+        .map(|file| match &repo {
+            Some(repo) => manifest::hash_algorithm_for(repo, file.url.as_str(), options),
+            None => options.hash_algorithm,
+        })
+        .collect::<Vec<_>>();
+    let compared = repo
+        .as_ref()
+        .map(|repo| manifest::compared_algorithms(repo, options))
+        .unwrap_or_default();
+    let jobs = files.iter().zip(algorithms).collect::<Vec<_>>();
+    let results = run_parallel(
+        &jobs,
+        options.jobs,
+        "Processing",
+        |(file, algorithm), progress| {
+            let result = process_file(file, *algorithm, &compared, options, thumbnails.as_ref());
+            if let Ok(Some(_)) = result {
+                progress.println(&format!("Processed {}", file.path.display()));
+            }
+            result
+        },
+    );
+
+    let (files, results): (Vec<_>, Vec<_>) = files
+        .iter()
+        .zip(results)
+        .filter_map(|(file, result)| Some((file, result.transpose()?)))
+        .unzip();
+    let urls = files
+        .iter()
+        .map(|file| file.url.to_string())
+        .collect::<Vec<_>>();
+    if let (Some(path), Some(repo)) = (into, repo) {
+        let specs = urls
+            .iter()
+            .map(|url| ImageSpec {
+                url,
+                ..ImageSpec::default()
+            })
+            .collect::<Vec<_>>();
+        return manifest::merge_results(path, repo, &specs, results);
+    }
+
+    let mut images = vec![];
+    let mut failures = vec![];
+    for (file, result) in files.iter().zip(results) {
+        match result {
+            Ok(processed) => images.push(processed.image),
+            Err(e) => failures.push((file.path.display().to_string(), e)),
+        }
+    }
+    print_images(&images)?;
+    report_failures(&failures, files.len(), "process")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, process_file, url_for};
+    use crate::Options;
+    use image_repo::HashAlgorithm;
+    use std::fs;
+    use url::Url;
+
+    #[test]
+    fn maps_relative_paths_to_urls() {
+        let base = Url::parse("https://cdn.example.com/jwst").expect("url");
+        let url = url_for(&base, &["carina".into(), "cliffs #1.png".into()]).expect("url");
+        assert_eq!(
+            url.as_str(),
+            "https://cdn.example.com/jwst/carina/cliffs%20%231.png"
+        );
+
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("carina")).expect("dir");
+        fs::write(dir.join("carina").join("cliffs.png"), b"").expect("file");
+        fs::write(dir.join("a.png"), b"").expect("file");
+        fs::write(dir.join(".DS_Store"), b"").expect("file");
+
+        let base = Url::parse("https://cdn.example.com/jwst/").expect("url");
+        let urls = collect(&base, dir)
+            .expect("files")
+            .into_iter()
+            .map(|file| file.url.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://cdn.example.com/jwst/a.png",
+                "https://cdn.example.com/jwst/carina/cliffs.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_files_that_arent_images() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        fs::write(
+            dir.join("ferris.png"),
+            include_bytes!("../../image-repo/ferris.png"),
+        )
+        .expect("file");
+        fs::write(dir.join("data.bin"), vec![0; 1 << 20]).expect("file");
+
+        let base = Url::parse("https://cdn.example.com/jwst/").expect("url");
+        let (options, _) = Options::parse(&[]).expect("options");
+        let processed = collect(&base, dir)
+            .expect("files")
+            .iter()
+            .map(|file| {
+                process_file(file, None, &[HashAlgorithm::Sha256], &options, None)
+                    .expect("processed")
+                    .map(|processed| processed.image.url.to_string())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            processed,
+            [
+                None,
+                Some("https://cdn.example.com/jwst/ferris.png".to_string())
+            ]
+        );
+    }
+}
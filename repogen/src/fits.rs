@@ -0,0 +1,547 @@
+//! Build wallpapers from raw FITS telescope data.
+//!
+//! Only the subset of FITS needed for image data is supported: the primary
+//! HDU and `IMAGE` extensions with integer or floating point pixels. The first
+//! 2D plane of each selected HDU is stretched to 8 bits and written as PNG.
+//! One input makes a grayscale image; three inputs (e.g. three filters) are
+//! combined into the red, green and blue channels, in that order.
+
+use image_repo::types::ImageData;
+use std::{error::Error, fmt::Display, fs, path::Path, str::FromStr};
+use url::Url;
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+
+pub const USAGE: &str = "Usage:
+    repogen fits [options] <url> <output.png> <input.fits[hdu]>...
+
+    Renders one (grayscale) or three (red, green, blue) FITS images to a PNG and prints its
+    image data. <url> is where the PNG will be published. Append [n] to an input to select
+    the n-th HDU (0 is the primary HDU); by default the first HDU with image data is used.
+
+Options:
+    --stretch <linear|log|asinh>  Stretch applied after clipping (default: asinh)
+    --clip <low>,<high>           Percentiles to clip to, from 0 to 100 (default: 0.5,99.5)";
+
+#[derive(Debug)]
+pub enum FitsError {
+    /// The file isn't valid FITS. Contains a description of the problem.
+    Malformed(String),
+    /// The requested HDU doesn't exist or has no 2D image data.
+    NoImage(String),
+    /// Inputs to be combined into RGB have different dimensions.
+    DimensionMismatch,
+    /// Bad command line arguments.
+    Usage(String),
+}
+
+impl Display for FitsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FitsError::Malformed(reason) => write!(f, "Invalid FITS file: {reason}"),
+            FitsError::NoImage(input) => write!(f, "No 2D image data in {input}"),
+            FitsError::DimensionMismatch => {
+                write!(f, "All inputs must have the same width and height.")
+            }
+            FitsError::Usage(reason) => write!(f, "{reason}\n\n{USAGE}"),
+        }
+    }
+}
+
+impl Error for FitsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+/// Non-linear stretch applied to normalized pixel values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stretch {
+    Linear,
+    Log,
+    Asinh,
+}
+
+impl FromStr for Stretch {
+    type Err = FitsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Stretch::Linear),
+            "log" => Ok(Stretch::Log),
+            "asinh" => Ok(Stretch::Asinh),
+            _ => Err(FitsError::Usage(format!("Unknown stretch: {s}"))),
+        }
+    }
+}
+
+impl Stretch {
+    /// Apply the stretch to a value already normalized to `0.0..=1.0`.
+    fn apply(self, x: f64) -> f64 {
+        const LOG_A: f64 = 1000.0;
+        const ASINH_BETA: f64 = 0.1;
+        match self {
+            Stretch::Linear => x,
+            Stretch::Log => (LOG_A * x).ln_1p() / LOG_A.ln_1p(),
+            Stretch::Asinh => (x / ASINH_BETA).asinh() / (1.0 / ASINH_BETA).asinh(),
+        }
+    }
+}
+
+/// A single 2D image plane, rows stored top to bottom.
+#[derive(Debug)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<f64>,
+}
+
+/// Header of a single HDU.
+struct Header {
+    cards: Vec<(String, String)>,
+}
+
+impl Header {
+    fn value(&self, keyword: &str) -> Option<&str> {
+        self.cards
+            .iter()
+            .find(|(key, _)| key == keyword)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn int(&self, keyword: &str) -> Result<Option<i64>, FitsError> {
+        self.value(keyword)
+            .map(|value| {
+                value
+                    .parse::<i64>()
+                    .map_err(|_| FitsError::Malformed(format!("{keyword} = {value}")))
+            })
+            .transpose()
+    }
+
+    fn float(&self, keyword: &str) -> Result<Option<f64>, FitsError> {
+        self.value(keyword)
+            .map(|value| {
+                value
+                    .replace(['D', 'd'], "E")
+                    .parse::<f64>()
+                    .map_err(|_| FitsError::Malformed(format!("{keyword} = {value}")))
+            })
+            .transpose()
+    }
+
+    fn required_int(&self, keyword: &str) -> Result<i64, FitsError> {
+        self.int(keyword)?
+            .ok_or_else(|| FitsError::Malformed(format!("missing {keyword}")))
+    }
+
+    fn axes(&self) -> Result<Vec<usize>, FitsError> {
+        let naxis = self.required_int("NAXIS")?;
+        (1..=naxis)
+            .map(|n| {
+                let len = self.required_int(&format!("NAXIS{n}"))?;
+                usize::try_from(len).map_err(|_| FitsError::Malformed(format!("NAXIS{n} = {len}")))
+            })
+            .collect()
+    }
+
+    /// Size of the data unit following this header, excluding padding.
+    fn data_len(&self) -> Result<usize, FitsError> {
+        let axes = self.axes()?;
+        if axes.is_empty() {
+            return Ok(0);
+        }
+        let bytes_per_value = usize::try_from(self.required_int("BITPIX")?.unsigned_abs() / 8)
+            .map_err(|_| FitsError::Malformed("BITPIX".into()))?;
+        let pcount = usize::try_from(self.int("PCOUNT")?.unwrap_or(0))
+            .map_err(|_| FitsError::Malformed("PCOUNT".into()))?;
+        let gcount = usize::try_from(self.int("GCOUNT")?.unwrap_or(1))
+            .map_err(|_| FitsError::Malformed("GCOUNT".into()))?;
+        axes.iter()
+            .try_fold(1_usize, |product, &len| product.checked_mul(len))
+            .and_then(|values| values.checked_add(pcount))
+            .and_then(|values| values.checked_mul(gcount))
+            .and_then(|values| values.checked_mul(bytes_per_value))
+            .ok_or_else(|| FitsError::Malformed("data unit is too large".into()))
+    }
+
+    fn is_image(&self) -> bool {
+        let is_image_hdu =
+            self.value("SIMPLE").is_some() || self.value("XTENSION") == Some("IMAGE");
+        is_image_hdu
+            && self
+                .axes()
+                .is_ok_and(|axes| axes.len() >= 2 && axes[0] > 0 && axes[1] > 0)
+    }
+}
+
+/// Parse a header card value, stripping comments and string quotes.
+fn card_value(raw: &str) -> String {
+    let raw = raw.trim();
+    if let Some(quoted) = raw.strip_prefix('\'') {
+        let end = quoted.find('\'').unwrap_or(quoted.len());
+        return quoted[..end].trim_end().to_string();
+    }
+    raw.split('/').next().unwrap_or_default().trim().to_string()
+}
+
+/// Parse the header starting at `offset`. Returns the header and the offset of its data unit.
+fn read_header(bytes: &[u8], mut offset: usize) -> Result<(Header, usize), FitsError> {
+    let mut cards = vec![];
+    loop {
+        let Some(card) = bytes.get(offset..offset + CARD_SIZE) else {
+            return Err(FitsError::Malformed("header has no END card".into()));
+        };
+        offset += CARD_SIZE;
+        let card = String::from_utf8_lossy(card);
+        let keyword = card.get(..8).unwrap_or(&card).trim_end();
+        if keyword == "END" {
+            break;
+        }
+        if let Some(value) = card.get(8..).and_then(|rest| rest.strip_prefix("= ")) {
+            cards.push((keyword.to_string(), card_value(value)));
+        }
+    }
+    Ok((Header { cards }, offset.div_ceil(BLOCK_SIZE) * BLOCK_SIZE))
+}
+
+/// Decode the first 2D plane of an image HDU, applying `BSCALE` and `BZERO`.
+/// Blank pixels become NaN.
+fn read_plane(header: &Header, data: &[u8]) -> Result<Plane, FitsError> {
+    let axes = header.axes()?;
+    let (width, height) = (axes[0], axes[1]);
+    let bitpix = header.required_int("BITPIX")?;
+    let bscale = header.float("BSCALE")?.unwrap_or(1.0);
+    let bzero = header.float("BZERO")?.unwrap_or(0.0);
+    let blank = header.int("BLANK")?;
+
+    if !matches!(bitpix, 8 | 16 | 32 | 64 | -32 | -64) {
+        return Err(FitsError::Malformed(format!("BITPIX = {bitpix}")));
+    }
+    let bytes_per_value = usize::try_from(bitpix.unsigned_abs() / 8)
+        .map_err(|_| FitsError::Malformed(format!("BITPIX = {bitpix}")))?;
+    let Some(len) = width
+        .checked_mul(height)
+        .and_then(|len| len.checked_mul(bytes_per_value))
+    else {
+        return Err(FitsError::Malformed("image is too large".into()));
+    };
+    let Some(data) = data.get(..len) else {
+        return Err(FitsError::Malformed("data unit is truncated".into()));
+    };
+
+    #[allow(clippy::cast_precision_loss)]
+    let decode = |chunk: &[u8]| -> Result<f64, FitsError> {
+        let raw = match bitpix {
+            8 => i64::from(chunk[0]),
+            16 => i64::from(i16::from_be_bytes([chunk[0], chunk[1]])),
+            32 => i64::from(i32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])),
+            64 => i64::from_be_bytes(chunk.try_into().unwrap_or_default()),
+            -32 => {
+                let value = f32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                return Ok(f64::from(value) * bscale + bzero);
+            }
+            -64 => {
+                let value = f64::from_be_bytes(chunk.try_into().unwrap_or_default());
+                return Ok(value * bscale + bzero);
+            }
+            _ => return Err(FitsError::Malformed(format!("BITPIX = {bitpix}"))),
+        };
+        if blank == Some(raw) {
+            return Ok(f64::NAN);
+        }
+        Ok(raw as f64 * bscale + bzero)
+    };
+
+    let values = data
+        .chunks_exact(bytes_per_value)
+        .map(decode)
+        .collect::<Result<Vec<_>, _>>()?;
+    // FITS stores the bottom row first
+    let pixels = values
+        .chunks_exact(width)
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+    Ok(Plane {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Read an image plane from FITS bytes. With `hdu` set, reads that HDU (0 is the
+/// primary HDU); otherwise reads the first HDU that has 2D image data.
+///
+/// # Errors
+///
+/// [`FitsError`]
+pub fn read_image(bytes: &[u8], hdu: Option<usize>, name: &str) -> Result<Plane, FitsError> {
+    let mut offset = 0;
+    let mut index = 0;
+    while offset < bytes.len() {
+        let (header, data_offset) = read_header(bytes, offset)?;
+        let data_len = header.data_len()?;
+        let selected = hdu.map_or_else(|| header.is_image(), |hdu| hdu == index);
+        if selected {
+            if !header.is_image() {
+                return Err(FitsError::NoImage(name.to_string()));
+            }
+            return read_plane(&header, &bytes[data_offset.min(bytes.len())..]);
+        }
+        offset = data_len
+            .checked_next_multiple_of(BLOCK_SIZE)
+            .and_then(|len| data_offset.checked_add(len))
+            .ok_or_else(|| FitsError::Malformed("data unit is too large".into()))?;
+        index += 1;
+    }
+    Err(FitsError::NoImage(name.to_string()))
+}
+
+/// Value at the given percentile (`0.0..=100.0`) of the finite pixel values.
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    let index = ((percent / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[index.min(sorted.len() - 1)]
+}
+
+/// Clip the plane to the given percentiles, normalize and stretch it, and quantize to 8 bits.
+#[must_use]
+pub fn render(plane: &Plane, stretch: Stretch, clip: (f64, f64)) -> Vec<u8> {
+    let mut sorted = plane
+        .pixels
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .collect::<Vec<_>>();
+    sorted.sort_by(f64::total_cmp);
+    let low = percentile(&sorted, clip.0);
+    let high = percentile(&sorted, clip.1);
+    let range = if high > low { high - low } else { 1.0 };
+
+    plane
+        .pixels
+        .iter()
+        .map(|&value| {
+            if !value.is_finite() {
+                return 0;
+            }
+            let normalized = ((value - low) / range).clamp(0.0, 1.0);
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let value = (stretch.apply(normalized) * 255.0).round() as u8;
+            value
+        })
+        .collect()
+}
+
+/// Split an input argument like `image.fits[1]` into the path and HDU index.
+fn parse_input(input: &str) -> Result<(&str, Option<usize>), FitsError> {
+    let Some((path, hdu)) = input
+        .strip_suffix(']')
+        .and_then(|input| input.rsplit_once('['))
+    else {
+        return Ok((input, None));
+    };
+    let hdu = hdu
+        .parse()
+        .map_err(|_| FitsError::Usage(format!("Invalid HDU index in {input}")))?;
+    Ok((path, Some(hdu)))
+}
+
+fn parse_clip(clip: &str) -> Result<(f64, f64), FitsError> {
+    let invalid = || FitsError::Usage(format!("Invalid --clip: {clip}"));
+    let (low, high) = clip.split_once(',').ok_or_else(invalid)?;
+    let low = low.trim().parse::<f64>().map_err(|_| invalid())?;
+    let high = high.trim().parse::<f64>().map_err(|_| invalid())?;
+    if !(0.0..high).contains(&low) || high > 100.0 {
+        return Err(invalid());
+    }
+    Ok((low, high))
+}
+
+/// Run the `fits` subcommand with the arguments following it.
+pub fn run(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut stretch = Stretch::Asinh;
+    let mut clip = (0.5, 99.5);
+    let mut positional = vec![];
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--stretch" => {
+                stretch = args
+                    .next()
+                    .ok_or_else(|| FitsError::Usage("Missing value for --stretch".into()))?
+                    .parse()?;
+            }
+            "--clip" => {
+                clip = parse_clip(
+                    args.next()
+                        .ok_or_else(|| FitsError::Usage("Missing value for --clip".into()))?,
+                )?;
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let [url, output, inputs @ ..] = positional.as_slice() else {
+        return Err(FitsError::Usage("Missing arguments".into()).into());
+    };
+    if inputs.len() != 1 && inputs.len() != 3 {
+        return Err(FitsError::Usage("Expected one or three input files".into()).into());
+    }
+    let url = Url::parse(url)?;
+
+    let mut planes = vec![];
+    for &input in inputs {
+        let (path, hdu) = parse_input(input)?;
+        eprintln!("Reading {input} ...");
+        planes.push(read_image(&fs::read(path)?, hdu, input)?);
+    }
+    let (width, height) = (planes[0].width, planes[0].height);
+    if planes
+        .iter()
+        .any(|plane| (plane.width, plane.height) != (width, height))
+    {
+        return Err(FitsError::DimensionMismatch.into());
+    }
+
+    eprintln!("Rendering {width}x{height} image...");
+    let channels = planes
+        .iter()
+        .map(|plane| render(plane, stretch, clip))
+        .collect::<Vec<_>>();
+    let (width, height) = (u32::try_from(width)?, u32::try_from(height)?);
+    let mut png = std::io::Cursor::new(vec![]);
+    if let [gray] = channels.as_slice() {
+        image::GrayImage::from_raw(width, height, gray.clone())
+            .ok_or_else(|| FitsError::Malformed("pixel count mismatch".into()))?
+            .write_to(&mut png, image::ImageOutputFormat::Png)?;
+    } else {
+        let rgb = (0..channels[0].len())
+            .flat_map(|i| [channels[0][i], channels[1][i], channels[2][i]])
+            .collect();
+        image::RgbImage::from_raw(width, height, rgb)
+            .ok_or_else(|| FitsError::Malformed("pixel count mismatch".into()))?
+            .write_to(&mut png, image::ImageOutputFormat::Png)?;
+    }
+    let png = png.into_inner();
+    fs::write(Path::new(output), &png)?;
+    eprintln!("Wrote {output}");
+
+    let img_data = ImageData::try_from((url, png))?;
+    println!("{}", serde_json::to_string_pretty(&img_data)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_input, read_image, render, Stretch, BLOCK_SIZE, CARD_SIZE};
+
+    fn fits(cards: &[&str], data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for card in cards.iter().chain(&["END"]) {
+            bytes.extend_from_slice(format!("{card:<CARD_SIZE$}").as_bytes());
+        }
+        bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+        bytes.extend_from_slice(data);
+        bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        bytes
+    }
+
+    #[test]
+    fn reads_image_extension() {
+        let mut bytes = fits(
+            &[
+                "SIMPLE  =                    T",
+                "NAXIS   =                    0",
+            ],
+            &[],
+        );
+        let data = [1_i16, 2, 3, 4, 5, 6]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect::<Vec<_>>();
+        bytes.extend(fits(
+            &[
+                "XTENSION= 'IMAGE   '           / image extension",
+                "BITPIX  =                   16",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    3",
+                "NAXIS2  =                    2",
+                "BZERO   =                 10.0",
+            ],
+            &data,
+        ));
+
+        let plane = read_image(&bytes, None, "test.fits").expect("plane");
+        assert_eq!((plane.width, plane.height), (3, 2));
+        // bottom row is stored first, so it comes out last
+        assert_eq!(plane.pixels, [14.0, 15.0, 16.0, 11.0, 12.0, 13.0]);
+        assert!(read_image(&bytes, Some(0), "test.fits").is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_headers() {
+        for cards in [
+            // data units larger than memory
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                  -64",
+                "NAXIS   =                    3",
+                "NAXIS1  =  9223372036854775807",
+                "NAXIS2  =                    4",
+                "NAXIS3  =                    4",
+            ][..],
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                  -64",
+                "NAXIS   =                    2",
+                "NAXIS1  =  4611686018427387904",
+                "NAXIS2  =                    1",
+            ],
+            // no bytes per value
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                    0",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    1",
+                "NAXIS2  =                    1",
+            ],
+        ] {
+            let bytes = fits(cards, &[]);
+            assert!(read_image(&bytes, Some(0), "test.fits").is_err());
+            assert!(read_image(&bytes, None, "test.fits").is_err());
+        }
+    }
+
+    #[test]
+    fn clips_and_stretches() {
+        let plane = super::Plane {
+            width: 4,
+            height: 1,
+            pixels: vec![0.0, 1.0, 2.0, f64::NAN],
+        };
+        assert_eq!(
+            render(&plane, Stretch::Linear, (0.0, 100.0)),
+            [0, 128, 255, 0]
+        );
+        let stretched = render(&plane, Stretch::Asinh, (0.0, 100.0));
+        assert!(stretched[1] > 128);
+    }
+
+    #[test]
+    fn parses_hdu_suffix() {
+        assert!(matches!(parse_input("a.fits"), Ok(("a.fits", None))));
+        assert!(matches!(parse_input("a.fits[2]"), Ok(("a.fits", Some(2)))));
+        assert!(parse_input("a.fits[x]").is_err());
+    }
+}
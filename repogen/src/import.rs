@@ -0,0 +1,362 @@
+//! The `import` subcommand: find the images to add in a release feed (RSS or Atom)
+//! or an HTML gallery page instead of listing their URLs by hand, then process
+//! them like any other images.
+
+use crate::{download, generate, manifest, Errors, ImageSpec, Options};
+use image_repo::types::{ImageMetadata, SupportedFormat};
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::{error::Error, path::Path};
+use url::Url;
+
+/// An image link found in a feed or page, with whatever it said about the image.
+#[derive(Debug, PartialEq)]
+pub struct Found {
+    pub url: Url,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// How to find images in a feed or page.
+#[derive(Default)]
+pub struct Rules {
+    /// Elements of an HTML page that each stand for one image. By default, every
+    /// link is one.
+    item: Option<Selector>,
+    /// Element within an HTML item holding the image's title. By default, the
+    /// title is taken from the link's `title` or an image's `alt` attribute.
+    title: Option<Selector>,
+    /// Element within an HTML item holding the image's description.
+    description: Option<Selector>,
+    /// Links that count as images, matched against the absolute URL. By default,
+    /// links to files with an image extension count. The first matching link of
+    /// each item or feed entry is used.
+    pattern: Option<Regex>,
+}
+
+/// Parse a CSS selector given for `option`.
+fn selector(option: &str, value: &str) -> Result<Selector, Errors> {
+    Selector::parse(value).map_err(|_| Errors::InvalidOption((option.into(), value.into())))
+}
+
+/// Collapse the text of an HTML fragment into a single line, or `None` if there
+/// is none.
+fn text_of(element: ElementRef<'_>) -> Option<String> {
+    let text = element.text().collect::<Vec<_>>().join(" ");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// The text of a feed field, which may contain escaped HTML.
+fn html_text(html: &str) -> Option<String> {
+    text_of(Html::parse_fragment(html).root_element())
+}
+
+/// The element and all elements within it, in document order.
+fn elements(element: ElementRef<'_>) -> impl Iterator<Item = ElementRef<'_>> {
+    element.descendants().filter_map(ElementRef::wrap)
+}
+
+/// The `attr` attribute of the `name` elements within an element, including itself.
+fn attrs<'a>(
+    element: ElementRef<'a>,
+    name: &'a str,
+    attr: &'a str,
+) -> impl Iterator<Item = &'a str> {
+    elements(element)
+        .filter(move |element| element.value().name() == name)
+        .filter_map(move |element| element.value().attr(attr))
+}
+
+/// Links in an HTML fragment, preferring links to files over inline images,
+/// which are often thumbnails.
+fn html_links(element: ElementRef<'_>) -> Vec<&str> {
+    attrs(element, "a", "href")
+        .chain(attrs(element, "img", "src"))
+        .collect()
+}
+
+/// Whether a URL's file has the extension of a supported image format.
+fn has_image_extension(url: &Url) -> bool {
+    url.path()
+        .rsplit_once('.')
+        .and_then(|(_, extension)| SupportedFormat::from_extension(extension))
+        .is_some()
+}
+
+impl Rules {
+    /// The first of `links` that counts as an image, resolved against `base`.
+    fn image_link<'a>(&self, base: &Url, links: impl IntoIterator<Item = &'a str>) -> Option<Url> {
+        links
+            .into_iter()
+            .filter_map(|link| base.join(link.trim()).ok())
+            .find(|url| match &self.pattern {
+                Some(pattern) => pattern.is_match(url.as_str()),
+                None => has_image_extension(url),
+            })
+    }
+
+    /// Find the images in a feed or an HTML page, in order and without repeats.
+    /// Relative links are resolved against `base`, the URL of the feed or page.
+    pub fn find(&self, text: &str, base: &Url) -> Vec<Found> {
+        let xml = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..roxmltree::ParsingOptions::default()
+        };
+        let mut found = match roxmltree::Document::parse_with_options(text, xml) {
+            Ok(doc) if matches!(doc.root_element().tag_name().name(), "rss" | "feed" | "RDF") => {
+                self.find_in_feed(&doc, base)
+            }
+            _ => self.find_in_html(text, base),
+        };
+        let mut seen = std::collections::HashSet::new();
+        found.retain(|image| seen.insert(image.url.clone()));
+        found
+    }
+
+    /// Find the images in the entries of an RSS or Atom feed: their enclosures and
+    /// media content, or else the links in their descriptions.
+    fn find_in_feed(&self, doc: &roxmltree::Document<'_>, base: &Url) -> Vec<Found> {
+        let entries = doc
+            .descendants()
+            .filter(|node| matches!(node.tag_name().name(), "item" | "entry"));
+        entries
+            .filter_map(|entry| {
+                let child = |names: &[&str]| {
+                    entry
+                        .children()
+                        .find(|node| names.contains(&node.tag_name().name()))
+                        .and_then(|node| node.text())
+                };
+                let description = child(&["description", "summary", "content"]);
+                let attached = entry.children().filter_map(|node| {
+                    match (node.tag_name().name(), node.attribute("rel")) {
+                        ("enclosure" | "content", _) => node.attribute("url"),
+                        ("link", Some("enclosure")) => node.attribute("href"),
+                        _ => None,
+                    }
+                });
+                let fragment = description.map(Html::parse_fragment);
+                let linked = fragment
+                    .as_ref()
+                    .map(|html| html_links(html.root_element()))
+                    .unwrap_or_default();
+                Some(Found {
+                    url: self.image_link(base, attached.chain(linked))?,
+                    title: child(&["title"]).and_then(html_text),
+                    description: description.and_then(html_text),
+                })
+            })
+            .collect()
+    }
+
+    /// Find the images in the items of an HTML page, see [`Rules::item`].
+    fn find_in_html(&self, text: &str, base: &Url) -> Vec<Found> {
+        let html = Html::parse_document(text);
+        let root = html.root_element();
+        let base = attrs(root, "base", "href")
+            .next()
+            .and_then(|href| base.join(href).ok())
+            .unwrap_or_else(|| base.clone());
+        let items = match &self.item {
+            Some(item) => html.select(item).collect::<Vec<_>>(),
+            None => elements(root)
+                .filter(|element| element.value().name() == "a")
+                .collect(),
+        };
+        items
+            .into_iter()
+            .filter_map(|item| {
+                let within =
+                    |selector: &Option<Selector>| item.select(selector.as_ref()?).find_map(text_of);
+                let title = match &self.title {
+                    Some(_) => within(&self.title),
+                    None => item
+                        .value()
+                        .attr("title")
+                        .or_else(|| attrs(item, "img", "alt").next())
+                        .map(str::to_string),
+                };
+                Some(Found {
+                    url: self.image_link(&base, html_links(item))?,
+                    title,
+                    description: within(&self.description),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Run the `import` subcommand with the arguments following it.
+pub fn run(args: &[&str], options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut rules = Rules::default();
+    let mut into = None;
+    let mut base = None;
+    let mut source = None;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        if !arg.starts_with("--") {
+            if source.replace(arg).is_some() {
+                return Err(Errors::Usage("Expected a single feed or page to import from").into());
+            }
+            continue;
+        }
+        let value = *args
+            .next()
+            .ok_or_else(|| Errors::InvalidOption((arg.into(), String::new())))?;
+        match arg {
+            "--into" => into = Some(Path::new(value)),
+            "--base" => base = Some(Url::parse(value)?),
+            "--item" => rules.item = Some(selector(arg, value)?),
+            "--title" => rules.title = Some(selector(arg, value)?),
+            "--description" => rules.description = Some(selector(arg, value)?),
+            "--pattern" => {
+                rules.pattern = Some(
+                    Regex::new(value)
+                        .map_err(|_| Errors::InvalidOption((arg.into(), value.into())))?,
+                );
+            }
+            _ => return Err(Errors::InvalidOption((arg.into(), value.into())).into()),
+        }
+    }
+    let Some(source) = source else {
+        return Err(Errors::Usage("Expected a feed or page to import from").into());
+    };
+    let url = download::url_or_path(source)?;
+    let found = rules.find(&download::fetch(&url)?, base.as_ref().unwrap_or(&url));
+    if found.is_empty() {
+        return Err(format!("Found no images in {source}").into());
+    }
+    eprintln!("Found {} images in {source}", found.len());
+
+    let urls = found
+        .iter()
+        .map(|image| image.url.to_string())
+        .collect::<Vec<_>>();
+    let mut specs = found
+        .into_iter()
+        .zip(&urls)
+        .map(|(image, url)| ImageSpec {
+            url,
+            metadata: ImageMetadata {
+                title: image.title,
+                description: image.description,
+                ..ImageMetadata::default()
+            },
+            ..ImageSpec::default()
+        })
+        .collect::<Vec<_>>();
+    let Some(path) = into else {
+        return generate(&specs, options);
+    };
+    // importing again later only adds what was published since
+    let repo = manifest::read(path)?;
+    specs.retain(|spec| {
+        !repo
+            .images
+            .iter()
+            .any(|image| image.urls().any(|url| url.as_str() == spec.url))
+    });
+    if specs.is_empty() {
+        eprintln!("All images are already in {}", path.display());
+        return Ok(());
+    }
+    manifest::add(path, &specs, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{selector, Found, Rules};
+    use regex::Regex;
+    use url::Url;
+
+    fn found(url: &str, title: Option<&str>, description: Option<&str>) -> Found {
+        Found {
+            url: Url::parse(url).expect("url"),
+            title: title.map(str::to_string),
+            description: description.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn finds_images_in_feeds() {
+        let base = Url::parse("https://example.com/feed.xml").expect("url");
+        let rss = Rules::default().find(include_str!("../fixtures/releases.rss"), &base);
+        assert_eq!(
+            rss,
+            [
+                found(
+                    "https://example.com/files/cliffs.png",
+                    Some("Cosmic Cliffs in the Carina Nebula"),
+                    Some("The edge of a nearby, young, star-forming region. Full resolution PNG"),
+                ),
+                found(
+                    "https://example.com/files/ring.png",
+                    Some("Southern Ring Nebula"),
+                    Some("Two stars, locked in a tight orbit, shape the local scenery."),
+                ),
+            ]
+        );
+
+        let atom = Rules::default().find(include_str!("../fixtures/releases.atom"), &base);
+        assert_eq!(
+            atom,
+            [
+                found(
+                    "https://example.com/files/quintet.tif",
+                    Some("Stephan\u{2019}s Quintet"),
+                    Some("A visual grouping of five galaxies."),
+                ),
+                found(
+                    "https://example.com/files/cliffs.png",
+                    Some("Carina Nebula, again"),
+                    None,
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_images_in_html() {
+        let html = include_str!("../fixtures/gallery.html");
+        let base = Url::parse("https://example.com/gallery.html").expect("url");
+
+        // every image link, resolved against the page's <base>
+        let links = Rules::default().find(html, &base);
+        let urls = links
+            .iter()
+            .map(|image| image.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/logo.png",
+                "https://example.com/gallery/downloads/cliffs.png",
+                "https://example.com/gallery/downloads/cliffs-large.jpg",
+                "https://cdn.example.com/ring.png?download=1",
+            ]
+        );
+
+        let rules = Rules {
+            item: Some(selector("--item", ".release").expect("selector")),
+            title: Some(selector("--title", "h2").expect("selector")),
+            description: Some(selector("--description", ".caption").expect("selector")),
+            pattern: Some(Regex::new(r"\.png").expect("pattern")),
+        };
+        assert_eq!(
+            rules.find(html, &base),
+            [
+                found(
+                    "https://example.com/gallery/downloads/cliffs.png",
+                    Some("Cosmic Cliffs"),
+                    Some("The edge of a nearby, young, star-forming region."),
+                ),
+                found(
+                    "https://cdn.example.com/ring.png?download=1",
+                    Some("Southern Ring Nebula"),
+                    Some("Two stars shape the local scenery."),
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! The `lint` subcommand: check a manifest for mistakes, like `validate`, but
+//! report warnings as well as errors, each with the JSON path of the value at
+//! fault, so they're easy to find and fix or act on in CI.
+
+use crate::{download, manifest, Errors};
+use image_repo::{
+    lint::{self, Severity},
+    schema,
+};
+use std::{error::Error, fs, path::Path};
+
+/// Run the `lint` subcommand with the arguments following it.
+pub fn run(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let (json, source) = match args {
+        ["--json", source] | [source, "--json"] => (true, *source),
+        [source] => (false, *source),
+        _ => return Err(Errors::Usage("Expected a manifest and optionally --json").into()),
+    };
+    // a downloaded manifest must be where its updateUrl says it is, and a local
+    // one should at least have the same file name
+    let (repo, url) = match url::Url::parse(source) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+            (schema::from_slice(download::fetch(&url)?.as_bytes())?, url)
+        }
+        _ => {
+            let path = Path::new(source);
+            let url = url::Url::from_file_path(fs::canonicalize(path)?)
+                .map_err(|()| format!("Can't make a URL of {source}"))?;
+            (manifest::read(path)?, url)
+        }
+    };
+
+    let diagnostics = lint::lint(&repo, Some(&url));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diagnostics)?);
+    } else {
+        for diagnostic in &diagnostics {
+            println!("{}: {diagnostic}", diagnostic.severity);
+        }
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        return Err(format!("Found {errors} errors and {warnings} warnings in {source}").into());
+    }
+    if warnings > 0 {
+        eprintln!("Found {warnings} warnings in {source}");
+    } else {
+        eprintln!("{source} has no problems");
+    }
+    Ok(())
+}
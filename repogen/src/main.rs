@@ -1,25 +1,227 @@
 #![deny(clippy::all, clippy::pedantic, rust_2018_idioms, clippy::unwrap_used)]
 
 use colored_json::ToColoredJson;
-use image_repo::types::ImageData;
-use reqwest::StatusCode;
-use std::{env, error::Error, fmt::Display, io::Read};
+use download::{run_parallel, Download, Progress};
+use image_repo::{
+    types::{CropRegion, FocalPoint, ImageData, ImageMetadata, ImgError, Rect},
+    Digest, HashAlgorithm,
+};
+use std::{env, error::Error, fmt::Display, path::Path, process::ExitCode};
+use thumbnail::Thumbnails;
 use url::Url;
 
+mod check;
+mod diff;
+mod download;
+mod files;
+#[cfg(feature = "fits")]
+mod fits;
+mod import;
+mod lint;
+mod manifest;
+mod publish;
+mod sign;
+mod thumbnail;
+
+const USAGE: &str = "Usage:
+    repogen init <manifest.json> <name> [--description <text>] [--update-url <url>]
+                                             Create a new, empty repository manifest
+    repogen add [download options] <manifest.json> [[image options] url...]
+                                             Download images and append them to a manifest
+    repogen merge [download options] <manifest.json> [[image options] url...]
+                                             Download images and merge them into a manifest,
+                                             updating existing entries but keeping hand edits.
+                                             With no URLs, every image in it is regenerated
+    repogen remove <manifest.json> <url or hash>...
+                                             Remove images from a manifest
+    repogen validate <manifest.json>         Check a manifest for mistakes without downloading
+    repogen lint [--json] <manifest.json or url>
+                                             Check a manifest for errors and likely mistakes, with
+                                             the JSON path of each. A downloaded manifest's
+                                             updateUrl must point to where it was downloaded from
+    repogen diff [--json] <old.json> <new.json>
+                                             Compare two manifests image by image, ignoring order
+                                             and formatting
+    repogen verify [--jobs <n>] [--decode] <manifest.json>
+                                             Download every image and mirror in a manifest and
+                                             check them against their hash, size and dimensions
+    repogen check [--jobs <n>] [--decode] [--download] <manifest.json>
+                                             Check that every image and mirror link still works
+                                             and print a JSON report. With --download, also
+                                             download and verify each image like `verify`
+    repogen files [download options] [--into <manifest.json>] <base-url> <file or dir>...
+                                             Print image data for local files, with URLs made by
+                                             joining the base URL and each file's path relative to
+                                             the given directory. With --into, merge them into a
+                                             manifest instead, like `merge`
+    repogen import [download options] [import options] [--into <manifest.json>] <feed or page>
+                                             Find images in an RSS or Atom feed or an HTML page,
+                                             by URL or a saved file, and print their JSON data.
+                                             With --into, add the ones not in a manifest yet
+    repogen [download options] [[image options] url...]
+                                             Download images and print their JSON data
+    repogen publish <manifest.json> <output-dir>
+                                             Write a static gallery page for a manifest, with the
+                                             manifest and its thumbnails, to upload together
+    repogen keygen <key-file>                Generate a new publisher signing key
+    repogen sign <manifest.json> <key-file>  Sign a repository manifest in place
+    repogen fits [options] <url> <output.png> <input.fits>...
+                                             Render FITS data to a PNG (requires the `fits`
+                                             feature, see `repogen fits --help`)
+
+    Download options:
+        --hash <algorithm>                   One of sha256, sha512 or blake3; if omitted, a bare
+                                             SHA-256 hex digest is emitted for older app versions
+        -j, --jobs <n>                       Number of images to download and process at once
+                                             (default 4)
+        --decode                             Fully decode every image to check it isn't corrupt;
+                                             otherwise only its header is read for the dimensions
+        --colors                             Add each image's dominant colours and luminance
+        --perceptual-hash                    Add each image's perceptual hash and warn about
+                                             near-duplicates in the manifest, i.e. recompressed
+                                             or rescaled copies; crops aren't detected
+        --thumbnails                         Write a thumbnail of each image to a `thumbnails`
+                                             directory next to the manifest, and add its URL,
+                                             based on the manifest's updateUrl, and a blur hash
+
+    Import options:
+        --pattern <regex>                    Links that are images; by default, links to files
+                                             with an image extension. The first matching link of
+                                             each item or feed entry is used
+        --item <selector>                    CSS selector for the elements of a page that each
+                                             hold one image; by default, every link
+        --title <selector>                   CSS selector for the title within each item; by
+                                             default, the link's title or an image's alt text
+        --description <selector>             CSS selector for the description within each item
+        --base <url>                         URL to resolve relative links against, for saved
+                                             copies of pages
+
+    Image options apply to the URL that follows them:
+        --focal <x>,<y>                      Focal point as fractions of width and height
+        --crop <name>=<x>,<y>,<w>,<h>        Named crop region in pixels, may be repeated
+
+    Downloads go through the proxy in the HTTP_PROXY or HTTPS_PROXY environment variable,
+    if set. The app's proxy setting doesn't apply to repogen.
+
+    Exits with 0 on success, 1 if anything failed, and 2 for invalid arguments.";
+
+/// Errors from work done on other threads.
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Options for the subcommands that download images.
+// each bool is an independent command line flag
+#[allow(clippy::struct_excessive_bools)]
+struct Options {
+    hash_algorithm: Option<HashAlgorithm>,
+    jobs: usize,
+    /// Whether to fully decode images rather than only reading their headers.
+    decode: bool,
+    /// Whether to compute the images' colour statistics.
+    colors: bool,
+    /// Whether to compute the images' perceptual hashes.
+    perceptual_hash: bool,
+    /// Whether to generate thumbnails and blur hashes, see [`Thumbnails`].
+    thumbnails: bool,
+}
+
+/// Subcommands that take download options right after their name.
+const DOWNLOAD_SUBCOMMANDS: [&str; 6] = ["add", "merge", "verify", "check", "files", "import"];
+
+impl Options {
+    /// Take the download options out of the arguments, returning them and the
+    /// remaining arguments. Options are only read at the start of the arguments,
+    /// or right after the name of a subcommand that downloads, so they can still
+    /// be given as the values of other options, e.g. `init --description -j`.
+    fn parse<'a>(args: &[&'a str]) -> Result<(Options, Vec<&'a str>), Box<dyn Error>> {
+        let mut options = Options {
+            hash_algorithm: None,
+            jobs: download::DEFAULT_JOBS,
+            decode: false,
+            colors: false,
+            perceptual_hash: false,
+            thumbnails: false,
+        };
+        let rest = match options.parse_leading(args)? {
+            [subcommand, rest @ ..] if DOWNLOAD_SUBCOMMANDS.contains(subcommand) => {
+                let mut args = vec![*subcommand];
+                args.extend_from_slice(options.parse_leading(rest)?);
+                args
+            }
+            rest => rest.to_vec(),
+        };
+        Ok((options, rest))
+    }
+
+    /// Read the download options at the start of `args`, up to the first other
+    /// argument, and return the arguments after them.
+    fn parse_leading<'a, 'b>(
+        &mut self,
+        mut args: &'b [&'a str],
+    ) -> Result<&'b [&'a str], Box<dyn Error>> {
+        loop {
+            args = match args {
+                ["--decode", rest @ ..] => {
+                    self.decode = true;
+                    rest
+                }
+                ["--colors", rest @ ..] => {
+                    self.colors = true;
+                    rest
+                }
+                ["--perceptual-hash", rest @ ..] => {
+                    self.perceptual_hash = true;
+                    rest
+                }
+                ["--thumbnails", rest @ ..] => {
+                    self.thumbnails = true;
+                    rest
+                }
+                ["--hash", algorithm, rest @ ..] => {
+                    self.hash_algorithm = Some(algorithm.parse()?);
+                    rest
+                }
+                [arg @ ("-j" | "--jobs"), jobs, rest @ ..] => {
+                    self.jobs =
+                        jobs.parse().ok().filter(|&jobs| jobs > 0).ok_or_else(|| {
+                            Errors::InvalidOption(((*arg).into(), (*jobs).into()))
+                        })?;
+                    rest
+                }
+                [arg @ ("--hash" | "-j" | "--jobs")] => {
+                    return Err(Errors::InvalidOption(((*arg).into(), String::new())).into())
+                }
+                rest => return Ok(rest),
+            };
+        }
+    }
+}
+
+/// Exit code for invalid command line arguments.
+const USAGE_EXIT_CODE: u8 = 2;
+
 #[derive(Debug)]
 enum Errors {
-    HttpFailed,
+    HttpFailed(reqwest::StatusCode),
+    InvalidOption((String, String)),
+    /// The arguments don't make sense together.
+    Usage(&'static str),
+    /// Some of the images being processed failed. Contains the number that
+    /// failed, the total, and what was being done to them.
+    Failed((usize, usize, &'static str)),
 }
 
 impl Display for Errors {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Errors::HttpFailed => "Failed to download image.",
+        match self {
+            Errors::HttpFailed(status) => write!(f, "Failed to download image: HTTP {status}"),
+            Errors::InvalidOption((option, value)) => {
+                write!(f, "Invalid value for {option}: {value}")
             }
-        )
+            Errors::Usage(message) => write!(f, "{message}"),
+            Errors::Failed((failed, total, action)) => {
+                write!(f, "{failed} of {total} images failed to {action}")
+            }
+        }
     }
 }
 
@@ -29,59 +231,304 @@ impl Error for Errors {
     }
 }
 
-fn main() {
-    let mut errors: Vec<Box<dyn Error>> = vec![];
-    let mut images: Vec<ImageData> = vec![];
-    for url in env::args().skip(1) {
-        eprintln!("Downloading image {url} ...");
-        //
-        // Download image
-        //
-        let http_resp = reqwest::blocking::get(url.clone());
-        if let Err(e) = http_resp {
-            errors.push(Box::new(e));
-            continue;
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let (options, args) = match Options::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(USAGE_EXIT_CODE);
         }
-
-        let mut http_resp = http_resp.expect("HTTP Failed");
-        if http_resp.status() != StatusCode::OK {
-            errors.push(Box::new(Errors::HttpFailed));
-            continue;
+    };
+    let result = match args.as_slice() {
+        ["init", manifest, name, init_options @ ..] => {
+            manifest::init(Path::new(manifest), name, init_options)
+        }
+        ["add", manifest, args @ ..] => parse_images(args)
+            .and_then(|specs| manifest::add(Path::new(manifest), &specs, &options)),
+        ["merge", manifest, args @ ..] => parse_images(args)
+            .and_then(|specs| manifest::merge(Path::new(manifest), &specs, &options)),
+        ["remove", manifest, targets @ ..] if !targets.is_empty() => {
+            manifest::remove(Path::new(manifest), targets)
+        }
+        ["validate", manifest] => manifest::validate(Path::new(manifest)),
+        ["lint", args @ ..] => lint::run(args),
+        ["diff", args @ ..] => diff::run(args),
+        ["verify", manifest] => manifest::verify(Path::new(manifest), &options),
+        ["check", args @ ..] if !args.is_empty() => check::run(args, &options),
+        ["files", args @ ..] if !args.is_empty() => files::run(args, &options),
+        ["import", args @ ..] if !args.is_empty() => import::run(args, &options),
+        ["publish", args @ ..] => publish::run(args),
+        ["keygen", key] => sign::keygen(Path::new(key)),
+        ["sign", manifest, key] => sign::sign(Path::new(manifest), Path::new(key)),
+        #[cfg(feature = "fits")]
+        ["fits", "-h" | "--help"] => {
+            eprintln!("{}", fits::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        #[cfg(feature = "fits")]
+        ["fits", args @ ..] => fits::run(args),
+        #[cfg(not(feature = "fits"))]
+        ["fits", ..] => {
+            Err("repogen was built without FITS support; rebuild with `--features fits`".into())
         }
-        let mut img_bytes = vec![];
-        if let Err(e) = http_resp.read_to_end(&mut img_bytes) {
-            errors.push(Box::new(e));
-            continue;
+        ["-h" | "--help"] => {
+            eprintln!("{USAGE}");
+            return ExitCode::SUCCESS;
         }
+        []
+        | ["init" | "add" | "merge" | "remove" | "validate" | "verify" | "check" | "files"
+        | "import" | "keygen" | "sign", ..] => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(USAGE_EXIT_CODE);
+        }
+        args => parse_images(args).and_then(|specs| generate(&specs, &options)),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            if matches!(
+                e.downcast_ref(),
+                Some(Errors::InvalidOption(_) | Errors::Usage(_))
+            ) {
+                ExitCode::from(USAGE_EXIT_CODE)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// An image to download, with the curator's options for it.
+#[derive(Default)]
+struct ImageSpec<'a> {
+    url: &'a str,
+    focal_point: Option<FocalPoint>,
+    crops: Vec<CropRegion>,
+    /// Metadata found elsewhere, e.g. by `import`, to fill in what the image's own
+    /// metadata leaves out.
+    metadata: ImageMetadata,
+}
 
-        eprintln!("Decoding image...");
+/// Parse exactly `N` comma separated numbers, e.g. `0.3,0.6`.
+fn parse_numbers<T: std::str::FromStr, const N: usize>(
+    option: &str,
+    value: &str,
+) -> Result<[T; N], Errors> {
+    let invalid = || Errors::InvalidOption((option.into(), value.into()));
+    value
+        .split(',')
+        .map(|n| n.trim().parse().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?
+        .try_into()
+        .map_err(|_| invalid())
+}
 
-        let img_data = ImageData::try_from((Url::parse(&url).expect("Invalid URL"), img_bytes));
-        if let Err(e) = img_data {
-            errors.push(Box::new(e));
-            continue;
+/// Parse the image URLs and the options preceding each of them.
+fn parse_images<'a>(args: &[&'a str]) -> Result<Vec<ImageSpec<'a>>, Box<dyn Error>> {
+    let mut specs = vec![];
+    let mut spec = ImageSpec::default();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--focal" | "--crop" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| Errors::InvalidOption((arg.into(), String::new())))?;
+                if arg == "--focal" {
+                    let [x, y] = parse_numbers(arg, value)?;
+                    spec.focal_point = Some(FocalPoint { x, y });
+                } else {
+                    let (name, rect) = value
+                        .split_once('=')
+                        .ok_or_else(|| Errors::InvalidOption((arg.into(), (*value).into())))?;
+                    let [x, y, width, height] = parse_numbers(arg, rect)?;
+                    spec.crops.push(CropRegion {
+                        name: name.into(),
+                        rect: Rect {
+                            x,
+                            y,
+                            width,
+                            height,
+                        },
+                    });
+                }
+            }
+            url => {
+                spec.url = url;
+                specs.push(std::mem::take(&mut spec));
+            }
         }
-        let img_data = img_data.expect("Failed to decode response");
-        images.push(img_data);
+    }
+    if spec.focal_point.is_some() || !spec.crops.is_empty() {
+        return Err(
+            Errors::Usage("Image options must be followed by the URL they apply to").into(),
+        );
+    }
+    Ok(specs)
+}
 
-        eprintln!("Successfully processed image!");
+/// The optional steps that need an image's pixels, and so decode the whole image:
+/// checking its integrity, computing its colours and perceptual hash, and adding
+/// its thumbnail.
+fn analyze(
+    image: &mut ImageData,
+    bytes: &[u8],
+    options: &Options,
+    thumbnails: Option<&Thumbnails>,
+) -> Result<(), BoxError> {
+    if options.decode {
+        image_repo::decoding::check_integrity(image.format, bytes)?;
+    }
+    if options.colors {
+        match image_repo::colors::analyze(image.format, bytes) {
+            Ok(colors) => image.colors = colors,
+            // images that can't be decoded, currently AVIF, are left without
+            Err(ImgError::UnsupportedFormat(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
     }
+    if options.perceptual_hash {
+        match image_repo::perceptual::hash(image.format, bytes) {
+            Ok(hash) => image.perceptual_hash = Some(hash),
+            Err(ImgError::UnsupportedFormat(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if let Some(thumbnails) = thumbnails {
+        thumbnails.add(image, bytes)?;
+    }
+    Ok(())
+}
+
+/// A processed image. Its bytes are dropped once it's processed, keeping only
+/// their digests to compare against other images' hashes.
+struct Processed {
+    image: ImageData,
+    /// The digests of the image's bytes in each hash algorithm it's compared
+    /// against, see [`manifest::digests`].
+    digests: Vec<Digest>,
+}
+
+/// Download an image and build its [`ImageData`] with the curator's options applied,
+/// then [`analyze`] it. Its bytes are also hashed with each of the `compared`
+/// algorithms, reusing the digest computed while downloading.
+fn process(
+    spec: &ImageSpec<'_>,
+    hash_algorithm: Option<HashAlgorithm>,
+    compared: &[HashAlgorithm],
+    options: &Options,
+    thumbnails: Option<&Thumbnails>,
+    progress: &Progress,
+) -> Result<Processed, BoxError> {
+    let url = Url::parse(spec.url)?;
+    let algorithm = hash_algorithm.unwrap_or(HashAlgorithm::Sha256);
+    let Download { bytes, digest } = download::download(&url, algorithm, progress)?;
+    // without an explicit algorithm, emit a bare digest that older app versions understand
+    let hash = if hash_algorithm.is_some() {
+        digest.to_string()
+    } else {
+        digest.hex.clone()
+    };
 
-    let json = serde_json::to_string_pretty(&images).expect("Failed to serialize image data.");
+    let mut img_data = ImageData::with_hash(url, &bytes, hash)?;
+    img_data.focal_point = spec.focal_point;
+    img_data.crops.clone_from(&spec.crops);
+    manifest::fill_metadata(&mut img_data.metadata, spec.metadata.clone());
+    image_repo::crop::check(&img_data)?;
+    analyze(&mut img_data, &bytes, options, thumbnails)?;
+    let digests = manifest::digests(digest, compared, &bytes);
+
+    progress.println(&format!("Processed {}", spec.url));
+    Ok(Processed {
+        image: img_data,
+        digests,
+    })
+}
+
+/// Print the failures, if any, and turn them into an [`Errors::Failed`].
+fn report_failures(
+    failures: &[(String, BoxError)],
+    total: usize,
+    action: &'static str,
+) -> Result<(), Box<dyn Error>> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    for (url, error) in failures {
+        eprintln!("    {url}: {error}");
+    }
+    Err(Errors::Failed((failures.len(), total, action)).into())
+}
+
+/// Process images and print their JSON data to paste into a manifest by hand.
+fn generate(specs: &[ImageSpec<'_>], options: &Options) -> Result<(), Box<dyn Error>> {
+    if options.thumbnails {
+        return Err(Errors::Usage("--thumbnails needs a manifest to write them next to").into());
+    }
+    let results = run_parallel(specs, options.jobs, "Processing", |spec, progress| {
+        process(spec, options.hash_algorithm, &[], options, None, progress)
+    });
+    let mut failures = vec![];
+    let mut images = vec![];
+    for (spec, result) in specs.iter().zip(results) {
+        match result {
+            Ok(processed) => images.push(processed.image),
+            Err(e) => failures.push((spec.url.to_string(), e)),
+        }
+    }
+
+    print_images(&images)?;
+    report_failures(&failures, specs.len(), "process")
+}
+
+/// Print image data as colored JSON, to paste into a manifest by hand.
+fn print_images(images: &[ImageData]) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string_pretty(images)?;
 
     #[cfg(target_os = "windows")]
     let _ = colored_json::enable_ansi_support();
 
-    println!(
-        "{}",
-        json.to_colored_json_auto()
-            .expect("Failed to init output colorizer.")
-    );
+    println!("{}", json.to_colored_json_auto()?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+    use image_repo::HashAlgorithm;
 
-    if !errors.is_empty() {
-        eprintln!("{} images failed to process:", errors.len());
-        for error in &errors {
-            eprintln!("    {error}");
-        }
+    #[test]
+    fn only_parses_leading_options() {
+        let (options, rest) = Options::parse(&[
+            "--decode",
+            "add",
+            "-j",
+            "2",
+            "--hash",
+            "blake3",
+            "repo.json",
+            "--colors",
+            "https://example.com/a.png",
+        ])
+        .expect("options");
+        assert!(options.decode && !options.colors);
+        assert_eq!(options.jobs, 2);
+        assert_eq!(options.hash_algorithm, Some(HashAlgorithm::Blake3));
+        assert_eq!(
+            rest,
+            ["add", "repo.json", "--colors", "https://example.com/a.png"]
+        );
+
+        let (options, rest) =
+            Options::parse(&["init", "repo.json", "Test", "--description", "--thumbnails"])
+                .expect("options");
+        assert!(!options.thumbnails);
+        assert_eq!(rest.len(), 5);
+
+        assert!(Options::parse(&["verify", "--jobs"]).is_err());
     }
 }
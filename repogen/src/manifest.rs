@@ -0,0 +1,776 @@
+//! Subcommands that create, edit and check a repository manifest file in place.
+
+use crate::{
+    download::{self, run_parallel, Download},
+    process, report_failures, BoxError, Errors, ImageSpec, Options, Processed, Thumbnails,
+};
+use image_repo::{
+    lint::{self, Severity},
+    schema::{self, CURRENT_SCHEMA_VERSION},
+    signing,
+    types::{ChecksumError, ImageData, ImageMetadata, ImageRepo},
+    Digest, HashAlgorithm,
+};
+use serde_json::{Map, Value};
+use std::{error::Error, fs, path::Path};
+use url::Url;
+
+/// Read a manifest, upgrading it to the current schema version.
+pub fn read(path: &Path) -> Result<ImageRepo, Box<dyn Error>> {
+    Ok(schema::from_slice(&fs::read(path)?)?)
+}
+
+/// Write a manifest back in place. Any signature no longer matches the edited
+/// contents, so it is dropped.
+fn write(path: &Path, repo: &mut ImageRepo) -> Result<(), Box<dyn Error>> {
+    if repo.signature.take().is_some() {
+        eprintln!(
+            "Removed the signature from {}; sign it again with `repogen sign`.",
+            path.display()
+        );
+    }
+    fs::write(path, serde_json::to_string_pretty(repo)? + "\n")?;
+    Ok(())
+}
+
+/// Create a new manifest with no images. Refuses to overwrite an existing file.
+pub fn init(path: &Path, name: &str, options: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut repo = ImageRepo {
+        schema_version: CURRENT_SCHEMA_VERSION,
+        name: name.into(),
+        description: None,
+        update_url: None,
+        images: vec![],
+        includes: vec![],
+        mirrors: vec![],
+        signature: None,
+        extra: Map::new(),
+    };
+    let mut options = options.iter();
+    while let Some(&option) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| Errors::InvalidOption((option.into(), String::new())))?;
+        match option {
+            "--description" => repo.description = Some((*value).into()),
+            "--update-url" => repo.update_url = Some(Url::parse(value)?),
+            _ => return Err(Errors::InvalidOption((option.into(), (*value).into())).into()),
+        }
+    }
+
+    if path.exists() {
+        return Err(format!("Refusing to overwrite existing file {}", path.display()).into());
+    }
+    write(path, &mut repo)?;
+    eprintln!("Created {}", path.display());
+    Ok(())
+}
+
+/// Whether `target` is the URL or hash of `image`. Hashes match regardless of
+/// whether they're written with an algorithm prefix.
+fn matches(image: &ImageData, target: &str) -> bool {
+    image.url.as_str() == target
+        || image.hash == target
+        || Digest::parse(target).is_ok_and(|digest| image.digest().is_ok_and(|d| d == digest))
+}
+
+/// The hash algorithms used by the manifest's images.
+fn algorithms(repo: &ImageRepo) -> Vec<HashAlgorithm> {
+    let mut algorithms = vec![];
+    for digest in repo.images.iter().filter_map(|image| image.digest().ok()) {
+        if !algorithms.contains(&digest.algorithm) {
+            algorithms.push(digest.algorithm);
+        }
+    }
+    algorithms
+}
+
+/// The hash algorithms to hash processed images with, to compare them against
+/// the manifest's images and against other new images.
+pub fn compared_algorithms(repo: &ImageRepo, options: &Options) -> Vec<HashAlgorithm> {
+    let mut algorithms = algorithms(repo);
+    let new = options.hash_algorithm.unwrap_or(HashAlgorithm::Sha256);
+    if !algorithms.contains(&new) {
+        algorithms.push(new);
+    }
+    algorithms
+}
+
+/// The digests of `bytes` in each of `algorithms`, to compare against every image
+/// using one of them without hashing again for each. `digest` was already
+/// computed, so its algorithm isn't hashed again either.
+pub fn digests(digest: Digest, algorithms: &[HashAlgorithm], bytes: &[u8]) -> Vec<Digest> {
+    let mut digests = algorithms
+        .iter()
+        .filter(|&&algorithm| algorithm != digest.algorithm)
+        .map(|&algorithm| Digest::compute(algorithm, bytes))
+        .collect::<Vec<_>>();
+    digests.push(digest);
+    digests
+}
+
+/// Whether `image`'s hash is one of `digests`.
+fn is_same(image: &ImageData, digests: &[Digest]) -> bool {
+    image.digest().is_ok_and(|digest| digests.contains(&digest))
+}
+
+/// The image in the manifest whose hash is one of `digests`, whatever algorithm it uses.
+fn find_same<'a>(repo: &'a ImageRepo, digests: &[Digest]) -> Option<&'a ImageData> {
+    repo.images.iter().find(|image| is_same(image, digests))
+}
+
+/// The `generated` hash written in the same form as the `existing` one, so a
+/// manifest of bare SHA-256 digests doesn't gain prefixed ones unless the
+/// algorithm changes.
+fn in_form_of(existing: &str, generated: String) -> String {
+    match (Digest::parse(existing), Digest::parse(&generated)) {
+        (Ok(old), Ok(new)) if old.algorithm == new.algorithm && !existing.contains(':') => new.hex,
+        _ => generated,
+    }
+}
+
+/// Pairs of images in the manifest that are perceptually the same picture, as
+/// `(i, j, distance)` indices into its images with `j < i` if both are `new`.
+/// Only pairs with at least one `new` image are returned, and images without a
+/// perceptual hash are never near-duplicates.
+fn near_duplicates(repo: &ImageRepo, new: impl Fn(&ImageData) -> bool) -> Vec<(usize, usize, u32)> {
+    let hashes = repo
+        .images
+        .iter()
+        .enumerate()
+        .filter_map(|(i, image)| Some((i, image.perceptual_hash?, new(image))))
+        .collect::<Vec<_>>();
+    let mut pairs = vec![];
+    for &(i, hash, _) in hashes.iter().filter(|(_, _, is_new)| *is_new) {
+        for &(j, other, other_is_new) in &hashes {
+            if j != i && !(other_is_new && j > i) && hash.is_near_duplicate(other) {
+                pairs.push((i, j, hash.distance(other)));
+            }
+        }
+    }
+    pairs
+}
+
+/// Warn about images at `urls` that look like other images in the manifest.
+/// These aren't errors, since a curator may well want a recompressed copy too.
+fn warn_near_duplicates(repo: &ImageRepo, urls: &[&str]) {
+    let pairs = near_duplicates(repo, |image| urls.contains(&image.url.as_str()));
+    for (i, j, distance) in pairs {
+        eprintln!(
+            "Warning: {} looks like {} (perceptual hash distance {distance})",
+            repo.images[i].url, repo.images[j].url
+        );
+    }
+}
+
+/// Download images and append them to the manifest. Images that are already in
+/// the manifest are skipped. Successfully processed images are written even if
+/// others fail.
+pub fn add(path: &Path, specs: &[ImageSpec<'_>], options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut repo = read(path)?;
+    let mut failures = vec![];
+    let (known, new): (Vec<_>, Vec<_>) = specs
+        .iter()
+        .partition(|spec| repo.images.iter().any(|image| matches(image, spec.url)));
+    for spec in known {
+        failures.push((spec.url.to_string(), "Already in the manifest".into()));
+    }
+
+    let thumbnails = Thumbnails::for_manifest(path, &repo, options)?;
+    let compared = compared_algorithms(&repo, options);
+    let results = run_parallel(&new, options.jobs, "Adding", |spec, progress| {
+        let algorithm = options.hash_algorithm;
+        process(
+            spec,
+            algorithm,
+            &compared,
+            options,
+            thumbnails.as_ref(),
+            progress,
+        )
+    });
+    let mut added = 0;
+    for (spec, result) in new.iter().zip(results) {
+        match result {
+            Ok(Processed { image, digests }) => {
+                if let Some(existing) = find_same(&repo, &digests) {
+                    failures.push((
+                        spec.url.to_string(),
+                        format!("Same image as {}", existing.url).into(),
+                    ));
+                } else {
+                    repo.images.push(image);
+                    added += 1;
+                }
+            }
+            Err(e) => failures.push((spec.url.to_string(), e)),
+        }
+    }
+
+    if added > 0 {
+        write(path, &mut repo)?;
+        eprintln!("Added {added} images to {}", path.display());
+        let urls = new.iter().map(|spec| spec.url).collect::<Vec<_>>();
+        warn_near_duplicates(&repo, &urls);
+    }
+    report_failures(&failures, specs.len(), "add")
+}
+
+/// What merging a freshly generated image into a manifest did.
+#[derive(Debug, PartialEq, Eq)]
+enum Merged {
+    Added,
+    /// An existing image with the same URL was updated. Contains the names of
+    /// the fields that changed, if any.
+    Updated(Vec<&'static str>),
+    /// The same image is already in the manifest under a different URL, at
+    /// this index; the URL was added to its mirrors.
+    Mirror(usize),
+}
+
+/// Fill in the metadata fields the curator left empty. Fields they set are kept.
+pub fn fill_metadata(metadata: &mut ImageMetadata, generated: ImageMetadata) -> bool {
+    let before = metadata.clone();
+    metadata.title = metadata.title.take().or(generated.title);
+    metadata.description = metadata.description.take().or(generated.description);
+    metadata.credit = metadata.credit.take().or(generated.credit);
+    metadata.coordinates = metadata.coordinates.or(generated.coordinates);
+    if metadata.subjects.is_empty() {
+        metadata.subjects = generated.subjects;
+    }
+    if metadata.spectral_bands.is_empty() {
+        metadata.spectral_bands = generated.spectral_bands;
+    }
+    *metadata != before
+}
+
+/// Merge a freshly generated image into the manifest, given the `digests` of its
+/// bytes in the manifest's hash algorithms. Only the fields `repogen` derives
+/// from the file itself are updated; anything the curator added by hand is kept,
+/// except for focal points and crops given on the command line.
+fn merge_image(
+    repo: &mut ImageRepo,
+    spec: &ImageSpec<'_>,
+    generated: ImageData,
+    digests: &[Digest],
+) -> Result<Merged, BoxError> {
+    let same_image = |image: &ImageData| is_same(image, digests);
+
+    if let Some(existing) = repo.images.iter_mut().find(|i| i.url == generated.url) {
+        let mut changed = vec![];
+        if !same_image(existing) {
+            existing.hash = in_form_of(&existing.hash, generated.hash);
+            changed.push("hash");
+        }
+        if (existing.width, existing.height) != (generated.width, generated.height) {
+            (existing.width, existing.height) = (generated.width, generated.height);
+            changed.push("dimensions");
+        }
+        if existing.format != generated.format {
+            existing.format = generated.format;
+            changed.push("format");
+        }
+        if existing.size != generated.size {
+            existing.size = generated.size;
+            changed.push("size");
+        }
+        if fill_metadata(&mut existing.metadata, generated.metadata) {
+            changed.push("metadata");
+        }
+        if generated.colors.is_some() && existing.colors != generated.colors {
+            existing.colors = generated.colors;
+            changed.push("colors");
+        }
+        if generated.perceptual_hash.is_some()
+            && existing.perceptual_hash != generated.perceptual_hash
+        {
+            existing.perceptual_hash = generated.perceptual_hash;
+            changed.push("perceptual hash");
+        }
+        // only generated with --thumbnails, so keep what's there otherwise
+        if generated.thumbnail_url.is_some()
+            && (existing.thumbnail_url != generated.thumbnail_url
+                || existing.blur_hash != generated.blur_hash)
+        {
+            existing.thumbnail_url = generated.thumbnail_url;
+            existing.blur_hash = generated.blur_hash;
+            changed.push("thumbnail");
+        }
+        if spec.focal_point.is_some() && existing.focal_point != spec.focal_point {
+            existing.focal_point = spec.focal_point;
+            changed.push("focal point");
+        }
+        if !spec.crops.is_empty() && existing.crops != spec.crops {
+            existing.crops.clone_from(&spec.crops);
+            changed.push("crops");
+        }
+        return Ok(Merged::Updated(changed));
+    }
+
+    if let Some(i) = repo
+        .images
+        .iter()
+        .position(|i| i.mirrors.contains(&generated.url))
+    {
+        if !same_image(&repo.images[i]) {
+            return Err(format!("Is a mirror of images[{i}], but serves a different image").into());
+        }
+        // already known as a mirror, so there's nothing to update
+        return Ok(Merged::Updated(vec![]));
+    }
+    if let Some(i) = repo.images.iter().position(same_image) {
+        repo.images[i].mirrors.push(generated.url);
+        return Ok(Merged::Mirror(i));
+    }
+
+    repo.images.push(generated);
+    Ok(Merged::Added)
+}
+
+/// Regenerate images and merge them into the manifest, see [`merge_image`]. With
+/// no images given, every image already in the manifest is regenerated. Existing
+/// images keep their hash algorithm unless another is given.
+pub fn merge(
+    path: &Path,
+    specs: &[ImageSpec<'_>],
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let repo = read(path)?;
+    let existing_urls = repo
+        .images
+        .iter()
+        .map(|image| image.url.to_string())
+        .collect::<Vec<_>>();
+    let all_images = existing_urls
+        .iter()
+        .map(|url| ImageSpec {
+            url: url.as_str(),
+            ..ImageSpec::default()
+        })
+        .collect::<Vec<_>>();
+    let specs = if specs.is_empty() { &all_images } else { specs };
+
+    let algorithms = specs
+        .iter()
+        .map(|spec| hash_algorithm_for(&repo, spec.url, options))
+        .collect::<Vec<_>>();
+    let jobs = specs.iter().zip(algorithms).collect::<Vec<_>>();
+    let thumbnails = Thumbnails::for_manifest(path, &repo, options)?;
+    let compared = compared_algorithms(&repo, options);
+    let results = run_parallel(
+        &jobs,
+        options.jobs,
+        "Merging",
+        |(spec, algorithm), progress| {
+            process(
+                spec,
+                *algorithm,
+                &compared,
+                options,
+                thumbnails.as_ref(),
+                progress,
+            )
+        },
+    );
+    merge_results(path, repo, specs, results)
+}
+
+/// The hash algorithm to use for the image at `url` when merging: the one given
+/// on the command line, or else the one the image already uses in the manifest.
+pub fn hash_algorithm_for(repo: &ImageRepo, url: &str, options: &Options) -> Option<HashAlgorithm> {
+    options.hash_algorithm.or_else(|| {
+        repo.images
+            .iter()
+            .find(|image| image.url.as_str() == url)
+            .and_then(|image| image.digest().ok())
+            .map(|digest| digest.algorithm)
+    })
+}
+
+/// Merge processed images into the manifest with [`merge_image`] and write it
+/// if anything changed. `results` are in the same order as `specs`, and their
+/// digests must cover [`compared_algorithms`].
+pub fn merge_results(
+    path: &Path,
+    mut repo: ImageRepo,
+    specs: &[ImageSpec<'_>],
+    results: Vec<Result<Processed, BoxError>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut failures = vec![];
+    let mut modified = false;
+    for (spec, result) in specs.iter().zip(results) {
+        let merged = result
+            .and_then(|Processed { image, digests }| merge_image(&mut repo, spec, image, &digests));
+        match merged {
+            Ok(Merged::Added) => {
+                eprintln!("Added {}", spec.url);
+                modified = true;
+            }
+            Ok(Merged::Updated(changed)) if changed.is_empty() => {
+                eprintln!("Unchanged {}", spec.url);
+            }
+            Ok(Merged::Updated(changed)) => {
+                eprintln!("Updated {} of {}", changed.join(", "), spec.url);
+                modified = true;
+            }
+            Ok(Merged::Mirror(i)) => {
+                eprintln!(
+                    "{} is the same image as {}, kept as a mirror",
+                    spec.url, repo.images[i].url
+                );
+                modified = true;
+            }
+            Err(e) => failures.push((spec.url.to_string(), e)),
+        }
+    }
+
+    if modified {
+        write(path, &mut repo)?;
+    }
+    let urls = specs.iter().map(|spec| spec.url).collect::<Vec<_>>();
+    warn_near_duplicates(&repo, &urls);
+    report_failures(&failures, specs.len(), "merge")
+}
+
+/// Remove images by URL or hash. Nothing is written unless every target matches.
+pub fn remove(path: &Path, targets: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut repo = read(path)?;
+    let missing = targets
+        .iter()
+        .filter(|target| !repo.images.iter().any(|image| matches(image, target)))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        for target in &missing {
+            eprintln!("    {target}: Not in the manifest");
+        }
+        return Err(Errors::Failed((missing.len(), targets.len(), "remove")).into());
+    }
+
+    let before = repo.images.len();
+    repo.images
+        .retain(|image| !targets.iter().any(|target| matches(image, target)));
+    write(path, &mut repo)?;
+    eprintln!(
+        "Removed {} images from {}",
+        before - repo.images.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Find mistakes in a manifest that can be spotted without downloading anything
+/// and make it unusable, see [`lint::lint`]. Lesser problems are left to `lint`.
+fn problems(repo: &ImageRepo) -> Vec<String> {
+    lint::lint(repo, None)
+        .into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| diagnostic.to_string())
+        .collect()
+}
+
+/// Check the manifest's schema, signature and images without downloading anything.
+pub fn validate(path: &Path) -> Result<(), Box<dyn Error>> {
+    let doc = serde_json::from_slice::<Value>(&fs::read(path)?)?;
+    let mut problems = vec![];
+    match signing::verify(&doc) {
+        Ok(Some(key)) => eprintln!("Signed with publisher key {key}"),
+        Ok(None) => {}
+        Err(e) => problems.push(format!("signature: {e}")),
+    }
+    let repo = schema::from_value(doc)?;
+    problems.extend(self::problems(&repo));
+
+    for (i, j, distance) in near_duplicates(&repo, |_| true) {
+        eprintln!(
+            "Warning: images[{i}] looks like images[{j}] (perceptual hash distance {distance})"
+        );
+    }
+
+    if problems.is_empty() {
+        eprintln!("{} is valid", path.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("    {problem}");
+    }
+    Err(format!("Found {} problems in {}", problems.len(), path.display()).into())
+}
+
+/// Check a downloaded image against its declared hash and size, and if
+/// `check_contents` is set, its format and dimensions. The dimensions are read
+/// from the header unless `decode` is set, which also checks the pixel data.
+pub fn verify_image(
+    image: &ImageData,
+    download: &Download,
+    check_contents: bool,
+    decode: bool,
+) -> Result<(), BoxError> {
+    let expected = image.digest()?;
+    if download.digest != expected {
+        return Err(
+            ChecksumError::NoMatch((image.hash.clone(), download.digest.to_string())).into(),
+        );
+    }
+    let bytes = download.bytes.as_slice();
+    let size = u64::try_from(bytes.len())?;
+    if let Some(expected) = image.size.filter(|&expected| expected != size) {
+        return Err(format!("Size is {size} bytes, expected {expected}").into());
+    }
+    if check_contents {
+        let format = image_repo::decoding::detect_format(bytes)?;
+        if format != image.format {
+            return Err(format!("Format is {format}, expected {}", image.format).into());
+        }
+        let (width, height) = if decode {
+            image_repo::decoding::check_integrity(format, bytes)?
+        } else {
+            image_repo::decoding::dimensions(format, bytes)?
+        };
+        if (width, height) != (image.width, image.height) {
+            return Err(format!(
+                "Dimensions are {width}x{height}, expected {}x{}",
+                image.width, image.height
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Download every image in the manifest from every URL it's listed at, including
+/// mirrors, and check each against the manifest.
+pub fn verify(path: &Path, options: &Options) -> Result<(), Box<dyn Error>> {
+    let repo = read(path)?;
+    let images = repo.images_with_mirrors();
+    // mirrors serve the same bytes once the checksum matches, so only check contents once
+    let urls = images
+        .iter()
+        .flat_map(|image| {
+            image
+                .urls()
+                .enumerate()
+                .map(move |(i, url)| (image, url, i == 0))
+        })
+        .collect::<Vec<_>>();
+    let results = run_parallel(
+        &urls,
+        options.jobs,
+        "Verifying",
+        |(image, url, check_contents), progress| {
+            let algorithm = image.digest()?.algorithm;
+            let download = download::download(url, algorithm, progress)?;
+            verify_image(image, &download, *check_contents, options.decode)
+        },
+    );
+
+    let failures = urls
+        .iter()
+        .zip(results)
+        .filter_map(|((_, url, _), result)| Some((url.to_string(), result.err()?)))
+        .collect::<Vec<_>>();
+    if failures.is_empty() {
+        eprintln!("All {} images verified", urls.len());
+    }
+    report_failures(&failures, urls.len(), "verify")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{algorithms, digests, matches, merge_image, near_duplicates, problems, Merged};
+    use crate::ImageSpec;
+    use image_repo::{
+        types::{FocalPoint, ImageData, ImageRepo},
+        Digest, HashAlgorithm,
+    };
+
+    const SHA256: &str = "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb";
+    const BLAKE3: &str = "blake3:51424140b70a97fb4095ede915f4b88506d15a7520850cbf2daf7a8a8d9f57de";
+
+    #[test]
+    fn merges_without_clobbering_curator_edits() {
+        let mut repo = serde_json::from_str::<ImageRepo>(
+            r#"{
+                "name": "Test",
+                "images": [
+                    {
+                        "url": "https://example.com/a.png",
+                        "hash": "blake3:0000000000000000000000000000000000000000000000000000000000000000",
+                        "width": 100,
+                        "height": 100,
+                        "format": "png",
+                        "metadata": { "title": "Curated title" },
+                        "focalPoint": { "x": 0.2, "y": 0.8 },
+                        "curatorNote": "keep me"
+                    }
+                ]
+            }"#,
+        )
+        .expect("repo");
+        let generated = |url: &str, hash: &str| {
+            serde_json::from_value::<ImageData>(serde_json::json!({
+                "url": url,
+                "hash": hash,
+                "width": 460,
+                "height": 307,
+                "format": "png",
+                "metadata": { "title": "Embedded title", "credit": "Embedded credit" }
+            }))
+            .expect("image")
+        };
+        let spec = ImageSpec::default();
+        let ferris = include_bytes!("../../image-repo/ferris.png");
+
+        let ferris_digests = digests(
+            Digest::compute(HashAlgorithm::Sha256, ferris),
+            &algorithms(&repo),
+            ferris,
+        );
+        let merged = merge_image(
+            &mut repo,
+            &spec,
+            generated("https://example.com/a.png", SHA256),
+            &ferris_digests,
+        );
+        assert_eq!(
+            merged.expect("merged"),
+            Merged::Updated(vec!["hash", "dimensions", "metadata"])
+        );
+        let image = &repo.images[0];
+        assert_eq!((image.width, image.height), (460, 307));
+        assert_eq!(image.metadata.title.as_deref(), Some("Curated title"));
+        assert_eq!(image.metadata.credit.as_deref(), Some("Embedded credit"));
+        assert_eq!(image.focal_point, Some(FocalPoint { x: 0.2, y: 0.8 }));
+        assert_eq!(image.extra["curatorNote"], "keep me");
+
+        let ferris_digests = digests(
+            Digest::compute(HashAlgorithm::Sha256, ferris),
+            &algorithms(&repo),
+            ferris,
+        );
+        let merged = merge_image(
+            &mut repo,
+            &spec,
+            generated("https://mirror.example.com/a.png", BLAKE3),
+            &ferris_digests,
+        );
+        assert_eq!(merged.expect("merged"), Merged::Mirror(0));
+        assert_eq!(repo.images.len(), 1);
+        assert_eq!(
+            repo.images[0].mirrors[0].as_str(),
+            "https://mirror.example.com/a.png"
+        );
+        let merged = merge_image(
+            &mut repo,
+            &spec,
+            generated("https://mirror.example.com/a.png", BLAKE3),
+            &ferris_digests,
+        );
+        assert_eq!(merged.expect("merged"), Merged::Updated(vec![]));
+    }
+
+    #[test]
+    fn keeps_bare_hashes_bare() {
+        let mut repo = serde_json::from_str::<ImageRepo>(&format!(
+            r#"{{"name": "Test", "images": [{{"url": "https://example.com/a.png",
+                "hash": "{}", "width": 460, "height": 307, "format": "png"}}]}}"#,
+            "0".repeat(64)
+        ))
+        .expect("repo");
+        let generated = serde_json::from_value::<ImageData>(serde_json::json!({
+            "url": "https://example.com/a.png",
+            "hash": format!("sha256:{SHA256}"),
+            "width": 460,
+            "height": 307,
+            "format": "png"
+        }))
+        .expect("image");
+        let ferris = include_bytes!("../../image-repo/ferris.png");
+
+        let ferris_digests = digests(
+            Digest::compute(HashAlgorithm::Sha256, ferris),
+            &algorithms(&repo),
+            ferris,
+        );
+        let merged = merge_image(&mut repo, &ImageSpec::default(), generated, &ferris_digests);
+        assert_eq!(merged.expect("merged"), Merged::Updated(vec!["hash"]));
+        assert_eq!(repo.images[0].hash, SHA256);
+    }
+
+    #[test]
+    fn finds_duplicates_and_bad_hashes() {
+        let repo = serde_json::from_str::<ImageRepo>(
+            r#"{
+                "name": "Test",
+                "images": [
+                    {
+                        "url": "https://example.com/a.png",
+                        "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                        "width": 460,
+                        "height": 307,
+                        "format": "png"
+                    },
+                    {
+                        "url": "https://example.com/b.png",
+                        "hash": "sha256:b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                        "width": 460,
+                        "height": 307,
+                        "format": "png",
+                        "mirrors": ["https://example.com/a.png"]
+                    },
+                    {
+                        "url": "https://example.com/c.png",
+                        "hash": "md5:abc",
+                        "width": 460,
+                        "height": 307,
+                        "format": "png"
+                    }
+                ]
+            }"#,
+        )
+        .expect("repo");
+
+        assert_eq!(
+            problems(&repo),
+            [
+                "$.images[1].hash: Same hash as $.images[0]",
+                "$.images[1].mirrors[0]: https://example.com/a.png is also used at $.images[0].url",
+                "$.images[2].hash: Unsupported hash algorithm: md5",
+            ]
+        );
+        assert!(matches(
+            &repo.images[0],
+            "sha256:b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb"
+        ));
+        assert!(matches(&repo.images[1], "https://example.com/b.png"));
+        assert!(!matches(&repo.images[2], "https://example.com/a.png"));
+    }
+
+    #[test]
+    fn finds_near_duplicates() {
+        let image = |name: &str, perceptual_hash: &str| {
+            format!(
+                r#"{{"url": "https://example.com/{name}.png", "hash": "{SHA256}", "width": 1,
+                    "height": 1, "format": "png"{perceptual_hash}}}"#
+            )
+        };
+        let images = [
+            image("a", r#", "perceptualHash": "ff00ff00ff00ff00""#),
+            image("b", r#", "perceptualHash": "0f0f0f0f0f0f0f0f""#),
+            image("c", ""),
+            // 3 bits away from a
+            image("d", r#", "perceptualHash": "ff00ff00ff00ff07""#),
+        ];
+        let repo = serde_json::from_str::<ImageRepo>(&format!(
+            r#"{{"name": "Test", "images": [{}]}}"#,
+            images.join(",")
+        ))
+        .expect("repo");
+
+        assert_eq!(near_duplicates(&repo, |_| true), [(3, 0, 3)]);
+        assert_eq!(
+            near_duplicates(&repo, |image| image.url.as_str().ends_with("a.png")),
+            [(0, 3, 3)]
+        );
+        assert!(near_duplicates(&repo, |image| image.perceptual_hash.is_none()).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+//! The `publish` subcommand: render a manifest into a static gallery page, and
+//! put the manifest and its thumbnails next to it, ready to upload to any static
+//! host.
+
+use crate::{
+    manifest,
+    thumbnail::{self, Thumbnails},
+    Errors,
+};
+use image_repo::{deep_link, types::ImageRepo};
+use indicatif::HumanBytes;
+use std::{error::Error, fmt::Write, fs, path::Path};
+
+/// Styles for the gallery page, kept inline so the page is a single file.
+const STYLE: &str = "
+    body { margin: 0; font-family: system-ui, sans-serif; background: #0b0d17; color: #e8e8f0; }
+    header { padding: 2rem; }
+    h1 { margin: 0 0 0.5rem; }
+    a { color: #9ab8ff; }
+    .button { display: inline-block; padding: 0.5rem 1rem; border-radius: 0.5rem;
+        background: #3b5bdb; color: #fff; text-decoration: none; }
+    main { display: grid; gap: 1.5rem; padding: 0 2rem 2rem;
+        grid-template-columns: repeat(auto-fill, minmax(18rem, 1fr)); }
+    figure { margin: 0; }
+    figure img, .placeholder { display: block; width: 100%; height: auto; border-radius: 0.5rem; }
+    figcaption { display: flex; flex-direction: column; gap: 0.25rem; padding-top: 0.5rem; }
+    .credit, .details { font-size: 0.85rem; color: #a0a0b8; }";
+
+/// Escape text for use in HTML content and quoted attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the gallery page of a repository. `manifest` is the link to the
+/// manifest, and `thumbnails` the `src` of each image's thumbnail, if it has one.
+/// Images without a thumbnail are shown as a box in their dominant colour.
+fn render(repo: &ImageRepo, manifest: &str, thumbnails: &[Option<String>]) -> String {
+    let name = escape(&repo.name);
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{name}</title>\n<style>{STYLE}\n</style>\n</head>\n<body>\n<header>\n<h1>{name}</h1>\n"
+    );
+    if let Some(description) = &repo.description {
+        let _ = writeln!(page, "<p>{}</p>", escape(description));
+    }
+    page.push_str("<p>");
+    if let Some(update_url) = &repo.update_url {
+        let link = deep_link::add_repository(update_url);
+        let _ = write!(
+            page,
+            "<a class=\"button\" href=\"{}\">Add to jdt</a> ",
+            escape(&link)
+        );
+    }
+    let _ = writeln!(
+        page,
+        "<a href=\"{}\">Manifest JSON</a></p>",
+        escape(manifest)
+    );
+    let total = repo
+        .images
+        .iter()
+        .filter_map(|image| image.size)
+        .sum::<u64>();
+    let at_least = if repo.images.iter().all(|image| image.size.is_some()) {
+        ""
+    } else {
+        "at least "
+    };
+    let _ = writeln!(
+        page,
+        "<p>{} images, {at_least}{}</p>\n</header>\n<main>",
+        repo.images.len(),
+        HumanBytes(total)
+    );
+
+    for (image, thumbnail) in repo.images.iter().zip(thumbnails) {
+        let file_name = image
+            .url
+            .path_segments()
+            .and_then(Iterator::last)
+            .unwrap_or_default();
+        let title = escape(image.metadata.title.as_deref().unwrap_or(file_name));
+        let (width, height) = (image.width, image.height);
+        let preview = if let Some(src) = thumbnail {
+            format!(
+                "<img src=\"{}\" alt=\"{title}\" width=\"{width}\" height=\"{height}\" loading=\"lazy\">",
+                escape(src)
+            )
+        } else {
+            let color = image
+                .colors
+                .as_ref()
+                .and_then(|colors| colors.palette.first())
+                .map_or_else(|| "#1c1f33".to_string(), ToString::to_string);
+            format!(
+                "<div class=\"placeholder\" style=\"aspect-ratio: {width} / {height}; \
+                 background: {color}\"></div>"
+            )
+        };
+        let _ = writeln!(
+            page,
+            "<figure>\n<a href=\"{}\">{preview}</a>\n<figcaption>\n<strong>{title}</strong>",
+            escape(image.url.as_str())
+        );
+        if let Some(credit) = &image.metadata.credit {
+            let _ = writeln!(page, "<span class=\"credit\">{}</span>", escape(credit));
+        }
+        let format = image.format.to_string().to_uppercase();
+        let size = image
+            .size
+            .map(|size| format!(", {}", HumanBytes(size)))
+            .unwrap_or_default();
+        let _ = writeln!(
+            page,
+            "<span class=\"details\">{width} \u{d7} {height} {format}{size}</span>\n\
+             </figcaption>\n</figure>"
+        );
+    }
+    page.push_str("</main>\n</body>\n</html>\n");
+    page
+}
+
+/// Run the `publish` subcommand with the arguments following it.
+pub fn run(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let [path, out] = args else {
+        return Err(Errors::Usage("Expected a manifest and an output directory").into());
+    };
+    let (path, out) = (Path::new(path), Path::new(out));
+    let repo = manifest::read(path)?;
+    fs::create_dir_all(out.join(thumbnail::DIR))?;
+
+    // copied as-is rather than re-serialized, so a signature stays valid
+    let manifest_name = repo
+        .update_url
+        .as_ref()
+        .and_then(|url| url.path_segments()?.next_back().map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .or_else(|| Some(path.file_name()?.to_string_lossy().to_string()))
+        .ok_or_else(|| format!("Not a manifest file: {}", path.display()))?;
+    fs::copy(path, out.join(&manifest_name))?;
+
+    let mut thumbnails = vec![];
+    for image in &repo.images {
+        let thumbnail = match Thumbnails::local_copy(path, &repo, image) {
+            Some(file) => {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                let src = format!("{}/{name}", thumbnail::DIR);
+                fs::copy(&file, out.join(&src))?;
+                Some(src)
+            }
+            None => image.thumbnail_url.as_ref().map(ToString::to_string),
+        };
+        thumbnails.push(thumbnail);
+    }
+    fs::write(
+        out.join("index.html"),
+        render(&repo, &manifest_name, &thumbnails),
+    )?;
+
+    eprintln!(
+        "Published {} images to {}",
+        repo.images.len(),
+        out.display()
+    );
+    if repo.update_url.is_none() {
+        eprintln!("The manifest has no updateUrl, so the page can't link to adding it to the app");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use image_repo::types::ImageRepo;
+
+    #[test]
+    fn renders_gallery() {
+        let repo = serde_json::from_str::<ImageRepo>(
+            r##"{
+                "name": "Webb <Favourites>",
+                "updateUrl": "https://example.com/jwst/repo.json",
+                "images": [
+                    {
+                        "url": "https://example.com/cliffs.png",
+                        "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                        "width": 460,
+                        "height": 307,
+                        "format": "png",
+                        "size": 49880,
+                        "metadata": { "title": "Cosmic \"Cliffs\"", "credit": "NASA & STScI" }
+                    },
+                    {
+                        "url": "https://example.com/ring.tif",
+                        "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+                        "width": 300,
+                        "height": 200,
+                        "format": "tiff",
+                        "colors": { "palette": ["#05050f"], "luminance": 0.1 }
+                    }
+                ]
+            }"##,
+        )
+        .expect("repo");
+        let page = render(
+            &repo,
+            "repo.json",
+            &[Some("thumbnails/sha256-b645.jpg".into()), None],
+        );
+
+        assert!(page.contains("<h1>Webb &lt;Favourites&gt;</h1>"), "{page}");
+        assert!(
+            page.contains("href=\"jdt://add?url=https%3A%2F%2Fexample.com%2Fjwst%2Frepo.json\"")
+        );
+        assert!(page.contains("<a href=\"repo.json\">Manifest JSON</a>"));
+        assert!(page.contains("2 images, at least 48.71 KiB"));
+        assert!(page.contains(
+            "<a href=\"https://example.com/cliffs.png\"><img src=\"thumbnails/sha256-b645.jpg\" \
+             alt=\"Cosmic &quot;Cliffs&quot;\" width=\"460\" height=\"307\" loading=\"lazy\"></a>"
+        ));
+        assert!(page.contains("<span class=\"credit\">NASA &amp; STScI</span>"));
+        assert!(page.contains("460 \u{d7} 307 PNG, 48.71 KiB"));
+        // no thumbnail, so a box in its colour, titled after the file
+        assert!(page.contains("aspect-ratio: 300 / 200; background: #05050f"));
+        assert!(page.contains("<strong>ring.tif</strong>"));
+    }
+}
@@ -0,0 +1,33 @@
+use image_repo::signing;
+use serde_json::Value;
+use std::{error::Error, fs, path::Path};
+
+/// Generate a new Ed25519 signing key and write it to `key_path` as a PKCS#8 document.
+/// Refuses to overwrite an existing key.
+pub fn keygen(key_path: &Path) -> Result<(), Box<dyn Error>> {
+    if key_path.exists() {
+        return Err(format!("Refusing to overwrite existing key {}", key_path.display()).into());
+    }
+    let key = signing::generate_signing_key()?;
+    fs::write(key_path, key)?;
+    eprintln!("Wrote signing key to {}", key_path.display());
+    eprintln!("Keep this file secret; anyone with it can publish updates to your repositories.");
+    Ok(())
+}
+
+/// Sign the manifest at `manifest_path` in place with the key at `key_path`.
+pub fn sign(manifest_path: &Path, key_path: &Path) -> Result<(), Box<dyn Error>> {
+    let key = fs::read(key_path)?;
+    let mut doc = serde_json::from_slice::<Value>(&fs::read(manifest_path)?)?;
+    // make sure we're signing something the app will actually accept
+    image_repo::schema::from_value(doc.clone())?;
+    signing::sign(&mut doc, &key)?;
+    fs::write(manifest_path, serde_json::to_string_pretty(&doc)? + "\n")?;
+
+    let public_key = signing::verify(&doc)?.unwrap_or_default();
+    eprintln!(
+        "Signed {} with publisher key {public_key}",
+        manifest_path.display()
+    );
+    Ok(())
+}
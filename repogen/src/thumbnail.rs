@@ -0,0 +1,81 @@
+//! Thumbnails and blur hashes for `--thumbnails`. Thumbnails are written to a
+//! directory next to the manifest, to be published alongside it.
+
+use crate::{BoxError, Options};
+use image_repo::{
+    preview,
+    types::{ImageData, ImageRepo, ImgError},
+};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// Name of the directory next to the manifest that thumbnails are written to.
+pub const DIR: &str = "thumbnails";
+
+/// Where the thumbnails of a manifest's images go, on disk and once published.
+pub struct Thumbnails {
+    dir: PathBuf,
+    base: Url,
+}
+
+impl Thumbnails {
+    /// The thumbnails of the manifest at `path`, or `None` unless `--thumbnails`
+    /// was given. Their URLs are resolved against the manifest's `updateUrl`, so
+    /// they're found wherever the manifest is published.
+    pub fn for_manifest(
+        path: &Path,
+        repo: &ImageRepo,
+        options: &Options,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        if !options.thumbnails {
+            return Ok(None);
+        }
+        let Some(update_url) = &repo.update_url else {
+            return Err(format!(
+                "{} has no updateUrl to publish thumbnails next to",
+                path.display()
+            )
+            .into());
+        };
+        let dir = path.parent().unwrap_or(Path::new(".")).join(DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(Some(Thumbnails {
+            dir,
+            base: update_url.join(&format!("{DIR}/"))?,
+        }))
+    }
+
+    /// The local copy of an image's thumbnail, if it was written by `--thumbnails`
+    /// next to the manifest at `path` and hasn't been deleted since.
+    pub fn local_copy(path: &Path, repo: &ImageRepo, image: &ImageData) -> Option<PathBuf> {
+        let base = repo.update_url.as_ref()?.join(&format!("{DIR}/")).ok()?;
+        let name = image
+            .thumbnail_url
+            .as_ref()?
+            .as_str()
+            .strip_prefix(base.as_str())?;
+        let file = path.parent().unwrap_or(Path::new(".")).join(DIR).join(name);
+        (!name.contains('/') && file.is_file()).then_some(file)
+    }
+
+    /// Write the thumbnail of an image and fill in its [`ImageData::thumbnail_url`]
+    /// and [`ImageData::blur_hash`]. Thumbnails are named after the image's hash.
+    /// Images that can't be decoded, currently AVIF, are left without.
+    pub fn add(&self, image: &mut ImageData, bytes: &[u8]) -> Result<(), BoxError> {
+        let preview = match preview::generate(image.format, bytes) {
+            Ok(preview) => preview,
+            Err(ImgError::UnsupportedFormat(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let digest = image.digest()?;
+        let name = format!("{}-{}.jpg", digest.algorithm, digest.hex);
+        fs::write(self.dir.join(&name), preview.thumbnail)?;
+        image.thumbnail_url = Some(self.base.join(&name)?);
+        image.blur_hash = Some(preview.blur_hash);
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Mutex};
 
+use tauri::{AppHandle, Manager, State};
 use url::Url;
-use viewmodel_api::{error::Error, viewmodels::RepositoryViewModel, ResourceType};
+use viewmodel_api::{
+    error::Error,
+    settings::Settings,
+    viewmodels::{RepositoryPreview, RepositoryViewModel},
+    ResourceType,
+};
 
 trait TauriResult<T> {
     fn serialize_err(self) -> Result<T, String>;
@@ -21,12 +27,20 @@ impl<T> TauriResult<T> for std::io::Result<T> {
 
 #[tauri::command]
 pub async fn get_repositories_view_model() -> Result<Vec<RepositoryViewModel>, String> {
+    viewmodel_api::rescan_local_repositories()
+        .await
+        .serialize_err()?;
     viewmodel_api::list_repositories().await.serialize_err()
 }
 
+#[tauri::command]
+pub async fn preview_repository(url: Url) -> Result<RepositoryPreview, String> {
+    viewmodel_api::preview_repository(url).await.serialize_err()
+}
+
 #[tauri::command]
 pub async fn add_repository(url: Url) -> Result<RepositoryViewModel, String> {
-    let (repo, file_path) = viewmodel_api::download_resource_to_file(url, false)
+    let (repo, file_path) = viewmodel_api::install_repository(url, false)
         .await
         .serialize_err()?;
     RepositoryViewModel::from_resource(repo, file_path)
@@ -34,6 +48,39 @@ pub async fn add_repository(url: Url) -> Result<RepositoryViewModel, String> {
         .serialize_err()
 }
 
+/// The manifest URL of the last `jdt://` link opened, until the UI takes it with
+/// [`take_repository_link`].
+#[derive(Default)]
+pub struct RepositoryLink(Mutex<Option<Url>>);
+
+/// Offer to add the repository a `jdt://` link from its gallery page points to.
+/// Nothing is installed until the user has seen what it would download and
+/// confirmed: the manifest URL is kept for the UI, which is told with a
+/// `repository-link-opened` event and then takes it with [`take_repository_link`].
+/// It's kept rather than sent with the event, since a link that launches the app
+/// is opened before the UI listens for events. Anything but a `jdt://` link to
+/// add a repository is ignored.
+pub fn open_link(handle: &AppHandle, link: &str) {
+    let Some(manifest) = Url::parse(link)
+        .ok()
+        .and_then(|link| image_repo::deep_link::parse(&link))
+    else {
+        return;
+    };
+    if let Ok(mut pending) = handle.state::<RepositoryLink>().0.lock() {
+        *pending = Some(manifest);
+    }
+    let _ = handle.emit_all("repository-link-opened", ());
+}
+
+/// Take the manifest URL of the last `jdt://` link opened, if the UI hasn't
+/// already, see [`open_link`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)] // commands are given their state by value
+pub fn take_repository_link(link: State<'_, RepositoryLink>) -> Option<String> {
+    link.0.lock().ok()?.take().map(|url| url.to_string())
+}
+
 #[tauri::command]
 pub async fn delete_resource(path: PathBuf, resource_type: ResourceType) -> Result<(), String> {
     let storage_root = viewmodel_api::storage_root(resource_type).serialize_err()?;
@@ -41,14 +88,18 @@ pub async fn delete_resource(path: PathBuf, resource_type: ResourceType) -> Resu
         return Err("Attempted to delete file that is outside application storage.".into());
     }
 
-    tokio::fs::remove_file(path).await.serialize_err()
+    match resource_type {
+        ResourceType::Repo => viewmodel_api::delete_repository(&path).await,
+        _ => viewmodel_api::delete_resource(&path).await,
+    }
+    .serialize_err()
 }
 
 #[tauri::command]
 pub async fn update_repo(repo: RepositoryViewModel) -> Result<(), String> {
     if let Some(url) = repo.update_url {
         let url = Url::parse(&url).map_err(|_| "Invalid URL".to_string())?;
-        let _ = viewmodel_api::download_resource_to_file(url, true)
+        let _ = viewmodel_api::install_repository(url, true)
             .await
             .serialize_err()?;
         Ok(())
@@ -56,3 +107,17 @@ pub async fn update_repo(repo: RepositoryViewModel) -> Result<(), String> {
         Err("Repo has no update URL.".into())
     }
 }
+
+#[tauri::command]
+pub async fn get_settings() -> Result<Settings, String> {
+    viewmodel_api::settings::load_settings()
+        .await
+        .serialize_err()
+}
+
+#[tauri::command]
+pub async fn set_settings(settings: Settings) -> Result<(), String> {
+    viewmodel_api::settings::save_settings(&settings)
+        .await
+        .serialize_err()
+}
@@ -4,17 +4,40 @@
 
 mod api;
 
+/// Must match `tauri.bundle.identifier` in `tauri.conf.json`.
+const IDENTIFIER: &str = "com.tauri.dev";
+
 #[tokio::main]
 async fn main() {
+    // hands links opened while the app is already running over to that instance
+    tauri_plugin_deep_link::prepare(IDENTIFIER);
     viewmodel_api::init_storage()
         .await
         .expect("Failed to initialize storage directories.");
     tauri::Builder::default()
+        .manage(api::RepositoryLink::default())
+        .setup(|app| {
+            let handle = app.handle();
+            // on macOS the scheme is registered by Info.plist instead
+            tauri_plugin_deep_link::register(image_repo::deep_link::SCHEME, move |link| {
+                api::open_link(&handle, &link);
+            })?;
+            // on Windows and Linux, a link that launches the app is its first argument
+            #[cfg(not(target_os = "macos"))]
+            if let Some(link) = std::env::args().nth(1) {
+                api::open_link(&app.handle(), &link);
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             api::get_repositories_view_model,
+            api::take_repository_link,
+            api::preview_repository,
             api::add_repository,
             api::delete_resource,
-            api::update_repo
+            api::update_repo,
+            api::get_settings,
+            api::set_settings
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,5 +1,7 @@
 import { useEffect, useState } from "react";
+import { listen } from "@tauri-apps/api/event";
 import { invoke } from "./api";
+import { formatSize } from "./format";
 import { RepositoryViewModel } from "./types";
 import AddRepoModal from "./components/AddRepoModal/AddRepoModal";
 import { ArrowPathIcon, TrashIcon } from "@heroicons/react/20/solid";
@@ -27,10 +29,35 @@ function App() {
   }, []);
 
   const [showAddModal, setShowAddModal] = useState(false);
+  // manifest URL from a "Add to jdt" link on a gallery page, if that's what
+  // opened the add modal
+  const [linkedUrl, setLinkedUrl] = useState<string>();
   const [deletePending, setDeletePending] = useState(false);
 
-  const onRepoAdded = () => {
+  // the link may have launched the app, before there was anything to listen
+  useEffect(() => {
+    const openLink = () =>
+      invoke("take_repository_link")
+        .then((url) => {
+          if (!url) return;
+          setLinkedUrl(url);
+          setShowAddModal(true);
+        })
+        .catch(console.error);
+    openLink();
+    const opened = listen("repository-link-opened", openLink);
+    return () => {
+      opened.then((unlisten) => unlisten());
+    };
+  }, []);
+
+  const closeAddModal = () => {
     setShowAddModal(false);
+    setLinkedUrl(undefined);
+  };
+
+  const onRepoAdded = () => {
+    closeAddModal();
     refresh();
   };
 
@@ -46,7 +73,7 @@ function App() {
       }, 3000);
       return;
     }
-    invoke("delete_resource", { path: repo.path })
+    invoke("delete_resource", { path: repo.path, resourceType: "Repo" })
       .then(refresh)
       .catch(console.error); // TODO error toasts
   };
@@ -71,6 +98,7 @@ function App() {
               <th>Name</th>
               <th>Description</th>
               <th>Update URL</th>
+              <th>Size</th>
               <th>Last Updated</th>
               <th className="w-4">Actions</th>
             </tr>
@@ -100,6 +128,7 @@ function App() {
                     )) ||
                       "None"}
                   </td>
+                  <td>{formatSize(repo)}</td>
                   <td>{repo.lastUpdated}</td>
                   <td className="flex justify-end w-fit">
                     {repo.updateUrl && (
@@ -130,7 +159,8 @@ function App() {
       </div>
       <AddRepoModal
         show={showAddModal}
-        onCancel={() => setShowAddModal(false)}
+        linkedUrl={linkedUrl}
+        onCancel={closeAddModal}
         onConfirmComplete={onRepoAdded}
       />
     </div>
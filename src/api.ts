@@ -1,19 +1,30 @@
 // TODO figure out a way to auto generate this file
 
 import { invoke as tauriInvoke } from "@tauri-apps/api";
-import { RepositoryViewModel } from "./types";
+import { RepositoryPreview, RepositoryViewModel } from "./types";
 
 type Invocations = {
 	get_repositories_view_model: {
 		args: undefined;
 		returns: RepositoryViewModel[];
 	};
+	take_repository_link: {
+		args: undefined;
+		returns: string | null;
+	};
+	preview_repository: {
+		args: { url: string };
+		returns: RepositoryPreview;
+	};
 	add_repository: {
 		args: { url: string };
 		returns: RepositoryViewModel;
 	};
 	delete_resource: {
-		args: { path: string };
+		args: {
+			path: string;
+			resourceType: "Repo" | "Image" | "TrustedKey" | "Thumbnail";
+		};
 		returns: void;
 	};
 	update_repo: {
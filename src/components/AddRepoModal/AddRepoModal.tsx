@@ -1,28 +1,60 @@
 import { useEffect, useState } from "react";
 import { invoke } from "../../api";
+import { formatSize } from "../../format";
+import { RepositoryPreview } from "../../types";
 
 export interface AddRepoModalProps {
   show: boolean;
+  /** Manifest URL from a "Add to jdt" link, previewed as soon as it's shown */
+  linkedUrl?: string;
   onCancel: () => void;
   onConfirmComplete: () => void;
 }
 
 export default function AddRepoModal({
   show,
+  linkedUrl,
   onCancel,
   onConfirmComplete,
 }: AddRepoModalProps) {
   const [loading, setLoading] = useState(false);
   const [url, setUrl] = useState("");
+  // shown before installing so the download size isn't a surprise
+  const [preview, setPreview] = useState<RepositoryPreview>();
 
   useEffect(() => {
     if (show) return;
     // reset
     setLoading(false);
     setUrl("");
+    setPreview(undefined);
   }, [show]);
 
+  const showPreview = (url: string) => {
+    setLoading(true);
+    invoke("preview_repository", { url })
+      .then(setPreview)
+      .catch((e) => {
+        console.error(e);
+        // TODO error toasts
+        onCancel();
+      })
+      .finally(() => setLoading(false));
+  };
+
+  // links are only installed once the user has seen the preview and confirmed
+  useEffect(() => {
+    if (!show || !linkedUrl) return;
+    setUrl(linkedUrl);
+    setPreview(undefined);
+    showPreview(linkedUrl);
+  }, [show, linkedUrl]);
+
   const onConfirm = () => {
+    if (!preview) {
+      showPreview(url);
+      return;
+    }
     setLoading(true);
     invoke("add_repository", { url })
       .then(onConfirmComplete)
@@ -62,7 +94,10 @@ export default function AddRepoModal({
             className="input input-solid w-full max-w-none"
             placeholder="https://github.com/url/to/repository.json"
             value={url}
-            onChange={(e) => setUrl(e.target.value)}
+            onChange={(e) => {
+              setUrl(e.target.value);
+              setPreview(undefined);
+            }}
           />
           <span>
             Paste a URL to an image repository JSON file.{" "}
@@ -70,13 +105,19 @@ export default function AddRepoModal({
               Click here to learn more.
             </a>
           </span>
+          {preview && (
+            <span>
+              <strong>{preview.name}</strong> has {preview.imageCount}{" "}
+              images, {formatSize(preview)} to download.
+            </span>
+          )}
           <div className="flex gap-3">
             <button className="btn btn-error btn-block" onClick={onCancel}>
               Cancel
             </button>
             <button className="btn btn-block" onClick={onConfirm}>
               {(loading && <div className="spinner-dot-intermittent" />) ||
-                "Add"}
+                (preview ? "Install" : "Add")}
             </button>
           </div>
         </div>
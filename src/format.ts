@@ -0,0 +1,18 @@
+/** Format a total size in bytes, as a lower bound if it isn't exact. */
+export function formatSize({
+  totalSize,
+  totalSizeExact,
+}: {
+  totalSize: number;
+  totalSizeExact: boolean;
+}): string {
+  const units = ["B", "KB", "MB", "GB", "TB"];
+  let size = totalSize;
+  let unit = 0;
+  while (size >= 1000 && unit < units.length - 1) {
+    size /= 1000;
+    unit++;
+  }
+  const formatted = `${size.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+  return totalSizeExact ? formatted : `at least ${formatted}`;
+}
@@ -1,4 +1,10 @@
-use image_repo::{types::ChecksumError, RegexError};
+use image_repo::{
+    http::HttpError,
+    schema::SchemaError,
+    signing::SigningError,
+    types::{ChecksumError, ImgError},
+    RegexError,
+};
 use reqwest::StatusCode;
 use std::{fmt::Display, path::PathBuf, string::FromUtf8Error};
 
@@ -10,7 +16,7 @@ pub enum Error {
     /// Failed to serialize repository JSON
     Serialization(serde_json::Error),
     /// HTTP request failed
-    Http(reqwest::Error),
+    Http(HttpError),
     /// HTTP request returned a bad status code
     HttpStatus(StatusCode),
     /// Downloaded image does not match the checksum from the repository JSON
@@ -29,6 +35,33 @@ pub enum Error {
     InvalidUpdateUrl((String, String)),
     /// Couldn't generate a safe filename. Should never happen.
     ToSafeFilename(RegexError),
+    /// Repository JSON is invalid or uses an unsupported schema version
+    Schema(SchemaError),
+    /// Repository JSON signature is malformed or invalid
+    Signature(SigningError),
+    /// Repository JSON was signed by a different key than the one pinned for it.
+    /// Contains the pinned key first, then the key it was signed with.
+    PublisherKeyMismatch((String, String)),
+    /// Repository JSON is unsigned but a publisher key was previously pinned for it
+    MissingSignature,
+    /// Every URL and mirror for an image failed. Contains each URL with its error.
+    AllMirrorsFailed(Vec<(String, Error)>),
+    /// Failed to resolve the repositories included by a composite repository
+    ResolveIncludes(image_repo::Error),
+    /// A `file://` URL that isn't a valid local path
+    InvalidFileUrl(String),
+    /// A repository that wasn't loaded from this computer refers to a local
+    /// `file://` URL
+    LocalUrl(String),
+    /// A `jdt://` link that isn't a valid link to add a repository
+    InvalidLink(String),
+    /// Failed to decode or convert an image
+    Image(ImgError),
+    /// Downloaded size does not match the size from the repository JSON.
+    /// Contains the expected size first, then the actual size, in bytes.
+    SizeMismatch((u64, u64)),
+    /// Download is larger than the most that's accepted for it, in bytes
+    TooLarge(u64),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -55,6 +88,32 @@ impl Display for Error {
                 ),
                 Error::ToSafeFilename(regex_err) =>
                     format!("Could not generate a safe filename string: {regex_err}"),
+                Error::Schema(e) => format!("{e}"),
+                Error::Signature(e) => format!("Invalid repository signature: {e}"),
+                Error::PublisherKeyMismatch((expected, received)) => format!(
+                    "Repository JSON is signed by {received} but was previously signed by {expected}",
+                ),
+                Error::MissingSignature =>
+                    "Repository JSON is unsigned but was previously signed.".to_string(),
+                Error::AllMirrorsFailed(errors) => format!(
+                    "All mirrors failed: {}",
+                    errors
+                        .iter()
+                        .map(|(url, e)| format!("{url}: {e}"))
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
+                Error::ResolveIncludes(e) => format!("Failed to resolve included repository: {e}"),
+                Error::InvalidFileUrl(url) => format!("Not a valid local file URL: {url}"),
+                Error::LocalUrl(url) => format!(
+                    "Only repositories on this computer can use local files, not {url}"
+                ),
+                Error::InvalidLink(link) => format!("Not a valid link to add a repository: {link}"),
+                Error::Image(e) => format!("{e}"),
+                Error::SizeMismatch((expected, received)) => format!(
+                    "Expected a download of {expected} bytes but got {received} bytes",
+                ),
+                Error::TooLarge(max) => format!("Download is larger than the limit of {max} bytes"),
             }
         )
     }
@@ -78,9 +137,15 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<HttpError> for Error {
+    fn from(value: HttpError) -> Self {
+        Self::Http(value)
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
-        Self::Http(value)
+        Self::Http(value.into())
     }
 }
 
@@ -90,6 +155,39 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<SchemaError> for Error {
+    fn from(value: SchemaError) -> Self {
+        Error::Schema(value)
+    }
+}
+
+impl From<SigningError> for Error {
+    fn from(value: SigningError) -> Self {
+        Error::Signature(value)
+    }
+}
+
+impl From<image_repo::Error> for Error {
+    fn from(value: image_repo::Error) -> Self {
+        match value {
+            image_repo::Error::Request(e) => Error::Http(e),
+            image_repo::Error::Io(e) => Error::IOError(e),
+            image_repo::Error::Schema(e) => Error::Schema(e),
+            image_repo::Error::Signing(e) => Error::Signature(e),
+            image_repo::Error::LocalUrl(url) => Error::LocalUrl(url.to_string()),
+            e @ (image_repo::Error::UrlParse(_)
+            | image_repo::Error::IncludeCycle(_)
+            | image_repo::Error::IncludeDepthExceeded(_)) => Error::ResolveIncludes(e),
+        }
+    }
+}
+
+impl From<ImgError> for Error {
+    fn from(value: ImgError) -> Self {
+        Error::Image(value)
+    }
+}
+
 impl From<ChecksumError> for Error {
     fn from(value: ChecksumError) -> Self {
         Error::InvalidChecksum(value)
@@ -1,25 +1,35 @@
 #![deny(clippy::all, clippy::pedantic, rust_2018_idioms, clippy::unwrap_used)]
 
 pub mod error;
+pub mod settings;
+pub mod trust;
 pub mod types;
 pub mod viewmodels;
 
 use error::Error;
 use error::Result;
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use image_repo::types::{
+    Brightness, ColorStats, ImageData, ImageRepo, ImgError, PerceptualHash, SupportedFormat,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use strum::EnumIter;
 use strum::IntoEnumIterator;
 use tokio::fs;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio_stream::wrappers::ReadDirStream;
 use tokio_stream::StreamExt;
 use types::DownloadableResource;
 use types::TryIntoStoragePath;
 use types::UpdateInterval;
-use viewmodels::RepositoryViewModel;
+use viewmodels::{RepositoryPreview, RepositoryViewModel};
 
 #[cfg(debug_assertions)]
 const STORAGE_ROOT: &str = "jdt-debug";
@@ -30,6 +40,8 @@ const STORAGE_ROOT: &str = "jdt";
 pub enum ResourceType {
     Repo,
     Image,
+    TrustedKey,
+    Thumbnail,
 }
 
 /// Get the toplevel storage root directory for the given storage type.
@@ -45,6 +57,12 @@ pub fn storage_root(resource_type: ResourceType) -> Result<PathBuf> {
         ResourceType::Image => {
             dirs_next::cache_dir().map(|cache| cache.join(STORAGE_ROOT).join("images"))
         }
+        ResourceType::TrustedKey => {
+            dirs_next::config_dir().map(|config| config.join(STORAGE_ROOT).join("trusted-keys"))
+        }
+        ResourceType::Thumbnail => {
+            dirs_next::cache_dir().map(|cache| cache.join(STORAGE_ROOT).join("thumbnails"))
+        }
     }
     .ok_or(Error::FailedToGetStorageDir)
 }
@@ -59,11 +77,38 @@ pub async fn init_storage() -> Result<()> {
             fs::create_dir_all(root).await?;
         }
     }
+    migrate_image_file_names(&storage_root(ResourceType::Image)?).await?;
+
+    Ok(())
+}
 
+/// Rename images cached by earlier versions, named after their bare SHA-256 hex
+/// digest, and anything stored next to them, to the `sha256-<hex>` names they're
+/// now looked up by (see [`ImageData::to_file_name`]). An old file is removed
+/// instead if the image has since been stored under its new name.
+async fn migrate_image_file_names(dir: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let Some((hex, rest)) = file_name.to_str().and_then(|name| name.split_once('.')) else {
+            continue;
+        };
+        if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        let new_path = dir.join(format!("sha256-{}.{rest}", hex.to_ascii_lowercase()));
+        if new_path.symlink_metadata().is_ok() {
+            fs::remove_file(entry.path()).await?;
+        } else {
+            fs::rename(entry.path(), new_path).await?;
+        }
+    }
     Ok(())
 }
 
-/// Store the given resource.
+/// Store the given resource. An existing file is replaced rather than written
+/// through, since it may be a link to a file outside the cache, see
+/// [`store_reference`].
 /// # Errors
 ///
 /// [`crate::Error`]
@@ -71,16 +116,17 @@ pub async fn store_resource<T>(resource: &T, bytes: &[u8], overwrite: bool) -> R
 where
     T: TryIntoStoragePath,
 {
-    let filepath = resource.try_into_storage_path()?;
-    if !overwrite && filepath.exists() {
-        return Err(Error::FileAlreadyExists(filepath));
+    let path = resource.try_into_storage_path()?;
+    if path.symlink_metadata().is_ok() {
+        if !overwrite {
+            return Err(Error::FileAlreadyExists(path));
+        }
+        fs::remove_file(&path).await?;
     }
 
-    let path = resource.try_into_storage_path()?;
     let mut file = fs::OpenOptions::new()
-        .create(true)
+        .create_new(true)
         .write(true)
-        .truncate(true)
         .open(&path)
         .await?;
     file.write_all(bytes).await?;
@@ -88,6 +134,82 @@ where
     Ok(path)
 }
 
+/// Store the given resource as a reference to `source`, the local file its `bytes`
+/// were read and verified from, instead of a copy. The reference is a hard link,
+/// so moving or replacing `source` afterwards doesn't change what was verified.
+/// If `source` can't be linked, e.g. because it's on another filesystem, or it
+/// changed since it was verified, the verified `bytes` are stored instead.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn store_reference<T>(
+    resource: &T,
+    source: &Path,
+    bytes: &[u8],
+    overwrite: bool,
+) -> Result<PathBuf>
+where
+    T: TryIntoStoragePath,
+{
+    let path = resource.try_into_storage_path()?;
+    if path.symlink_metadata().is_ok() {
+        if !overwrite {
+            return Err(Error::FileAlreadyExists(path));
+        }
+        fs::remove_file(&path).await?;
+    }
+
+    if fs::hard_link(source, &path).await.is_ok() {
+        if fs::read(&path).await? == bytes {
+            return Ok(path);
+        }
+        fs::remove_file(&path).await?;
+    }
+    store_resource(resource, bytes, false).await
+}
+
+/// Delete a stored resource, along with any analyses of it cached next to it,
+/// see [`color_stats`] and [`perceptual_hash`].
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn delete_resource(path: &Path) -> Result<()> {
+    fs::remove_file(path).await?;
+    let Some(extension) = path.extension() else {
+        return Ok(());
+    };
+    for name in ANALYSES {
+        let cached = path.with_extension(format!("{}.{name}.json", extension.to_string_lossy()));
+        match fs::remove_file(cached).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Delete a stored repository, like [`delete_resource`], and forget the publisher
+/// key pinned for it so that it can be added again after its publisher changes keys.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn delete_repository(path: &Path) -> Result<()> {
+    // a manifest that can't be read has nothing to unpin, but is still deleted
+    let update_url = fs::read(path)
+        .await
+        .ok()
+        .and_then(|bytes| image_repo::schema::from_slice(&bytes).ok())
+        .and_then(|repo| repo.update_url);
+    delete_resource(path).await?;
+    if let Some(url) = update_url {
+        trust::remove_pin(&url).await?;
+    }
+    Ok(())
+}
+
 /// Load the resource if it exists locally. Does not connect to the internet to download
 /// the resource.
 ///
@@ -109,7 +231,8 @@ where
 }
 
 /// Download the given resource and save it to disk in the right location.
-/// Overwrites the file if it already exists.
+/// Overwrites the file if it already exists. Resources that are already local
+/// files are stored by reference, see [`store_reference`].
 ///
 /// Example:
 ///
@@ -121,7 +244,7 @@ where
 ///     .await
 ///     .unwrap();
 /// // (ImageData, PathBuf)
-/// let (img_data, img_file_path) = viewmodel_api::download_resource_to_file(img_repo.images[0].clone(), false /* overwrite? */).await.unwrap();
+/// let (img_data, img_file_path) = viewmodel_api::download_resource_to_file(img_repo.images_with_mirrors()[0].clone(), false /* overwrite? */).await.unwrap();
 /// # }
 /// ```
 ///
@@ -136,11 +259,127 @@ where
     V: TryIntoStoragePath,
     T: DownloadableResource<V>,
 {
-    let (resource, bytes) = downloadable.download_resource().await?;
-    let path = store_resource(&resource, bytes.as_slice(), overwrite).await?;
+    let types::Downloaded {
+        resource,
+        bytes,
+        local_source,
+    } = downloadable.download_resource().await?;
+    let path = match local_source {
+        Some(source) => store_reference(&resource, &source, &bytes, overwrite).await?,
+        None => store_resource(&resource, &bytes, overwrite).await?,
+    };
     Ok((resource, path))
 }
 
+/// Download a repository and store it, like [`download_resource_to_file`], then
+/// eagerly download the thumbnails of its images so the UI has something to show
+/// for every image straight away, see [`download_thumbnails`]. `url` may also be a
+/// link from a repository's gallery page, see [`image_repo::deep_link`].
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn install_repository(
+    url: reqwest::Url,
+    overwrite: bool,
+) -> Result<(ImageRepo, PathBuf)> {
+    let (repo, path) = download_resource_to_file(repository_url(url)?, overwrite).await?;
+    download_thumbnails(&repo).await?;
+    Ok((repo, path))
+}
+
+/// Download and verify a repository without storing it, to show how much
+/// installing it with [`install_repository`] would download. Publisher keys
+/// aren't pinned until it's installed.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn preview_repository(url: reqwest::Url) -> Result<RepositoryPreview> {
+    let downloaded = types::download_repo(&repository_url(url)?, false).await?;
+    Ok(downloaded.resource.into())
+}
+
+/// The manifest URL a repository URL or [`image_repo::deep_link`] points to.
+fn repository_url(url: reqwest::Url) -> Result<reqwest::Url> {
+    if url.scheme() == image_repo::deep_link::SCHEME {
+        image_repo::deep_link::parse(&url).ok_or_else(|| Error::InvalidLink(url.to_string()))
+    } else {
+        Ok(url)
+    }
+}
+
+/// Get the path the thumbnail of an image is stored at, named after the image's
+/// hash. The thumbnail may not have been downloaded yet.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub fn thumbnail_path(image: &ImageData) -> Result<PathBuf> {
+    let digest = image.digest()?;
+    storage_root(ResourceType::Thumbnail)
+        .map(|root| root.join(format!("{}-{}.jpg", digest.algorithm, digest.hex)))
+}
+
+/// How many thumbnails are downloaded at once.
+const THUMBNAIL_JOBS: usize = 4;
+
+/// Largest thumbnail accepted, in bytes. Thumbnails generated by `repogen` are a
+/// small fraction of this, so anything larger isn't really a thumbnail.
+const MAX_THUMBNAIL_SIZE: u64 = 1024 * 1024;
+
+/// Check that downloaded thumbnail bytes are a JPEG that decodes, before they're
+/// stored and shown by the UI.
+fn check_thumbnail(bytes: &[u8]) -> Result<()> {
+    let format = image_repo::decoding::detect_format(bytes)?;
+    if format != SupportedFormat::Jpg {
+        return Err(ImgError::UnsupportedFormat(format!("{format} thumbnail")).into());
+    }
+    image_repo::decoding::check_integrity(format, bytes)?;
+    Ok(())
+}
+
+/// Download the thumbnails of a repository's images that aren't stored yet,
+/// [`THUMBNAIL_JOBS`] at a time. Thumbnails are only a nicety, so any that fail to
+/// download or aren't a valid JPEG are skipped and the UI shows the image's blur
+/// hash instead. Returns the paths of the thumbnails that were downloaded.
+///
+/// # Errors
+///
+/// [`crate::Error`] if the storage location can't be determined.
+pub async fn download_thumbnails(repo: &ImageRepo) -> Result<Vec<PathBuf>> {
+    let jobs = Arc::new(Semaphore::new(THUMBNAIL_JOBS));
+    let mut downloads = JoinSet::new();
+    for image in &repo.images {
+        let Some(url) = image.thumbnail_url.clone() else {
+            continue;
+        };
+        let path = thumbnail_path(image)?;
+        if path.exists() {
+            continue;
+        }
+        let jobs = Arc::clone(&jobs);
+        downloads.spawn(async move {
+            let _job = jobs.acquire_owned().await.map_err(std::io::Error::other)?;
+            let bytes = types::download_bytes(&url, None, Some(MAX_THUMBNAIL_SIZE)).await?;
+            let bytes =
+                tokio::task::spawn_blocking(move || check_thumbnail(&bytes).map(|()| bytes))
+                    .await
+                    .map_err(std::io::Error::other)??;
+            fs::write(&path, bytes).await?;
+            Ok::<_, Error>(path)
+        });
+    }
+
+    let mut downloaded = vec![];
+    while let Some(result) = downloads.join_next().await {
+        if let Ok(Ok(path)) = result {
+            downloaded.push(path);
+        }
+    }
+    Ok(downloaded)
+}
+
 /// Check if a file has not been updated since longer than the specified interval.
 /// This checks the file modified metadata.
 ///
@@ -156,7 +395,230 @@ pub async fn needs_update(path: &PathBuf, update_interval: UpdateInterval) -> Re
     Ok(elapsed > update_interval.into())
 }
 
-/// List all insatlled image repositories.
+/// Get a path to a copy of the image that the desktop can display as a wallpaper.
+/// Images already in a format every desktop supports are used as-is; others are
+/// converted to PNG once and the converted copy is cached next to the original.
+/// The image must already be stored locally.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn desktop_image_path(image: &ImageData) -> Result<PathBuf> {
+    let path = image.try_into_storage_path()?;
+    if image.format.is_desktop_compatible() {
+        return Ok(path);
+    }
+
+    let derivative = path.with_extension(format!("{}.desktop.png", image.format));
+    if derivative.exists() {
+        return Ok(derivative);
+    }
+    if !path.exists() {
+        return Err(Error::FileNotFound(path));
+    }
+    let bytes = fs::read(&path).await?;
+    let format = image.format;
+    let (_, converted) = tokio::task::spawn_blocking(move || {
+        image_repo::decoding::to_desktop_compatible(format, &bytes)
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    fs::write(&derivative, converted).await?;
+    Ok(derivative)
+}
+
+/// Get a path to a copy of the image fitted to a monitor of `width` by `height`
+/// pixels. The image is cropped to the monitor's aspect ratio around the curator's
+/// focal point and crop regions, see [`image_repo::crop`], and scaled down to the
+/// monitor's resolution. Derivatives are cached next to the original, named after
+/// the crop so that a changed focal point produces a new one. Images that need no
+/// cropping are returned as from [`desktop_image_path`]. The image must already be
+/// stored locally.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn wallpaper_path(image: &ImageData, width: u32, height: u32) -> Result<PathBuf> {
+    let rect = image_repo::crop::crop_for(image, width, height);
+    if (rect.width, rect.height) == (image.width, image.height)
+        && image.width <= width
+        && image.height <= height
+    {
+        return desktop_image_path(image).await;
+    }
+
+    let path = image.try_into_storage_path()?;
+    let derivative = path.with_extension(format!(
+        "{}.{}x{}+{}+{}.{width}x{height}.png",
+        image.format, rect.width, rect.height, rect.x, rect.y
+    ));
+    if derivative.exists() {
+        return Ok(derivative);
+    }
+    if !path.exists() {
+        return Err(Error::FileNotFound(path));
+    }
+    let bytes = fs::read(&path).await?;
+    let format = image.format;
+    let fitted = tokio::task::spawn_blocking(move || {
+        image_repo::decoding::crop_to_fit(format, &bytes, rect, width, height)
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    fs::write(&derivative, fitted).await?;
+    Ok(derivative)
+}
+
+/// The names of the analyses cached next to stored images, see [`analysis`].
+const ANALYSES: [&str; 2] = ["colors", "phash"];
+
+/// Get the path an analysis of an image, e.g. its colours, is cached at when its
+/// manifest doesn't include it, next to the stored image.
+fn analysis_path(image: &ImageData, name: &str) -> Result<PathBuf> {
+    let path = image.try_into_storage_path()?;
+    Ok(path.with_extension(format!("{}.{name}.json", image.format)))
+}
+
+/// Get a cached analysis of an image without computing it, see [`analysis`].
+pub(crate) async fn cached_analysis<T: DeserializeOwned>(
+    image: &ImageData,
+    name: &str,
+) -> Option<T> {
+    let bytes = fs::read(analysis_path(image, name).ok()?).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Analyse the stored image with `analyze` once and cache the result next to it
+/// as `name`. Returns `None` if the image hasn't been downloaded yet or its format
+/// can't be decoded.
+async fn analysis<T>(
+    image: &ImageData,
+    name: &str,
+    analyze: fn(SupportedFormat, &[u8]) -> std::result::Result<T, ImgError>,
+) -> Result<Option<T>>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    if let Some(cached) = cached_analysis(image, name).await {
+        return Ok(Some(cached));
+    }
+    let path = image.try_into_storage_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(&path).await?;
+    let format = image.format;
+    let result = match tokio::task::spawn_blocking(move || analyze(format, &bytes))
+        .await
+        .map_err(std::io::Error::other)?
+    {
+        Ok(result) => result,
+        Err(ImgError::UnsupportedFormat(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    fs::write(analysis_path(image, name)?, serde_json::to_vec(&result)?).await?;
+    Ok(Some(result))
+}
+
+/// Get an image's dominant colours and mean luminance. These come from the
+/// manifest if the curator included them; otherwise they're computed from the
+/// stored image once and cached next to it. Returns `None` if the image hasn't
+/// been downloaded yet, its format can't be decoded, or it's fully transparent.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn color_stats(image: &ImageData) -> Result<Option<ColorStats>> {
+    if let Some(colors) = &image.colors {
+        return Ok(Some(colors.clone()));
+    }
+    Ok(analysis(image, "colors", image_repo::colors::analyze)
+        .await?
+        .flatten())
+}
+
+/// Get an image's perceptual hash, from the manifest or computed and cached like
+/// [`color_stats`].
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn perceptual_hash(image: &ImageData) -> Result<Option<PerceptualHash>> {
+    if let Some(hash) = image.perceptual_hash {
+        return Ok(Some(hash));
+    }
+    analysis(image, "phash", image_repo::perceptual::hash).await
+}
+
+/// Leave out the images that are the same picture as one of the `recent` ones,
+/// e.g. so rotation doesn't show a recompressed copy from another repository
+/// straight after the original. Images are the same picture if they have the
+/// same hash or near-duplicate perceptual hashes, see [`perceptual_hash`].
+/// Images whose perceptual hash isn't known are only compared by hash.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn skip_recently_shown(
+    images: Vec<ImageData>,
+    recent: &[ImageData],
+) -> Result<Vec<ImageData>> {
+    let mut recent_hashes = vec![];
+    for image in recent {
+        if let Some(hash) = perceptual_hash(image).await? {
+            recent_hashes.push(hash);
+        }
+    }
+    // compare parsed digests, so a bare hex hash matches its `sha256:` form
+    let recent_digests: Vec<_> = recent
+        .iter()
+        .filter_map(|shown| shown.digest().ok())
+        .collect();
+    let mut kept = vec![];
+    for image in images {
+        if image
+            .digest()
+            .is_ok_and(|digest| recent_digests.contains(&digest))
+        {
+            continue;
+        }
+        let hash = perceptual_hash(&image).await?;
+        if !hash.is_some_and(|hash| {
+            recent_hashes
+                .iter()
+                .any(|shown| hash.is_near_duplicate(*shown))
+        }) {
+            kept.push(image);
+        }
+    }
+    Ok(kept)
+}
+
+/// Keep only the images of the given brightness, e.g. to rotate through dark
+/// images only. Images whose colours aren't known, see [`color_stats`], are
+/// left out.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn filter_by_brightness(
+    images: Vec<ImageData>,
+    brightness: Brightness,
+) -> Result<Vec<ImageData>> {
+    let mut filtered = vec![];
+    for image in images {
+        if color_stats(&image)
+            .await?
+            .is_some_and(|colors| colors.brightness() == brightness)
+        {
+            filtered.push(image);
+        }
+    }
+    Ok(filtered)
+}
+
+/// List all insatlled image repositories. A repository that can't be read is
+/// left out rather than hiding all the others.
 ///
 /// # Errors
 ///
@@ -167,9 +629,127 @@ pub async fn list_repositories() -> Result<Vec<RepositoryViewModel>> {
     let mut dir_stream = ReadDirStream::new(fs::read_dir(storage_root).await?);
     while let Some(file) = dir_stream.next().await {
         let path = file?.path();
-        let view = RepositoryViewModel::from_path(path).await?;
-        repos.push(view);
+        match RepositoryViewModel::from_path(path.clone()).await {
+            Ok(view) => repos.push(view),
+            Err(e) => eprintln!("Skipping repository {}: {e}", path.display()),
+        }
     }
 
     Ok(repos)
 }
+
+/// Re-scan every installed local folder repository whose folder has changed since
+/// it was last scanned. Returns the paths of the repositories that were updated.
+/// A repository that fails to re-scan, e.g. because its folder can't be read, is
+/// skipped so that the others are still listed.
+///
+/// # Errors
+///
+/// [`crate::Error`] if the installed repositories can't be listed.
+pub async fn rescan_local_repositories() -> Result<Vec<PathBuf>> {
+    rescan_local_repositories_in(&storage_root(ResourceType::Repo)?).await
+}
+
+async fn rescan_local_repositories_in(repo_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut updated = vec![];
+    let mut dir_stream = ReadDirStream::new(fs::read_dir(repo_dir).await?);
+    while let Some(file) = dir_stream.next().await {
+        let path = file?.path();
+        match rescan_local_repository(&path).await {
+            Ok(Some(new_path)) => updated.push(new_path),
+            Ok(None) => {}
+            Err(e) => eprintln!("Couldn't re-scan repository {}: {e}", path.display()),
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Re-scan the repository stored at `path` if it's a local folder repository
+/// whose folder has changed since, returning its new path.
+async fn rescan_local_repository(path: &Path) -> Result<Option<PathBuf>> {
+    let repo = image_repo::schema::from_slice(&fs::read(path).await?)?;
+    let Some(url) = repo.update_url else {
+        return Ok(None);
+    };
+    let Some(dir) = types::local_path(&url)?.filter(|dir| dir.is_dir()) else {
+        return Ok(None);
+    };
+
+    let scanned = fs::metadata(path).await?.modified()?;
+    let changed = tokio::task::spawn_blocking(move || image_repo::local::last_modified(&dir))
+        .await
+        .map_err(std::io::Error::other)??;
+    if changed <= scanned {
+        return Ok(None);
+    }
+    let (_, new_path) = download_resource_to_file(url, true).await?;
+    Ok(Some(new_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrate_image_file_names, rescan_local_repositories_in};
+
+    const HEX: &str = "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb";
+
+    #[tokio::test]
+    async fn migrates_legacy_image_file_names() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let other = "e89fb6764fa3f176e0abeea3b55d8b055195dca3fc1573753deb4612cb7834a9";
+        for name in [
+            format!("{HEX}.png"),
+            format!("{HEX}.png.colors.json"),
+            // downloaded again since, under its new name
+            format!("{}.jpg", other.to_ascii_uppercase()),
+            format!("sha256-{other}.jpg"),
+            "notes.txt".to_string(),
+        ] {
+            std::fs::write(dir.join(&name), &name).expect("write");
+        }
+
+        migrate_image_file_names(dir).await.expect("migrate");
+
+        let mut names = std::fs::read_dir(dir)
+            .expect("read dir")
+            .map(|entry| {
+                entry
+                    .expect("entry")
+                    .file_name()
+                    .into_string()
+                    .expect("name")
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "notes.txt".to_string(),
+                format!("sha256-{HEX}.png"),
+                format!("sha256-{HEX}.png.colors.json"),
+                format!("sha256-{other}.jpg"),
+            ]
+        );
+        // the newer download is kept
+        assert_eq!(
+            std::fs::read_to_string(dir.join(format!("sha256-{other}.jpg"))).expect("read"),
+            format!("sha256-{other}.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn rescans_past_broken_repositories() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        std::fs::write(dir.join("broken.json"), "not a manifest").expect("write");
+        std::fs::write(
+            dir.join("remote.json"),
+            r#"{ "name": "Remote", "updateUrl": "https://example.com/repo.json", "images": [] }"#,
+        )
+        .expect("write");
+
+        let updated = rescan_local_repositories_in(dir).await.expect("rescan");
+        assert!(updated.is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+//! App settings, stored as JSON next to the installed repositories, and the
+//! HTTP client built from them that every download shares.
+
+use crate::{error::Result, storage_root, ResourceType};
+use image_repo::http::{HttpClient, HttpConfig};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{PoisonError, RwLock},
+};
+use tokio::fs;
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// The client built from the current settings, so connections are reused
+/// between downloads. Replaced whenever the settings are saved.
+static CLIENT: RwLock<Option<HttpClient>> = RwLock::new(None);
+
+/// Missing fields take their default, so settings saved by an older version
+/// still load.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// Timeouts, retries and proxy for downloading repositories and images.
+    pub http: HttpConfig,
+}
+
+/// Get the path the settings are stored at, in the config directory the
+/// repositories are installed to.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub fn settings_path() -> Result<PathBuf> {
+    let repos = storage_root(ResourceType::Repo)?;
+    Ok(repos.parent().unwrap_or(&repos).join(SETTINGS_FILE))
+}
+
+/// Load the settings, or the defaults if none were saved yet.
+///
+/// # Errors
+///
+/// [`crate::Error`] if the settings file can't be read or is invalid.
+pub async fn load_settings() -> Result<Settings> {
+    let path = settings_path()?;
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    Ok(serde_json::from_slice(&fs::read(path).await?)?)
+}
+
+/// Save the settings, and use them for every download from now on.
+///
+/// # Errors
+///
+/// [`crate::Error`] if the settings are invalid, e.g. the proxy URL isn't
+/// supported, or can't be written.
+pub async fn save_settings(settings: &Settings) -> Result<()> {
+    // check the settings work before saving anything
+    let client = HttpClient::new(&settings.http)?;
+    let path = settings_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::write(path, serde_json::to_vec_pretty(settings)?).await?;
+    *CLIENT.write().unwrap_or_else(PoisonError::into_inner) = Some(client);
+    Ok(())
+}
+
+/// Get the HTTP client for the current settings, loading them the first time.
+///
+/// # Errors
+///
+/// [`crate::Error`] if the settings can't be loaded or the client can't be
+/// built from them.
+pub async fn http_client() -> Result<HttpClient> {
+    if let Some(client) = CLIENT
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+    {
+        return Ok(client.clone());
+    }
+    let client = HttpClient::new(&load_settings().await?.http)?;
+    let mut cached = CLIENT.write().unwrap_or_else(PoisonError::into_inner);
+    // another download may have got there first, e.g. when settings were saved
+    Ok(cached.get_or_insert(client).clone())
+}
@@ -0,0 +1,215 @@
+//! Trust-on-first-use pinning of repository publisher keys.
+//!
+//! The first time a signed repository is downloaded, the public key it was
+//! signed with is pinned for that repository's URL. Every later download from
+//! the same URL must be signed with the same key, otherwise it is rejected.
+
+use crate::{
+    error::{Error, Result},
+    storage_root, ResourceType,
+};
+use image_repo::{Digest, HashAlgorithm, Url};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Pins are named after a hash of the repository URL, since URLs that only differ
+/// in characters [`image_repo::safe_filename`] replaces would share a file name.
+fn pin_path(dir: &Path, repo_url: &Url) -> PathBuf {
+    let digest = Digest::compute(HashAlgorithm::Sha256, repo_url.as_str().as_bytes());
+    dir.join(format!("{}.key", digest.hex))
+}
+
+/// Where earlier versions pinned the key for `repo_url`.
+fn legacy_pin_path(dir: &Path, repo_url: &Url) -> Result<PathBuf> {
+    let file_name = format!("{}.key", image_repo::safe_filename(repo_url.as_str())?);
+    Ok(dir.join(file_name))
+}
+
+/// Get the publisher key pinned for the repository at `repo_url`, if any.
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn pinned_key(repo_url: &Url) -> Result<Option<String>> {
+    pinned_key_in(&storage_root(ResourceType::TrustedKey)?, repo_url).await
+}
+
+async fn pinned_key_in(dir: &Path, repo_url: &Url) -> Result<Option<String>> {
+    let path = pin_path(dir, repo_url);
+    if !path.exists() {
+        // move a pin made by an earlier version to where it's now looked up
+        let legacy = legacy_pin_path(dir, repo_url)?;
+        if !legacy.exists() {
+            return Ok(None);
+        }
+        fs::rename(legacy, &path).await?;
+    }
+    Ok(Some(fs::read_to_string(path).await?.trim().to_string()))
+}
+
+/// Check the key a repository was signed with against the pinned key, pinning
+/// it if this is the first signed download for that repository.
+///
+/// # Errors
+///
+/// [`Error::PublisherKeyMismatch`] if signed by a different key than the pinned
+/// one, [`Error::MissingSignature`] if unsigned but a key was previously pinned.
+pub async fn check_publisher_key(repo_url: &Url, signed_by: Option<&str>) -> Result<()> {
+    check_publisher_key_in(
+        &storage_root(ResourceType::TrustedKey)?,
+        repo_url,
+        signed_by,
+    )
+    .await
+}
+
+/// [`check_publisher_key`] with the pinned keys in `dir`.
+async fn check_publisher_key_in(dir: &Path, repo_url: &Url, signed_by: Option<&str>) -> Result<()> {
+    match (pinned_key_in(dir, repo_url).await?, signed_by) {
+        (Some(pinned), Some(key)) if pinned != key => {
+            Err(Error::PublisherKeyMismatch((pinned, key.to_string())))
+        }
+        (Some(_), None) => Err(Error::MissingSignature),
+        (None, Some(key)) => {
+            fs::write(pin_path(dir, repo_url), key).await?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Forget the publisher key pinned for the repository at `repo_url`, so that
+/// the next download pins whichever key it's signed with. Used when the
+/// repository is deleted, see [`crate::delete_repository`].
+///
+/// # Errors
+///
+/// [`crate::Error`]
+pub async fn remove_pin(repo_url: &Url) -> Result<()> {
+    remove_pin_in(&storage_root(ResourceType::TrustedKey)?, repo_url).await
+}
+
+async fn remove_pin_in(dir: &Path, repo_url: &Url) -> Result<()> {
+    for path in [pin_path(dir, repo_url), legacy_pin_path(dir, repo_url)?] {
+        match fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_publisher_key_in, legacy_pin_path, pinned_key_in, remove_pin_in};
+    use crate::error::Error;
+    use image_repo::Url;
+
+    const KEY: &str = "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb";
+    const OTHER_KEY: &str = "0b0b5a6f5f1d1e3b0d3e0c5e06f7a8d1e4c2b9a8f7e6d5c4b3a291807f6e5d4c";
+
+    #[tokio::test]
+    async fn pins_key_on_first_use() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let url = Url::parse("https://example.com/jwst/repo.json").expect("url");
+        let other_url = Url::parse("https://example.com/hubble/repo.json").expect("url");
+
+        // unsigned repos are fine until a key is pinned
+        check_publisher_key_in(dir, &url, None)
+            .await
+            .expect("unsigned");
+        assert_eq!(pinned_key_in(dir, &url).await.expect("read"), None);
+
+        // the first signed download pins its key
+        check_publisher_key_in(dir, &url, Some(KEY))
+            .await
+            .expect("first pin");
+        assert_eq!(
+            pinned_key_in(dir, &url).await.expect("read").as_deref(),
+            Some(KEY)
+        );
+
+        // later downloads must be signed with the same key
+        check_publisher_key_in(dir, &url, Some(KEY))
+            .await
+            .expect("same key");
+        let mismatch = check_publisher_key_in(dir, &url, Some(OTHER_KEY)).await;
+        assert!(
+            matches!(&mismatch, Err(Error::PublisherKeyMismatch((pinned, key))) if pinned == KEY && key == OTHER_KEY),
+            "{mismatch:?}"
+        );
+        let unsigned = check_publisher_key_in(dir, &url, None).await;
+        assert!(
+            matches!(unsigned, Err(Error::MissingSignature)),
+            "{unsigned:?}"
+        );
+        // and the pin isn't replaced by a rejected key
+        assert_eq!(
+            pinned_key_in(dir, &url).await.expect("read").as_deref(),
+            Some(KEY)
+        );
+
+        // pins are per repository
+        check_publisher_key_in(dir, &other_url, Some(OTHER_KEY))
+            .await
+            .expect("other repo");
+    }
+
+    #[tokio::test]
+    async fn removes_pin() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let url = Url::parse("https://example.com/jwst/repo.json").expect("url");
+        check_publisher_key_in(dir, &url, Some(KEY))
+            .await
+            .expect("first pin");
+
+        // the publisher rotated their key; deleting the repository allows the new one
+        remove_pin_in(dir, &url).await.expect("remove");
+        assert_eq!(pinned_key_in(dir, &url).await.expect("read"), None);
+        check_publisher_key_in(dir, &url, Some(OTHER_KEY))
+            .await
+            .expect("new pin");
+        // removing a pin that doesn't exist is fine
+        let other_url = Url::parse("https://example.com/hubble/repo.json").expect("url");
+        remove_pin_in(dir, &other_url)
+            .await
+            .expect("remove missing");
+    }
+
+    #[tokio::test]
+    async fn pins_urls_with_similar_file_names_separately() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let url = Url::parse("https://example.com/a/b").expect("url");
+        let similar_url = Url::parse("https://example.com/a?b").expect("url");
+        check_publisher_key_in(dir, &url, Some(KEY))
+            .await
+            .expect("pin");
+        check_publisher_key_in(dir, &similar_url, Some(OTHER_KEY))
+            .await
+            .expect("similar pin");
+        assert_eq!(
+            pinned_key_in(dir, &url).await.expect("read").as_deref(),
+            Some(KEY)
+        );
+    }
+
+    #[tokio::test]
+    async fn moves_legacy_pins() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let url = Url::parse("https://example.com/jwst/repo.json").expect("url");
+        let legacy = legacy_pin_path(dir, &url).expect("legacy path");
+        tokio::fs::write(&legacy, KEY).await.expect("write");
+
+        assert_eq!(
+            pinned_key_in(dir, &url).await.expect("read").as_deref(),
+            Some(KEY)
+        );
+        assert!(!legacy.exists());
+        let mismatch = check_publisher_key_in(dir, &url, Some(OTHER_KEY)).await;
+        assert!(matches!(mismatch, Err(Error::PublisherKeyMismatch(_))));
+    }
+}
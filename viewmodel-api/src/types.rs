@@ -1,29 +1,95 @@
 use crate::{
     error::{Error, Result},
-    storage_root, ResourceType,
+    settings, storage_root, trust, ResourceType,
 };
 use async_trait::async_trait;
 use image_repo::types::{ImageData, ImageRepo};
 use reqwest::{StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use std::{path::PathBuf, time::Duration};
+use tokio::fs;
 use typeshare::typeshare;
 
 const ONE_DAY: u64 = 86_400;
 const ONE_WEEK: u64 = 604_800;
 
-/// Download a resource from the internet and return the response body bytes.
-async fn download_bytes<T>(url: T) -> Result<Vec<u8>>
-where
-    T: AsRef<str>,
-{
-    let url = url.as_ref();
-    let http_resp = reqwest::get(url).await?;
+/// Get the local filesystem path of a `file://` URL, or `None` for any other scheme.
+///
+/// # Errors
+///
+/// [`Error::InvalidFileUrl`] if it is a `file://` URL but not a valid local path.
+pub fn local_path(url: &Url) -> Result<Option<PathBuf>> {
+    if url.scheme() != "file" {
+        return Ok(None);
+    }
+    url.to_file_path()
+        .map(Some)
+        .map_err(|()| Error::InvalidFileUrl(url.to_string()))
+}
+
+/// Check a size in bytes against the expected size, if there is one.
+fn check_size(expected_size: Option<u64>, size: u64) -> Result<()> {
+    match expected_size {
+        Some(expected) if expected != size => Err(Error::SizeMismatch((expected, size))),
+        _ => Ok(()),
+    }
+}
+
+/// Check a size in bytes against the largest size allowed, if there is one.
+fn check_max_size(max_size: Option<u64>, size: u64) -> Result<()> {
+    match max_size {
+        Some(max) if size > max => Err(Error::TooLarge(max)),
+        _ => Ok(()),
+    }
+}
+
+/// Download a resource from the internet and return the response body bytes,
+/// with the client for the current [`settings`]. `file://` URLs are read from
+/// the local filesystem instead.
+///
+/// If `expected_size` is given, the download is rejected as soon as the server
+/// announces or sends a different number of bytes. If `max_size` is given, it's
+/// rejected as soon as it's known to be larger.
+pub(crate) async fn download_bytes(
+    url: &Url,
+    expected_size: Option<u64>,
+    max_size: Option<u64>,
+) -> Result<Vec<u8>> {
+    if let Some(path) = local_path(url)? {
+        if !path.exists() {
+            return Err(Error::FileNotFound(path));
+        }
+        let len = fs::metadata(&path).await?.len();
+        check_size(expected_size, len)?;
+        check_max_size(max_size, len)?;
+        return Ok(fs::read(path).await?);
+    }
+
+    let client = settings::http_client().await?;
+    let mut http_resp = client.get(url).await?;
     let status = http_resp.status();
     if status != StatusCode::OK {
         return Err(Error::HttpStatus(status));
     }
-    Ok(http_resp.bytes().await?.to_vec())
+    if let Some(content_length) = http_resp.content_length() {
+        check_size(expected_size, content_length)?;
+        check_max_size(max_size, content_length)?;
+    }
+
+    let mut bytes = vec![];
+    while client.read_chunk(&mut http_resp, &mut bytes).await? {
+        let received = u64::try_from(bytes.len()).unwrap_or(u64::MAX);
+        if let Some(expected) = expected_size.filter(|&expected| received > expected) {
+            // don't keep downloading from a server sending more than it should
+            return Err(Error::SizeMismatch((expected, received)));
+        }
+        check_max_size(max_size, received)?;
+    }
+    check_size(
+        expected_size,
+        u64::try_from(bytes.len()).unwrap_or(u64::MAX),
+    )?;
+    Ok(bytes)
 }
 
 /// Trait to allow getting a full filepath from
@@ -46,45 +112,151 @@ impl TryIntoStoragePath for ImageRepo {
 
 impl TryIntoStoragePath for ImageData {
     fn try_into_storage_path(&self) -> Result<PathBuf> {
-        storage_root(ResourceType::Image).map(|root| root.join(self.to_file_name()))
+        let file_name = self.to_file_name()?;
+        storage_root(ResourceType::Image).map(|root| root.join(file_name))
     }
 }
 
+/// A downloaded resource and its bytes.
+pub struct Downloaded<T> {
+    pub resource: T,
+    pub bytes: Vec<u8>,
+    /// The local file `bytes` were read and verified from, if any. Such resources
+    /// are stored as a reference to that file rather than a copy where possible,
+    /// see [`crate::store_reference`].
+    pub local_source: Option<PathBuf>,
+}
+
 #[async_trait]
 pub trait DownloadableResource<T>
 where
     T: TryIntoStoragePath,
 {
     /// Download the data from the URL and store it locally as a resource.
-    async fn download_resource(&self) -> Result<(T, Vec<u8>)>;
+    async fn download_resource(&self) -> Result<Downloaded<T>>;
 }
 
 #[async_trait]
 impl DownloadableResource<ImageRepo> for Url {
-    async fn download_resource(&self) -> Result<(ImageRepo, Vec<u8>)> {
-        let bytes = download_bytes(self).await?;
-        // verify the contents are proper JSON schema
-        let mut repo = serde_json::from_slice::<ImageRepo>(&bytes)?;
-        // verify the update URL
-        match &repo.update_url {
-            // Check update URL matches
-            Some(url) if url != self => {
-                return Err(Error::InvalidUpdateUrl((url.to_string(), self.to_string())))
-            }
-            // Inject update URL it was downloaded from if there is none
-            None => repo.update_url = Some(self.clone()),
-            _ => {}
+    async fn download_resource(&self) -> Result<Downloaded<ImageRepo>> {
+        download_repo(self, true).await
+    }
+}
+
+/// Download, verify and resolve the includes of the repository at `url`. Its
+/// publisher keys, and those of its includes, are only checked against and
+/// pinned if `pin_keys`, so that merely previewing a repository with
+/// [`crate::preview_repository`] doesn't pin anything.
+pub(crate) async fn download_repo(url: &Url, pin_keys: bool) -> Result<Downloaded<ImageRepo>> {
+    if let Some(dir) = local_path(url)?.filter(|path| path.is_dir()) {
+        // Local folder repository; build the manifest by scanning the images in it
+        let repo = tokio::task::spawn_blocking(move || image_repo::local::scan_directory(&dir))
+            .await
+            .map_err(std::io::Error::other)??;
+        let bytes = serde_json::to_vec_pretty(&repo)?;
+        return Ok(Downloaded {
+            resource: repo,
+            bytes,
+            local_source: None,
+        });
+    }
+
+    let bytes = download_bytes(url, None, None).await?;
+    let doc = serde_json::from_slice::<serde_json::Value>(&bytes)?;
+    // verify the publisher signature, if any, before trusting any of the contents
+    let signed_by = image_repo::signing::verify(&doc)?;
+    // verify the contents are proper JSON schema, upgrading older schema versions
+    let mut repo = image_repo::schema::from_value(doc)?;
+    // only a manifest on this computer may point at other files on it
+    repo.check_local_urls(url)
+        .map_err(|url| Error::LocalUrl(url.to_string()))?;
+    // verify the update URL
+    match &repo.update_url {
+        // Check update URL matches
+        Some(update_url) if update_url != url => {
+            return Err(Error::InvalidUpdateUrl((
+                update_url.to_string(),
+                url.to_string(),
+            )))
+        }
+        // Inject update URL it was downloaded from if there is none
+        None => repo.update_url = Some(url.clone()),
+        _ => {}
+    }
+    if pin_keys {
+        trust::check_publisher_key(url, signed_by.as_deref()).await?;
+    }
+    // only ever set by resolving includes, never trust what the manifest says
+    let mut forged_provenance = false;
+    for image in &mut repo.images {
+        forged_provenance |= image.included_from.take().is_some();
+    }
+    if repo.includes.is_empty() {
+        // the downloaded bytes are what's stored, so they mustn't keep it either
+        let bytes = if forged_provenance {
+            serde_json::to_vec_pretty(&repo)?
+        } else {
+            bytes
         };
-        Ok((repo, bytes))
+        return Ok(Downloaded {
+            resource: repo,
+            bytes,
+            local_source: None,
+        });
     }
+
+    // Composite repository; store it with the included images resolved so it
+    // can be used offline. This is only stored locally, never re-verified, so it
+    // doesn't matter that the signature no longer covers the resolved images.
+    let client = settings::http_client().await?;
+    let included = image_repo::includes::resolve_includes(&client, &mut repo, Some(url)).await?;
+    // included manifests are pinned to their publishers just like this one
+    if pin_keys {
+        for include in included {
+            trust::check_publisher_key(&include.url, include.signed_by.as_deref()).await?;
+        }
+    }
+    let bytes = serde_json::to_vec_pretty(&repo)?;
+    Ok(Downloaded {
+        resource: repo,
+        bytes,
+        local_source: None,
+    })
 }
 
+/// Tries [`ImageData::url`] and then each mirror in order, returning the first
+/// download that passes checksum verification. To include the repository's base
+/// mirrors, get the [`ImageData`] from [`ImageRepo::images_with_mirrors`]. If
+/// that download was a local file, it's the [`Downloaded::local_source`].
 #[async_trait]
 impl DownloadableResource<ImageData> for ImageData {
-    async fn download_resource(&self) -> Result<(ImageData, Vec<u8>)> {
-        let bytes = download_bytes(self.url.clone()).await?;
-        self.verify_checksum(&bytes)?;
-        Ok((self.clone(), bytes))
+    async fn download_resource(&self) -> Result<Downloaded<ImageData>> {
+        let mut errors = vec![];
+        for url in self.urls() {
+            let result = match download_bytes(url, self.size, None).await {
+                Ok(bytes) => self
+                    .verify_checksum(&bytes)
+                    .map(|()| bytes)
+                    .map_err(Error::from),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(bytes) => {
+                    return Ok(Downloaded {
+                        resource: self.clone(),
+                        bytes,
+                        local_source: local_path(url)?,
+                    })
+                }
+                Err(e) => errors.push((url.to_string(), e)),
+            }
+        }
+
+        if errors.len() == 1 {
+            Err(errors.remove(0).1)
+        } else {
+            Err(Error::AllMirrorsFailed(errors))
+        }
     }
 }
 
@@ -104,3 +276,176 @@ impl From<UpdateInterval> for Duration {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FERRIS: &[u8] = include_bytes!("../../image-repo/ferris.png");
+
+    #[tokio::test]
+    async fn falls_back_to_mirror() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let main = dir.join("main.png");
+        let mirror = dir.join("mirror.png");
+        // same size, so it's the checksum rather than the size check that fails
+        let mut corrupted = FERRIS.to_vec();
+        corrupted[FERRIS.len() / 2] ^= 0xff;
+        std::fs::write(&main, &corrupted).expect("write main");
+        std::fs::write(&mirror, FERRIS).expect("write mirror");
+
+        let main_url = Url::from_file_path(&main).expect("url");
+        let mut image = ImageData::try_from((main_url.clone(), FERRIS)).expect("image");
+        image.size = Some(u64::try_from(FERRIS.len()).expect("size"));
+        image.mirrors = vec![Url::from_file_path(&mirror).expect("url")];
+
+        let downloaded = image.download_resource().await.expect("mirror download");
+        assert_eq!(downloaded.bytes, FERRIS);
+        assert_eq!(downloaded.local_source.as_deref(), Some(mirror.as_path()));
+
+        // with no mirror left to fall back to, every URL's error is kept
+        std::fs::write(&mirror, &corrupted).expect("write mirror");
+        match image.download_resource().await {
+            Err(Error::AllMirrorsFailed(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert_eq!(errors[0].0, main_url.to_string());
+                assert!(errors
+                    .iter()
+                    .all(|(_, e)| matches!(e, Error::InvalidChecksum(_))));
+            }
+            _ => panic!("expected every mirror to fail"),
+        }
+
+        // a lone URL's error is returned as is
+        image.mirrors.clear();
+        assert!(matches!(
+            image.download_resource().await,
+            Err(Error::InvalidChecksum(_))
+        ));
+    }
+
+    #[test]
+    fn checks_sizes() {
+        assert!(check_size(None, 10).is_ok());
+        assert!(check_size(Some(10), 10).is_ok());
+        assert!(matches!(
+            check_size(Some(10), 11),
+            Err(Error::SizeMismatch((10, 11)))
+        ));
+        assert!(check_max_size(None, u64::MAX).is_ok());
+        assert!(check_max_size(Some(10), 10).is_ok());
+        assert!(matches!(
+            check_max_size(Some(10), 11),
+            Err(Error::TooLarge(10))
+        ));
+    }
+
+    #[tokio::test]
+    async fn enforces_download_sizes() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let path = dir.join("ten.bin");
+        std::fs::write(&path, [0; 10]).expect("write file");
+        let url = Url::from_file_path(&path).expect("url");
+
+        assert_eq!(
+            download_bytes(&url, Some(10), Some(10))
+                .await
+                .expect("bytes"),
+            [0; 10]
+        );
+        assert!(matches!(
+            download_bytes(&url, Some(9), None).await,
+            Err(Error::SizeMismatch((9, 10)))
+        ));
+        assert!(matches!(
+            download_bytes(&url, None, Some(9)).await,
+            Err(Error::TooLarge(9))
+        ));
+        let missing = Url::from_file_path(dir.join("missing.bin")).expect("url");
+        assert!(matches!(
+            download_bytes(&missing, None, None).await,
+            Err(Error::FileNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn previews_repository_size() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let dir = temp.path();
+        let path = dir.join("repo.json");
+        std::fs::write(
+            &path,
+            r#"{
+  "name": "Preview",
+  "images": [
+    {
+      "url": "https://example.com/a.png",
+      "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+      "width": 460,
+      "height": 307,
+      "format": "png",
+      "size": 1000
+    },
+    {
+      "url": "https://example.com/b.png",
+      "hash": "e89fb6764fa3f176e0abeea3b55d8b055195dca3fc1573753deb4612cb7834a9",
+      "width": 14575,
+      "height": 8441,
+      "format": "png",
+      "size": 2500
+    }
+  ]
+}"#,
+        )
+        .expect("write manifest");
+        let url = Url::from_file_path(&path).expect("url");
+
+        let preview = crate::preview_repository(url.clone())
+            .await
+            .expect("preview");
+        assert_eq!(preview.name, "Preview");
+        assert_eq!(preview.image_count, 2);
+        assert_eq!(preview.total_size, 3500);
+        assert!(preview.total_size_exact);
+
+        // a size that isn't declared makes the total a lower bound
+        let manifest = std::fs::read_to_string(&path).expect("read manifest");
+        std::fs::write(&path, manifest.replace(",\n      \"size\": 2500", ""))
+            .expect("write manifest");
+        let preview = crate::preview_repository(url).await.expect("preview");
+        assert_eq!(preview.total_size, 1000);
+        assert!(!preview.total_size_exact);
+    }
+
+    #[tokio::test]
+    async fn drops_forged_provenance() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let path = temp.path().join("repo.json");
+        std::fs::write(
+            &path,
+            r#"{
+  "name": "Forger",
+  "images": [
+    {
+      "url": "https://example.com/a.png",
+      "hash": "b64500c829882b4abed9d768dbb396569ff1d5e6baf7d274460ab372fe53aadb",
+      "width": 460,
+      "height": 307,
+      "format": "png",
+      "includedFrom": { "name": "NASA", "url": "https://nasa.example.com/repo.json" }
+    }
+  ]
+}"#,
+        )
+        .expect("write manifest");
+        let url = Url::from_file_path(&path).expect("url");
+
+        let downloaded = download_repo(&url, false).await.expect("repo");
+        assert!(downloaded.resource.images[0].included_from.is_none());
+        // the bytes are what's stored and reloaded later
+        let reloaded = image_repo::schema::from_slice(&downloaded.bytes).expect("reload");
+        assert!(reloaded.images[0].included_from.is_none());
+    }
+}
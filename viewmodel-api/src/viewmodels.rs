@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use crate::error::Result;
 use chrono::{DateTime, Utc};
-use image_repo::types::ImageRepo;
+use image_repo::types::{ColorStats, ImageData, ImageRepo};
 use serde::{Deserialize, Serialize};
 use tokio::{fs::File, io::AsyncReadExt};
 use typeshare::typeshare;
@@ -22,6 +22,130 @@ pub struct RepositoryViewModel {
     pub update_url: Option<String>,
     /// Local disk path of repo JSON file
     pub path: PathBuf,
+    /// Images in the repo, including those from included repos
+    pub images: Vec<ImageViewModel>,
+    /// Total size in bytes of the images in the repo that declare their size
+    #[typeshare(serialized_as = "number")]
+    pub total_size: u64,
+    /// Whether every image declares its size, i.e. whether `total_size` is exact
+    /// rather than a lower bound
+    pub total_size_exact: bool,
+}
+
+/// What installing a repository would download, to show before installing it.
+#[typeshare]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepositoryPreview {
+    /// Repo name
+    pub name: String,
+    /// Description
+    pub description: Option<String>,
+    /// Number of images in the repo, including those from included repos
+    pub image_count: u32,
+    /// Total size in bytes of the images in the repo that declare their size
+    #[typeshare(serialized_as = "number")]
+    pub total_size: u64,
+    /// Whether every image declares its size, i.e. whether `total_size` is exact
+    /// rather than a lower bound
+    pub total_size_exact: bool,
+}
+
+impl From<ImageRepo> for RepositoryPreview {
+    fn from(repo: ImageRepo) -> Self {
+        let (total_size, total_size_exact) = total_size(&repo.images);
+        Self {
+            name: repo.name,
+            description: repo.description,
+            image_count: u32::try_from(repo.images.len()).unwrap_or(u32::MAX),
+            total_size,
+            total_size_exact,
+        }
+    }
+}
+
+/// Total size in bytes of the images that declare their size, and whether they
+/// all do.
+fn total_size(images: &[ImageData]) -> (u64, bool) {
+    (
+        images.iter().filter_map(|image| image.size).sum(),
+        images.iter().all(|image| image.size.is_some()),
+    )
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageViewModel {
+    /// Image URL
+    pub url: String,
+    /// Image width in pixels
+    pub width: u32,
+    /// Image height in pixels
+    pub height: u32,
+    /// Image file size in bytes, if declared
+    #[typeshare(serialized_as = "number")]
+    pub size: Option<u64>,
+    /// Image title, if known
+    pub title: Option<String>,
+    /// Credit line to display with the image, if known
+    pub credit: Option<String>,
+    /// Name of the included repo this image came from, if it
+    /// didn't come from the repo itself
+    pub source_name: Option<String>,
+    /// Update URL of the included repo this image came from, if it
+    /// didn't come from the repo itself
+    pub source_url: Option<String>,
+    /// Local disk path of the image's thumbnail, if it has one and it
+    /// has been downloaded
+    pub thumbnail_path: Option<PathBuf>,
+    /// Blur hash placeholder to show until the thumbnail or image loads
+    pub blur_hash: Option<String>,
+    /// Dominant colours as `#rrggbb`, most common first, for theming the UI.
+    /// Empty if they haven't been computed yet
+    pub palette: Vec<String>,
+    /// Mean luminance from 0 to 1, if computed
+    pub luminance: Option<f64>,
+}
+
+impl ImageViewModel {
+    /// Convert an [`ImageData`], with its colours from the manifest or, if they've
+    /// been computed since it was downloaded, from the cache next to the image.
+    async fn new(image: ImageData) -> Self {
+        let thumbnail_path = image
+            .thumbnail_url
+            .as_ref()
+            .and_then(|_| crate::thumbnail_path(&image).ok())
+            .filter(|path| path.exists());
+        let colors = match image.colors.clone() {
+            Some(colors) => Some(colors),
+            None => crate::cached_analysis::<Option<ColorStats>>(&image, "colors")
+                .await
+                .flatten(),
+        };
+        let (source_name, source_url) = image
+            .included_from
+            .map(|source| (source.name, source.url.to_string()))
+            .unzip();
+        Self {
+            url: image.url.to_string(),
+            width: image.width,
+            height: image.height,
+            size: image.size,
+            title: image.metadata.title,
+            credit: image.metadata.credit,
+            source_name,
+            source_url,
+            thumbnail_path,
+            blur_hash: image.blur_hash,
+            palette: colors
+                .iter()
+                .flat_map(|colors| &colors.palette)
+                .map(ToString::to_string)
+                .collect(),
+            luminance: colors.map(|colors| colors.luminance),
+        }
+    }
 }
 
 async fn metadata_last_updated(file: &File) -> Result<String> {
@@ -44,14 +168,8 @@ impl RepositoryViewModel {
     /// [`crate::Error`]
     pub async fn from_path(path: PathBuf) -> Result<RepositoryViewModel> {
         let (file, file_bytes) = file_bytes(&path).await?;
-        let repo = serde_json::from_slice::<ImageRepo>(file_bytes.as_slice())?;
-        Ok(Self {
-            last_updated: metadata_last_updated(&file).await?,
-            name: repo.name,
-            description: repo.description,
-            update_url: repo.update_url.map(|url| url.to_string()),
-            path,
-        })
+        let repo = image_repo::schema::from_slice(file_bytes.as_slice())?;
+        Ok(Self::new(repo, path, metadata_last_updated(&file).await?).await)
     }
 
     /// Convert an [`ImageRepo`] to a [`RepositoryViewModel`]
@@ -61,12 +179,24 @@ impl RepositoryViewModel {
     /// [`crate::Error`]
     pub async fn from_resource(repo: ImageRepo, path: PathBuf) -> Result<RepositoryViewModel> {
         let (file, _) = file_bytes(&path).await?;
-        Ok(Self {
-            last_updated: metadata_last_updated(&file).await?,
+        Ok(Self::new(repo, path, metadata_last_updated(&file).await?).await)
+    }
+
+    async fn new(repo: ImageRepo, path: PathBuf, last_updated: String) -> RepositoryViewModel {
+        let (total_size, total_size_exact) = total_size(&repo.images);
+        let mut images = Vec::with_capacity(repo.images.len());
+        for image in repo.images {
+            images.push(ImageViewModel::new(image).await);
+        }
+        Self {
+            last_updated,
             name: repo.name,
             description: repo.description,
             update_url: repo.update_url.map(|url| url.to_string()),
             path,
-        })
+            images,
+            total_size,
+            total_size_exact,
+        }
     }
 }